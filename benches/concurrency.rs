@@ -0,0 +1,43 @@
+//! 按字节范围切块并行统计同一个文件时，耗时随线程数的变化：`cargo bench --bench concurrency`
+
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use csvaction::{count_file_mmap, CountOptions};
+use indicatif::ProgressBar;
+
+// 输入的行数及不同行数
+const LINES: usize = 10_000_000;
+const DISTINCT: u64 = 100_000;
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("csvaction-bench-{}.txt", std::process::id()));
+    let mut writer = BufWriter::new(std::fs::File::create(&path).unwrap());
+    let mut state: u64 = 7;
+    for _ in 0..LINES {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        writeln!(writer, "line {}", (state >> 33) % DISTINCT).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    let paths = [path.to_string_lossy().into_owned()];
+    let count_options = CountOptions::default();
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut baseline = None;
+    for concurrency in [1, 2, 4, 8, 16].into_iter().filter(|&n| n <= cores.max(2)) {
+        let pb = ProgressBar::hidden();
+        let started = Instant::now();
+        let (data_count, _) = count_file_mmap(&paths, concurrency, &count_options, &pb).unwrap();
+        let elapsed = started.elapsed();
+        let baseline = *baseline.get_or_insert(elapsed);
+        println!(
+            "{concurrency:>2} threads: {} distinct lines in {:?} ({:.2}x)",
+            data_count.len(),
+            elapsed,
+            baseline.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+    std::fs::remove_file(&path).unwrap();
+}
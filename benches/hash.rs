@@ -0,0 +1,45 @@
+//! 比较标准库 HashMap（SipHash）与 FxHashMap 统计相同 key 的耗时：`cargo bench --bench hash`
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::time::Instant;
+
+use rustc_hash::FxBuildHasher;
+
+// 统计的 key 个数及不同 key 的个数
+const KEYS: usize = 10_000_000;
+const DISTINCT: u64 = 1_000_000;
+
+// 只在 key 第一次出现时分配 String，与统计时的做法相同
+fn count<S: BuildHasher + Default>(keys: &[String]) -> HashMap<String, usize, S> {
+    let mut counts: HashMap<String, usize, S> = HashMap::default();
+    for key in keys {
+        match counts.get_mut(key.as_str()) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(key.clone(), 1);
+            }
+        }
+    }
+    counts
+}
+
+fn main() {
+    let mut state: u64 = 7;
+    let keys: Vec<String> = (0..KEYS)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            format!("line {}", (state >> 33) % DISTINCT)
+        })
+        .collect();
+
+    let started = Instant::now();
+    let distinct = count::<std::hash::RandomState>(&keys).len();
+    println!("sip: {distinct} distinct keys in {:?}", started.elapsed());
+
+    let started = Instant::now();
+    let distinct = count::<FxBuildHasher>(&keys).len();
+    println!(" fx: {distinct} distinct keys in {:?}", started.elapsed());
+}
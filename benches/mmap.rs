@@ -0,0 +1,47 @@
+//! 比较逐行读取与内存映射两种方式统计同一个文件的吞吐量：`cargo bench --bench mmap`
+
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use csvaction::{count_file, count_file_mmap, CountOptions};
+use indicatif::ProgressBar;
+
+// 输入的行数及不同行数
+const LINES: usize = 5_000_000;
+const DISTINCT: u64 = 100_000;
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("csvaction-bench-{}.txt", std::process::id()));
+    let mut writer = BufWriter::new(std::fs::File::create(&path).unwrap());
+    let mut state: u64 = 7;
+    for _ in 0..LINES {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        writeln!(writer, "line {}", (state >> 33) % DISTINCT).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    let paths = [path.to_string_lossy().into_owned()];
+    let megabytes = std::fs::metadata(&path).unwrap().len() as f64 / 1024.0 / 1024.0;
+    let count_options = CountOptions::default();
+    // 只用一个线程，比较的是读取方式本身而不是并行度
+    for use_mmap in [false, true] {
+        let pb = ProgressBar::hidden();
+        let started = Instant::now();
+        let (data_count, _) = if use_mmap {
+            count_file_mmap(&paths, 1, &count_options, &pb).unwrap()
+        } else {
+            count_file(&paths, 1, &count_options, &pb).unwrap()
+        };
+        let elapsed = started.elapsed();
+        let kind = if use_mmap { "mmap" } else { "read" };
+        println!(
+            "{kind:>4}: {} distinct lines in {:?} ({:.1} MiB/s)",
+            data_count.len(),
+            elapsed,
+            megabytes / elapsed.as_secs_f64()
+        );
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,41 @@
+//! 比较逐行与每 N 行批量推进进度条时统计的耗时：`cargo bench --bench progress`
+
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use csvaction::{count_file, CountOptions};
+use indicatif::{ProgressBar, ProgressDrawTarget};
+
+// 输入的行数及不同行数
+const LINES: usize = 5_000_000;
+const DISTINCT: u64 = 1000;
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("csvaction-bench-{}.txt", std::process::id()));
+    let mut writer = BufWriter::new(std::fs::File::create(&path).unwrap());
+    let mut state: u64 = 7;
+    for _ in 0..LINES {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        writeln!(writer, "line {}", (state >> 33) % DISTINCT).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    let paths = [path.to_string_lossy().into_owned()];
+    let len = std::fs::metadata(&path).unwrap().len();
+    for progress_interval in [1, 10_000] {
+        let count_options = CountOptions {
+            progress_interval,
+            ..CountOptions::default()
+        };
+        // 绘制到 stderr 的进度条，与实际运行时一样需要加锁和限频重绘
+        let pb = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::stderr());
+        let started = Instant::now();
+        count_file(&paths, 4, &count_options, &pb).unwrap();
+        let elapsed = started.elapsed();
+        pb.finish_and_clear();
+        println!("interval {progress_interval:>6}: {LINES} lines in {elapsed:?}");
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,49 @@
+//! 比较先数一遍行数作为进度条总数再统计，与按字节数显示进度、只读一遍的耗时：`cargo bench --bench single_pass`
+
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use csvaction::{count_file, count_lines, input_len, CountOptions};
+use indicatif::ProgressBar;
+
+// 输入的行数及不同行数
+const LINES: usize = 5_000_000;
+const DISTINCT: u64 = 100_000;
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("csvaction-bench-{}.txt", std::process::id()));
+    let mut writer = BufWriter::new(std::fs::File::create(&path).unwrap());
+    let mut state: u64 = 7;
+    for _ in 0..LINES {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        writeln!(writer, "line {}", (state >> 33) % DISTINCT).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    let paths = [path.to_string_lossy().into_owned()];
+    let count_options = CountOptions::default();
+
+    let started = Instant::now();
+    let pb = ProgressBar::hidden();
+    let lines = count_lines(&paths, &count_options, &pb).unwrap();
+    pb.set_length(lines as u64);
+    let (data_count, _) = count_file(&paths, 1, &count_options, &pb).unwrap();
+    println!(
+        "two passes: {} distinct lines in {:?}",
+        data_count.len(),
+        started.elapsed()
+    );
+
+    let started = Instant::now();
+    let pb = ProgressBar::hidden();
+    pb.set_length(input_len(&paths[0]).unwrap().unwrap_or(0));
+    let (data_count, _) = count_file(&paths, 1, &count_options, &pb).unwrap();
+    println!(
+        "  one pass: {} distinct lines in {:?}",
+        data_count.len(),
+        started.elapsed()
+    );
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,44 @@
+//! 比较排序输出时稳定排序与不稳定排序，以及单线程与并行排序的耗时：`cargo bench --bench sort`
+
+use std::time::Instant;
+
+use csvaction::{sorted_results, CountMap, LineCount, WriteOptions};
+use rayon::ThreadPoolBuilder;
+
+// 不同的行数，出现次数只有少数几种取值，次数相同的行很多
+const ROWS: usize = 1_000_000;
+
+fn main() {
+    let mut state: u64 = 7;
+    let mut data_count = CountMap::default();
+    for index in 0..ROWS {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let line_count = LineCount {
+            count: (state >> 33) as usize % 100 + 1,
+            first_seen: index + 1,
+            original: None,
+            positions: Vec::new(),
+        };
+        data_count.insert(format!("line {}", state >> 40), line_count);
+    }
+    // 只有一个线程的线程池中并行排序退化为顺序排序
+    let sequential = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let parallel = ThreadPoolBuilder::new().build().unwrap();
+    for stable_sort in [false, true] {
+        let write_options = WriteOptions {
+            stable_sort,
+            ..WriteOptions::default()
+        };
+        let kind = if stable_sort { "stable" } else { "unstable" };
+        for (threads, pool) in [("1 thread", &sequential), ("parallel", &parallel)] {
+            let started = Instant::now();
+            let rows = pool.install(|| sorted_results(&data_count, &write_options).count());
+            println!(
+                "{kind:>8}, {threads}: {rows} rows sorted in {:?}",
+                started.elapsed()
+            );
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! 比较直接写入 File 与经过 BufWriter 写出大量结果的耗时：`cargo bench --bench write`
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::Instant;
+
+use csvaction::{write_results, CountMap, LineCount, WriteOptions};
+
+// 不同的行数
+const ROWS: usize = 1_000_000;
+
+fn main() {
+    let mut data_count = CountMap::default();
+    for index in 0..ROWS {
+        let line_count = LineCount {
+            count: index % 1000 + 1,
+            first_seen: index + 1,
+            original: None,
+            positions: Vec::new(),
+        };
+        data_count.insert(format!("line {index}"), line_count);
+    }
+    let path = std::env::temp_dir().join(format!("csvaction-bench-{}.csv", std::process::id()));
+    let write_options = WriteOptions::default();
+    for buffered in [false, true] {
+        let file = File::create(&path).unwrap();
+        let started = Instant::now();
+        if buffered {
+            write_results(BufWriter::new(file), &data_count, &write_options).unwrap();
+        } else {
+            write_results(file, &data_count, &write_options).unwrap();
+        }
+        let kind = if buffered { "buffered" } else { "file" };
+        println!("{kind:>8}: {ROWS} rows written in {:?}", started.elapsed());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
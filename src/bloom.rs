@@ -0,0 +1,49 @@
+use xxhash_rust::xxh3::xxh3_128;
+
+// 每个 key 对应的比特位置数，即哈希函数的个数
+const HASHES: u64 = 6;
+
+// 过滤器的最小比特数，避免小文件的过滤器过小导致误判过多
+const MIN_BITS: u64 = 1 << 16;
+
+// BloomFilter 判断 key 是否出现过：不在其中的 key 一定没出现过，在其中的 key 有小概率是误判
+pub(crate) struct BloomFilter {
+    words: Vec<u64>,
+    bits: u64,
+}
+
+impl BloomFilter {
+    // 创建至少包含 bits 个比特的过滤器
+    pub(crate) fn new(bits: u64) -> Self {
+        let words = bits.max(MIN_BITS).div_ceil(64);
+        BloomFilter {
+            words: vec![0; words as usize],
+            bits: words * 64,
+        }
+    }
+
+    // 加入 key，返回 key 加入前是否可能已经存在
+    pub(crate) fn insert(&mut self, key: &str) -> bool {
+        let mut present = true;
+        for bit in self.positions(key) {
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            present &= self.words[word] & mask != 0;
+            self.words[word] |= mask;
+        }
+        present
+    }
+
+    // 判断 key 是否可能已经存在
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    // 用 128 位哈希的两半做双重哈希，得到 key 对应的 HASHES 个比特位置
+    fn positions(&self, key: &str) -> impl Iterator<Item = u64> {
+        let hash = xxh3_128(key.as_bytes());
+        let (h1, h2) = (hash as u64, (hash >> 64) as u64 | 1);
+        let bits = self.bits;
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+}
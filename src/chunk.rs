@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+
+use crate::error::{Error, Result};
+
+// 单个分块的最小字节数，避免小文件被切得过碎
+pub(crate) const MIN_CHUNK_SIZE: u64 = 1 << 20;
+
+// 每个线程分到的分块数，多切几块让先完成的线程可以继续领取
+const CHUNKS_PER_THREAD: u64 = 4;
+
+// 把长度为 len 的输入切成若干 [start, end) 区间，每个区间都从行首开始、在行尾结束
+// next_line_start(pos) 返回 pos 之后（含 pos）第一个行首的位置
+fn chunk_ranges<E>(
+    len: u64,
+    concurrency: usize,
+    mut next_line_start: impl FnMut(u64) -> std::result::Result<u64, E>,
+) -> std::result::Result<Vec<(u64, u64)>, E> {
+    let chunks = (concurrency.max(1) as u64 * CHUNKS_PER_THREAD).min(len / MIN_CHUNK_SIZE + 1);
+    let mut ranges = vec![];
+    let mut start = 0;
+    for i in 1..chunks {
+        let pos = len * i / chunks;
+        if pos <= start {
+            continue;
+        }
+        let end = next_line_start(pos)?;
+        ranges.push((start, end));
+        start = end;
+        if start >= len {
+            break;
+        }
+    }
+    if start < len {
+        ranges.push((start, len));
+    }
+    Ok(ranges)
+}
+
+// 按行边界切分文件，需要在每个切分点附近读取到下一个行分隔符 separator
+pub(crate) fn file_chunk_ranges(
+    file_path: &str,
+    concurrency: usize,
+    separator: u8,
+) -> Result<Vec<(u64, u64)>> {
+    let open_error = |source| Error::Open {
+        path: file_path.to_string(),
+        source,
+    };
+    let file = File::open(file_path).map_err(open_error)?;
+    let len = file.metadata().map_err(open_error)?.len();
+    let mut reader = BufReader::new(file);
+    chunk_ranges(len, concurrency, |pos| {
+        // 从 pos - 1 开始找分隔符，pos 恰好是行首时不会跳过整行
+        // skip_until 不保存跳过的内容，超长的行也不会占用额外内存
+        reader.seek(SeekFrom::Start(pos - 1)).map_err(Error::Read)?;
+        let n = reader.skip_until(separator).map_err(Error::Read)?;
+        Ok(pos - 1 + n as u64)
+    })
+}
+
+// 按行边界切分内存中的数据
+pub(crate) fn slice_chunk_ranges(
+    data: &[u8],
+    concurrency: usize,
+    separator: u8,
+) -> Vec<(usize, usize)> {
+    let ranges = chunk_ranges(data.len() as u64, concurrency, |pos| {
+        let from = pos as usize - 1;
+        let end = match data[from..].iter().position(|b| *b == separator) {
+            Some(i) => from + i + 1,
+            None => data.len(),
+        };
+        Ok::<_, Infallible>(end as u64)
+    });
+    let Ok(ranges) = ranges;
+    ranges
+        .into_iter()
+        .map(|(start, end)| (start as usize, end as usize))
+        .collect()
+}
@@ -0,0 +1,3121 @@
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use clap::ValueEnum;
+use encoding_rs::{Encoding, UTF_8};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use regex::Regex;
+use rustc_hash::{FxHashMap, FxHashSet};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64_with_seed};
+
+use crate::bloom::BloomFilter;
+use crate::chunk::{file_chunk_ranges, slice_chunk_ranges};
+use crate::error::{Error, Result};
+use crate::input::{
+    for_each_line, input_len, is_compressed, is_regular_file, is_set, is_url, is_zip, map_file,
+    reached_limit, read_file, read_header, skip_bom, strip_line_ending, truncate_line, with_input,
+    ProgressReader, UTF8_BOM,
+};
+
+/// 按相邻行对统计时，连接前一行与当前行的分隔符
+pub const BIGRAM_SEPARATOR: &str = "\t";
+
+/// 设置 [`CountOptions::max_distinct`] 时，超出上限的 key 合并计入的 key
+pub const OTHER_KEY: &str = "<OTHER>";
+
+/// 按时间窗口统计时，时间戳无法解析的行所在的时间窗口
+pub const UNKNOWN_BUCKET: &str = "<UNKNOWN>";
+
+/// [`CountOptions::read_buffer`] 的默认值，与标准库 BufReader 的默认容量相同
+pub const DEFAULT_READ_BUFFER: usize = 8 * 1024;
+
+/// 统计结果，key 为统计用的行内容
+///
+/// 本地文件统计不需要抵御哈希碰撞攻击，使用比 SipHash 更快的 FxHash
+pub type CountMap = FxHashMap<String, LineCount>;
+
+// 开启 hash_keys 时以 128 位摘要为 key 的统计结果，每个 key 只占 16 字节
+type DigestMap = FxHashMap<u128, LineCount>;
+
+// 统计过程中一个线程、分块或多个文件合并后的结果，普通的 key 在 text 中，
+// hash_keys 时的摘要在 digests 中，统计全部结束后才转换为十六进制字符串
+#[derive(Clone, Default)]
+struct Counts {
+    text: CountMap,
+    digests: DigestMap,
+}
+
+impl Counts {
+    fn len(&self) -> usize {
+        self.text.len() + self.digests.len()
+    }
+
+    // 把 src 合并进来，src 中的行号加上 line_offset
+    fn merge(&mut self, src: Counts, line_offset: usize) {
+        merge_counts(&mut self.text, src.text, line_offset);
+        merge_counts(&mut self.digests, src.digests, line_offset);
+    }
+
+    // 与已有的 key 相同、次数为 0 的占位，不包括 OTHER_KEY；首次出现行号为 PLACEHOLDER_LINE，
+    // 统计到时会被改为实际的行号
+    fn placeholders(&self) -> Counts {
+        let placeholder = || LineCount {
+            count: 0,
+            first_seen: PLACEHOLDER_LINE,
+            original: None,
+            positions: Vec::new(),
+        };
+        let text = (self.text.keys()).filter(|key| *key != OTHER_KEY);
+        Counts {
+            text: text.map(|key| (key.clone(), placeholder())).collect(),
+            digests: (self.digests.keys())
+                .map(|digest| (*digest, placeholder()))
+                .collect(),
+        }
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut LineCount> {
+        self.text.values_mut().chain(self.digests.values_mut())
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&LineCount) -> bool) {
+        self.text.retain(|_, line_count| f(line_count));
+        self.digests.retain(|_, line_count| f(line_count));
+    }
+
+    // 以字符串为 key 的结果，没有摘要时不需要复制
+    fn count_map(&self) -> Cow<'_, CountMap> {
+        if self.digests.is_empty() {
+            return Cow::Borrowed(&self.text);
+        }
+        let mut data_count = self.text.clone();
+        for (digest, line_count) in &self.digests {
+            data_count.insert(digest_hex(*digest), line_count.clone());
+        }
+        Cow::Owned(data_count)
+    }
+
+    fn into_count_map(self) -> CountMap {
+        let mut data_count = self.text;
+        data_count.reserve(self.digests.len());
+        for (digest, line_count) in self.digests {
+            data_count.insert(digest_hex(digest), line_count);
+        }
+        data_count
+    }
+}
+
+// Counts::placeholders 中尚未统计到的 key 的首次出现行号
+const PLACEHOLDER_LINE: usize = usize::MAX;
+
+// hash_keys 时输出中代替原文的十六进制摘要
+pub(crate) fn digest_hex(digest: u128) -> String {
+    format!("{:032x}", digest)
+}
+
+/// 统计阶段记录的每个 key 的出现次数、首次出现的行号及原始写法
+#[derive(Clone, Debug)]
+pub struct LineCount {
+    pub count: usize,
+    /// 首次出现的行号，从 1 开始，多个输入文件时连续编号
+    pub first_seen: usize,
+    /// 与 key 不同时才保存，例如忽略大小写时首次出现的原始大小写
+    pub original: Option<String>,
+    /// 出现过的行号，升序排列；只在设置 [`CountOptions::positions`] 时记录
+    pub positions: Vec<usize>,
+}
+
+impl LineCount {
+    // 合并另一个线程对同一 key 的统计，保留最早出现的原始写法
+    fn merge(&mut self, other: LineCount) {
+        self.count += other.count;
+        if other.first_seen < self.first_seen {
+            self.first_seen = other.first_seen;
+            self.original = other.original;
+        }
+        // 按分块顺序合并时两段本身就是有序的，只有多个线程交错统计时才需要重新排序
+        let sorted = match (self.positions.last(), other.positions.first()) {
+            (Some(last), Some(first)) => last <= first,
+            _ => true,
+        };
+        self.positions.extend(other.positions);
+        if !sorted {
+            self.positions.sort_unstable();
+        }
+    }
+}
+
+/// 统计过程中的汇总信息
+#[derive(Clone, Debug, Default)]
+pub struct CountStats {
+    /// 读取的行数，包括表头以及被过滤掉的行
+    pub lines: usize,
+    /// 字段数与 [`CountOptions::expected_columns`] 不一致的行数
+    pub mismatched_columns: usize,
+    /// [`CountOptions::weight_column`] 或 [`CountOptions::weight_regex`] 取出的权重不是非负整数而被跳过的行数
+    pub invalid_weights: usize,
+    /// 开启 [`CountOptions::strict`] 时第一处格式错误的行号（从 1 开始）及原因
+    pub malformed: Option<(usize, String)>,
+}
+
+impl CountStats {
+    /// 累加另一个分块、线程或文件的汇总信息
+    ///
+    /// other 中的行视为紧接在已累加的行之后，其格式错误的行号会加上已累加的行数
+    pub fn merge(&mut self, other: &CountStats) {
+        if self.malformed.is_none() {
+            self.malformed =
+                (other.malformed.clone()).map(|(line, reason)| (line + self.lines, reason));
+        }
+        self.lines += other.lines;
+        self.mismatched_columns += other.mismatched_columns;
+        self.invalid_weights += other.invalid_weights;
+    }
+}
+
+/// [`find_hash_collisions`] 找到的一处哈希碰撞：内容不同的两行在 [`CountOptions::hash_keys`] 下得到相同的摘要
+#[derive(Clone, Debug)]
+pub struct HashCollision {
+    /// 两行共同的十六进制摘要
+    pub digest: String,
+    /// 先出现的一行所在的文件及行号（从 1 开始）
+    pub first: (String, usize),
+    /// 先出现的一行计算摘要的内容，即去空白、大小写转换等处理之后的 key
+    pub first_key: String,
+    /// 后出现的一行所在的文件及行号（从 1 开始）
+    pub second: (String, usize),
+    /// 后出现的一行计算摘要的内容
+    pub second_key: String,
+}
+
+/// 统计的单元
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CountMode {
+    /// 每行（经过列选择、大小写等处理后）作为一个 key
+    Lines,
+    /// 每个字符作为一个 key，不包括换行符；控制字符转义后输出，例如 \t、\u{1b}
+    Chars,
+    /// 按 Unicode 词边界（或 [`CountOptions::word_regex`]）拆出的每个词作为一个 key，标点和空白不计入
+    Words,
+    /// 每两个相邻的行（经过处理、未被过滤的行）用 [`BIGRAM_SEPARATOR`] 连接后作为一个 key，
+    /// 不跨越文件；需要按顺序读取，每个文件只用一个线程统计
+    Bigrams,
+}
+
+/// 统计阶段对每行数据的处理选项
+#[derive(Clone, Debug)]
+pub struct CountOptions {
+    /// 统计的单元，默认按行统计
+    pub mode: CountMode,
+    /// 按词统计时用该正则的每个匹配作为一个词，代替 Unicode 词边界
+    pub word_regex: Option<Regex>,
+    /// 按词统计时不统计这些词，与处理后（例如转换大小写后）的 key 比较
+    pub stopwords: FxHashSet<String>,
+    /// 按行统计时把处理后的 key 按该分隔符拆开，每一段分别作为一个 key 统计，空段不统计
+    ///
+    /// 开启 [`CountOptions::trim`] 时每一段分别去掉首尾空白
+    pub explode: Option<String>,
+    /// 不统计这些 key，与经过列选择、大小写等处理后的 key 比较，用 [`load_ignore_lines`](crate::load_ignore_lines) 读取
+    pub ignore_lines: FxHashSet<String>,
+    /// 忽略大小写合并统计
+    pub ignore_case: bool,
+    /// 统计前去掉首尾空白
+    pub trim: bool,
+    /// 统计前把每行转换为 Unicode NFC 形式，合成与分解的重音字符按同一行统计
+    pub normalize_unicode: bool,
+    /// 统计前把连续的空白替换为一个空格并去掉首尾空白
+    pub collapse_whitespace: bool,
+    /// 统计前把每行转换为小写，输出同样为小写
+    pub to_lowercase: bool,
+    /// 统计前把每行转换为大写，输出同样为大写
+    pub to_uppercase: bool,
+    /// 只统计指定的列（从 0 开始），多列时用分隔符拼接为一个 key，列数不足的按空值统计
+    /// 为空时统计整行
+    pub column: Vec<usize>,
+    /// 按列名选择统计的列，每个输入分别在第一行（表头）中查找，找到的列代替 column，
+    /// 且表头不参与统计；不支持标准输入
+    pub column_name: Vec<String>,
+    /// 按 CSV 规则拆分列：双引号包裹的字段中的分隔符不拆分，选出的字段去掉外层双引号，
+    /// 并把其中的 "" 还原为 "，使 "foo" 与 foo 合并统计；同样用于表头、字段数检查和权重列
+    pub strip_quotes: bool,
+    /// 行中的字段数不足、选中的列不存在时用该字符串作为这一列的值，与列存在但为空的情况分开统计；
+    /// 为 None 时不存在的列与空列一样视为空字符串
+    pub missing_placeholder: Option<String>,
+    /// 拆分列时使用的分隔符
+    pub input_delimiter: String,
+    /// 用该正则的第一个捕获组（没有捕获组时为整个匹配）作为 key，不匹配的行不参与统计
+    /// 设置后 column 不再生效
+    pub capture: Option<Regex>,
+    /// 用每行中该范围内的字符（从 0 开始，左闭右开）作为 key，适用于定宽格式的文件
+    /// 行比范围短时只取实际存在的部分，字符数不超过起点的行不参与统计；设置后 column 不再生效
+    pub field_range: Option<Range<usize>>,
+    /// 用每行的前 N 个字符（按字符而不是字节计数）作为 key，不足 N 个字符的行使用整行；设置后 column 不再生效
+    pub key_prefix_len: Option<usize>,
+    /// 每行应有的字段数，不一致的行计入 [`CountStats::mismatched_columns`]
+    pub expected_columns: Option<usize>,
+    /// 按时间窗口统计：该值为窗口的秒数，key 变为 timestamp_column 列的时间戳所在窗口的起始时间、
+    /// input_delimiter 与原来的 key 连接而成；时间戳无法解析的行记入 [`UNKNOWN_BUCKET`]
+    pub time_bucket: Option<u64>,
+    /// 时间戳所在的列（从 0 开始，按 input_delimiter 拆分），只在设置 time_bucket 时使用
+    pub timestamp_column: usize,
+    /// 每行累加该列（从 0 开始，按 input_delimiter 拆分）的数值而不是 1，出现次数变为该列之和
+    ///
+    /// 该列不是非负整数（或不存在）的行不参与统计，计入 [`CountStats::invalid_weights`]
+    pub weight_column: Option<usize>,
+    /// 每行累加该正则第一个捕获组（没有捕获组时为整个匹配）的数值而不是 1，例如日志中的字节数；
+    /// key 仍由列选择等选项决定
+    ///
+    /// 不匹配或捕获的内容不是非负整数的行不参与统计，计入 [`CountStats::invalid_weights`]；
+    /// 设置 weight_column 时不再生效
+    pub weight_regex: Option<Regex>,
+    /// 跳过字段数与 expected_columns 不一致的行
+    pub skip_mismatched: bool,
+    /// 跳过空行
+    pub skip_empty: bool,
+    /// 每个输入的第一行为表头，不参与统计
+    pub skip_header: bool,
+    /// 每个输入的前 N 行不参与统计（包括表头），行号仍从输入的第一行算起
+    ///
+    /// 与 limit 一样需要从头逐行读取，设置后输入不再分块并行读取
+    pub skip: usize,
+    /// 每个输入跳过 skip 行之后最多统计这么多行，之后不再读取
+    pub limit: Option<usize>,
+    /// 输入文件的编码，统计前统一转换为 UTF-8
+    pub encoding: &'static Encoding,
+    /// 只统计匹配该正则的行
+    pub include: Option<Regex>,
+    /// 反转 include 的匹配结果，只统计不匹配的行，没有设置 include 时不起作用
+    pub invert: bool,
+    /// 不统计匹配该正则的行
+    pub exclude: Option<Regex>,
+    /// 以该前缀开头的行视为注释，不参与统计，空字符串表示不过滤
+    pub comment: Option<String>,
+    /// 输入为 ZIP 压缩包时读取的条目名，为 None 时压缩包中只能有一个文件
+    pub zip_entry: Option<String>,
+    /// 以 NUL 字符而不是换行分隔记录，适用于 find -print0 等输出
+    pub null_data: bool,
+    /// 只统计字符数不少于该值的 key
+    pub min_length: Option<usize>,
+    /// 只统计字符数不超过该值的 key
+    pub max_length: Option<usize>,
+    /// 以每行内容的 128 位哈希（十六进制摘要）作为 key，不保留原文以节省内存
+    ///
+    /// 统计过程中每个 key 只保存 16 字节的摘要，统计全部结束后才转换为十六进制字符串，
+    /// 因此行越短，相比直接保存原文节省的内存越少
+    ///
+    /// 不同的行哈希相同时会被合并统计；对 n 个不同的行，碰撞概率约为 n² / 2¹²⁹，
+    /// 即使是百亿级的行也可以忽略
+    pub hash_keys: bool,
+    /// 只随机抽取 N 个不同的行并统计它们的出现次数，内存占用与 N 成正比
+    pub sample: Option<usize>,
+    /// 抽样使用的随机种子，种子相同时抽样结果相同
+    pub seed: u64,
+    /// 把无效的 UTF-8 字节替换为 U+FFFD 后继续统计，而不是报错退出
+    pub lossy: bool,
+    /// 严格模式：无效的 UTF-8、字段数与 expected_columns 不一致（未指定时为选中的列不存在）、
+    /// 权重不是非负整数的行不再跳过，统计完每个文件后报告其中第一处这样的行并返回 [`Error::Parse`]
+    pub strict: bool,
+    /// 每行最多保留的字节数，超出的部分截断后再统计
+    pub max_line_len: Option<usize>,
+    /// 分两遍统计，只保留出现不止一次的行，适合绝大多数行都不重复的超大输入
+    ///
+    /// 第一遍用布隆过滤器找出可能重复的行，第二遍只为这些行分配内存。布隆过滤器
+    /// 只会把不重复的行误判为重复，不会漏掉重复的行，误判的行在第二遍统计后被去掉，
+    /// 因此结果是准确的，误判率只影响内存占用。输入需要读取两次，不支持标准输入，
+    /// 也只对 [`count_file`] 和 [`count_file_mmap`] 生效
+    pub repeats_only_memory: bool,
+    /// 内存映射读取时每统计多少行更新一次进度条，避免逐行更新的加锁和重绘开销
+    pub progress_interval: usize,
+    /// 无法分块的输入由一个线程读取后分发给统计线程，读取线程最多领先这么多行，
+    /// 统计跟不上时读取线程等待，避免把整个输入缓存在内存中
+    pub channel_capacity: usize,
+    /// 读取输入时的缓冲区字节数，网络文件系统等延迟较高的存储上调大可以减少系统调用次数
+    pub read_buffer: usize,
+    /// 与 key 不同时保存每个 key 首次出现时选出的原始内容（去空白、规范化、大小写转换之前），
+    /// 输出时代替 key 作为行内容，便于对照分组是如何合并不同写法的；[`hash_keys`](Self::hash_keys) 时无效
+    pub keep_original: bool,
+    /// 记录每个 key 出现过的行号，每个 key 最多保存这么多个（最早的），为 None 时不记录
+    pub positions: Option<usize>,
+    /// 不同的 key 最多保存这么多个，达到上限后新出现的 key 不再单独统计，
+    /// 而是合并计入 [`OTHER_KEY`]，已有的 key 照常累加，内存占用因此有确定的上限
+    ///
+    /// 保留的总是按输入顺序最先出现的 key，它们的次数是准确的；为此设置后只用一个线程顺序统计，
+    /// 结果与 concurrency 无关
+    pub max_distinct: Option<usize>,
+    /// 中断标志，被置位（例如按下 Ctrl-C）后尽快停止读取，已统计的部分照常返回
+    pub interrupted: Option<Arc<AtomicBool>>,
+    /// 从检查点继续：该文件的前这么多行已经统计过，统计时跳过，见 [`Checkpoint`]
+    pub resume: Option<(String, usize)>,
+}
+
+impl Default for CountOptions {
+    fn default() -> Self {
+        CountOptions {
+            mode: CountMode::Lines,
+            word_regex: None,
+            stopwords: FxHashSet::default(),
+            explode: None,
+            ignore_lines: FxHashSet::default(),
+            ignore_case: false,
+            trim: false,
+            normalize_unicode: false,
+            collapse_whitespace: false,
+            to_lowercase: false,
+            to_uppercase: false,
+            column: Vec::new(),
+            column_name: Vec::new(),
+            missing_placeholder: None,
+            input_delimiter: String::from(","),
+            capture: None,
+            field_range: None,
+            key_prefix_len: None,
+            expected_columns: None,
+            time_bucket: None,
+            timestamp_column: 0,
+            weight_column: None,
+            weight_regex: None,
+            strip_quotes: false,
+            skip_mismatched: false,
+            skip_empty: false,
+            skip_header: false,
+            skip: 0,
+            limit: None,
+            encoding: UTF_8,
+            include: None,
+            invert: false,
+            exclude: None,
+            comment: None,
+            zip_entry: None,
+            null_data: false,
+            min_length: None,
+            max_length: None,
+            hash_keys: false,
+            sample: None,
+            seed: 0,
+            lossy: false,
+            strict: false,
+            max_line_len: None,
+            repeats_only_memory: false,
+            progress_interval: 10_000,
+            channel_capacity: 4096,
+            read_buffer: DEFAULT_READ_BUFFER,
+            keep_original: false,
+            positions: None,
+            max_distinct: None,
+            interrupted: None,
+            resume: None,
+        }
+    }
+}
+
+impl CountOptions {
+    // 记录之间的分隔符
+    pub(crate) fn separator(&self) -> u8 {
+        if self.null_data {
+            b'\0'
+        } else {
+            b'\n'
+        }
+    }
+}
+
+/// 解析定宽字段的范围，格式为 `START..END`（左闭右开），省略 END 表示到行尾
+pub fn parse_field_range(range: &str) -> std::result::Result<Range<usize>, String> {
+    let invalid = || format!("invalid field range '{}', expected START..END", range);
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let start = start.trim().parse().map_err(|_| invalid())?;
+    let end = match end.trim() {
+        "" => usize::MAX,
+        end => end.parse().map_err(|_| invalid())?,
+    };
+    if end <= start {
+        return Err(format!("field range '{}' is empty", range));
+    }
+    Ok(start..end)
+}
+
+/// 解析时间窗口的长度，格式为正整数加单位 s、m、h 或 d，例如 `15m`、`1h`，返回秒数
+pub fn parse_time_bucket(bucket: &str) -> std::result::Result<u64, String> {
+    let invalid = || {
+        format!(
+            "invalid time bucket '{}', expected a number followed by s, m, h or d",
+            bucket
+        )
+    };
+    let unit = bucket.chars().last().ok_or_else(invalid)?;
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let number: u64 = bucket[..bucket.len() - 1].parse().map_err(|_| invalid())?;
+    if number == 0 {
+        return Err(format!("time bucket '{}' is empty", bucket));
+    }
+    number.checked_mul(scale).ok_or_else(invalid)
+}
+
+/// 在当前线程中逐行统计 reader 的内容，开头的 UTF-8 BOM 会被跳过
+pub fn count_occurrences<R: BufRead>(
+    mut reader: R,
+    count_options: &CountOptions,
+) -> std::io::Result<CountMap> {
+    skip_bom(&mut reader)?;
+    let mut counter = Counter::default();
+    let lines = count_reader(
+        &mut counter,
+        reader,
+        count_options.skip_header,
+        count_options,
+        None,
+    )
+    .map_err(|err| match err {
+        Error::Read(err) => err,
+        err => std::io::Error::other(err),
+    })?;
+    let (mut data_count, _) = counter.finish(lines);
+    prune_sample(&mut data_count, count_options);
+    Ok(data_count.into_count_map())
+}
+
+/// 与 [`count_occurrences`] 相同，但从 tokio 的异步 reader 逐行读取，等待数据时不会阻塞运行时的线程，
+/// 适合在服务中直接统计上传的内容；需要启用 `tokio` feature
+///
+/// 每行的统计仍在当前任务中同步完成，开头的 UTF-8 BOM 会被跳过
+#[cfg(feature = "tokio")]
+pub async fn count_stream<R: AsyncBufRead + Unpin>(
+    mut reader: R,
+    count_options: &CountOptions,
+) -> std::io::Result<CountMap> {
+    if reader.fill_buf().await?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+    }
+    let mut counter = Counter::default();
+    let separator = count_options.separator();
+    let mut buf = Vec::new();
+    let mut lines = 0;
+    loop {
+        if reached_limit(lines, count_options) || is_set(count_options.interrupted.as_ref()) {
+            break;
+        }
+        buf.clear();
+        if reader.read_until(separator, &mut buf).await? == 0 {
+            break;
+        }
+        lines += 1;
+        if (count_options.skip_header && lines == 1) || lines <= count_options.skip {
+            continue;
+        }
+        let line = buf.strip_suffix(&[separator]).unwrap_or(&buf);
+        let line = strip_line_ending(line, separator);
+        let line = truncate_line(line, count_options.max_line_len);
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(_) if count_options.lossy => String::from_utf8_lossy(line),
+            Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+        };
+        count_line(&mut counter, lines, &line, count_options);
+    }
+    let (mut counts, _) = counter.finish(lines);
+    prune_sample(&mut counts, count_options);
+    Ok(counts.into_count_map())
+}
+
+/// 依次读取多个文件并用 concurrency 个线程并发统计，"-" 表示标准输入，
+/// 以 http:// 或 https:// 开头的路径从网络下载
+///
+/// 普通文件按行边界切成若干块并行统计；标准输入、URL、gzip 或 zstd 压缩文件和非 UTF-8
+/// 编码的文件无法随机访问，由一个线程读取后经 channel 分发给统计线程。
+/// 多个文件的行号连续编号，统计结果合并到同一个 map 中，同时返回汇总信息
+pub fn count_file(
+    file_paths: &[String],
+    concurrency: usize,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<(CountMap, CountStats)> {
+    count_files(file_paths, concurrency, false, count_options, pb, None)
+}
+
+/// 与 [`count_file`] 相同，但把普通文件映射到内存后切块统计，避免逐行分配 String
+pub fn count_file_mmap(
+    file_paths: &[String],
+    concurrency: usize,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<(CountMap, CountStats)> {
+    count_files(file_paths, concurrency, true, count_options, pb, None)
+}
+
+/// [`count_file_with_checkpoint`] 保存检查点时的统计进度
+pub struct Checkpoint<'a> {
+    /// 到此为止的完整统计结果
+    pub data_count: &'a CountMap,
+    /// 正在统计的文件，输入中在它之前的文件都已统计完
+    pub file_path: &'a str,
+    /// 该文件中已经读取的行数，从检查点继续时跳过这些行，见 [`CountOptions::resume`]
+    pub lines: usize,
+}
+
+/// 保存检查点的回调，见 [`count_file_with_checkpoint`]
+pub type CheckpointFn<'a> = &'a mut dyn FnMut(&Checkpoint) -> Result<()>;
+
+/// 与 [`count_file`] 相同，use_mmap 为 true 时与 [`count_file_mmap`] 相同；
+/// checkpoint 为 Some((every, f)) 时每读取 every 行调用一次 f，可以据此保存中间结果以便中断后继续
+///
+/// 为了让检查点中的结果恰好对应输入的前若干行，设置 checkpoint 后在当前线程中逐行顺序统计，
+/// concurrency 与 use_mmap 不再生效；每次调用都会复制一份当前的统计结果，every 不宜太小。
+/// 传给 f 的结果尚未按 [`CountOptions::sample`] 抽样，也没有去掉 [`CountOptions::repeats_only_memory`] 误判的行
+pub fn count_file_with_checkpoint(
+    file_paths: &[String],
+    concurrency: usize,
+    use_mmap: bool,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+    checkpoint: Option<(usize, CheckpointFn<'_>)>,
+) -> Result<(CountMap, CountStats)> {
+    count_files(
+        file_paths,
+        concurrency,
+        use_mmap,
+        count_options,
+        pb,
+        checkpoint,
+    )
+}
+
+fn count_files(
+    file_paths: &[String],
+    concurrency: usize,
+    use_mmap: bool,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+    mut checkpoint: Option<(usize, CheckpointFn<'_>)>,
+) -> Result<(CountMap, CountStats)> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(concurrency)
+        .build()
+        .map_err(|_| Error::Worker)?;
+
+    let repeats = if count_options.repeats_only_memory {
+        let repeats = find_repeats(file_paths, count_options, pb)?;
+        // 第二遍统计重新从头推进进度条
+        pb.set_position(0);
+        Some(Arc::new(repeats))
+    } else {
+        None
+    };
+
+    // 相邻行对需要按顺序读取，不能分块或分发给多个线程；
+    // 限制不同 key 的个数时保留哪些 key 取决于出现的先后，也只能由一个 Counter 按顺序统计整个文件
+    let sequential =
+        count_options.mode == CountMode::Bigrams || count_options.max_distinct.is_some();
+    let concurrency = if sequential { 1 } else { concurrency };
+    let mut data_count = Counts::default();
+    let mut stats = CountStats::default();
+    for file_path in file_paths {
+        let count_options = resolve_column_names(file_path, count_options)?;
+        // 从检查点继续时跳过该文件中已经统计过的行
+        let count_options = &*match &count_options.resume {
+            Some((resume_path, lines)) if resume_path == file_path => Cow::Owned(CountOptions {
+                skip: count_options.skip.max(*lines),
+                ..count_options.into_owned()
+            }),
+            _ => count_options,
+        };
+        // 每个分块或线程从 template 复制出自己的 Counter；限制不同 key 的个数时预先放入之前的文件中
+        // 已经单独统计的 key，它们在本文件中照常累加，新 key 只能占用剩余的名额
+        let template = Counter {
+            data_count: match count_options.max_distinct {
+                Some(_) => data_count.placeholders(),
+                None => Counts::default(),
+            },
+            repeats: repeats.clone(),
+            ..Counter::default()
+        };
+        let (mut local_count, mut local_stats) = if let Some((every, f)) = &mut checkpoint {
+            let mut save = |counter: &Counter, lines: usize| {
+                // 加上此前的文件得到截至当前行的完整结果
+                let (mut local_count, _) = counter.clone().finish(lines);
+                local_count.retain(|line_count| line_count.first_seen != PLACEHOLDER_LINE);
+                let mut snapshot = data_count.clone();
+                snapshot.merge(local_count, stats.lines);
+                f(&Checkpoint {
+                    data_count: &snapshot.count_map(),
+                    file_path,
+                    lines,
+                })
+            };
+            with_input(file_path, count_options, pb, |reader| {
+                let mut counter = template.clone();
+                let lines = count_reader(
+                    &mut counter,
+                    reader,
+                    count_options.skip_header,
+                    count_options,
+                    Some((*every, &mut save)),
+                )?;
+                Ok(counter.finish(lines))
+            })?
+        } else if !can_split(file_path, count_options)? {
+            count_streamed(file_path, concurrency, count_options, &template, pb)?
+        } else if use_mmap {
+            let mmap = map_file(file_path)?;
+            let ranges = if sequential {
+                vec![(0, mmap.len())]
+            } else {
+                slice_chunk_ranges(&mmap, concurrency, count_options.separator())
+            };
+            count_chunks(&pool, &ranges, |(start, end), first| {
+                let mut counter = template.clone();
+                let lines =
+                    count_mapped(&mut counter, &mmap[start..end], first, count_options, pb)?;
+                Ok(counter.finish(lines))
+            })?
+        } else {
+            let ranges = if sequential {
+                vec![(0, input_len(file_path)?.unwrap_or(0))]
+            } else {
+                file_chunk_ranges(file_path, concurrency, count_options.separator())?
+            };
+            count_chunks(&pool, &ranges, |(start, end), first| {
+                count_file_range(file_path, start, end, first, count_options, &template, pb)
+            })?
+        };
+        // 去掉本文件中没有出现过的占位
+        local_count.retain(|line_count| line_count.first_seen != PLACEHOLDER_LINE);
+        if let Some((line, message)) = local_stats.malformed.take() {
+            return Err(Error::Parse {
+                path: file_path.clone(),
+                line,
+                message,
+            });
+        }
+        data_count.merge(local_count, stats.lines);
+        stats.merge(&local_stats);
+        // 每个线程各自最多记录 max 个行号，合并后只保留最早的 max 个
+        if let Some(max) = count_options.positions {
+            for line_count in data_count.values_mut() {
+                line_count.positions.truncate(max);
+            }
+        }
+        if is_set(count_options.interrupted.as_ref()) {
+            break;
+        }
+    }
+    // 去掉被布隆过滤器误判为重复的行
+    if repeats.is_some() {
+        data_count.retain(|line_count| line_count.count > 1);
+    }
+    prune_sample(&mut data_count, count_options);
+    Ok((data_count.into_count_map(), stats))
+}
+
+// 第一遍读取所有输入，用两个布隆过滤器找出可能出现不止一次的 key
+// 过滤器的比特数与输入的总字节数相同，每行平均几十个字节时误判率很低
+fn find_repeats(
+    file_paths: &[String],
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<BloomFilter> {
+    let mut bits = 0;
+    for file_path in file_paths {
+        bits += input_len(file_path)?.unwrap_or(0);
+    }
+    let mut seen = BloomFilter::new(bits);
+    let mut repeated = BloomFilter::new(bits);
+    // 字段数不一致的行在第二遍统计时才计数
+    let mut stats = CountStats::default();
+    for file_path in file_paths {
+        let count_options = &*resolve_column_names(file_path, count_options)?;
+        let mut previous = None;
+        for_each_line(file_path, count_options, pb, |_, line| {
+            if let Some((key, original)) = extract_key(&line, count_options, &mut stats) {
+                let Some((key, original)) = bigram(&mut previous, key, original, count_options)
+                else {
+                    return Ok(());
+                };
+                for_each_unit(key, original, count_options, |key, _| {
+                    if seen.insert(&key) {
+                        repeated.insert(&key);
+                    }
+                });
+            }
+            Ok(())
+        })?;
+    }
+    Ok(repeated)
+}
+
+/// 重新读取一遍输入，检查 [`CountOptions::hash_keys`] 时是否有内容不同的行得到了相同的摘要而被合并统计
+///
+/// 每个摘要保存首次得到它的 key 及其位置，之后摘要相同的 key 与之逐字比较，内容不同即为碰撞，
+/// 每对不同的内容只报告一次；需要为每个不同的 key 保存一份原文，内存占用与不开启 hash_keys 的统计相当。
+/// 不支持标准输入
+pub fn find_hash_collisions(
+    file_paths: &[String],
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<Vec<HashCollision>> {
+    collisions_with(file_paths, count_options, pb, xxh3_128)
+}
+
+// 用 digest 计算每个 key 的摘要并查找碰撞，测试中可以换成容易碰撞的摘要
+fn collisions_with(
+    file_paths: &[String],
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+    digest: impl Fn(&[u8]) -> u128,
+) -> Result<Vec<HashCollision>> {
+    // 关闭 hash_keys 后得到的 key 就是计算摘要之前的内容
+    let plain_options = CountOptions {
+        hash_keys: false,
+        ..count_options.clone()
+    };
+    // 每个摘要首次出现时的 key、文件序号和行号
+    let mut seen: FxHashMap<u128, (String, usize, usize)> = FxHashMap::default();
+    let mut collisions = Vec::new();
+    let mut reported = FxHashSet::default();
+    let mut stats = CountStats::default();
+    for (index, file_path) in file_paths.iter().enumerate() {
+        let count_options = &*resolve_column_names(file_path, &plain_options)?;
+        for_each_line(file_path, count_options, pb, |line_no, line| {
+            let Some((key, _)) = extract_key(&line, count_options, &mut stats) else {
+                return Ok(());
+            };
+            let digest = digest(key.as_bytes());
+            let (first_key, first_file, first_line) = match seen.entry(digest) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert((key.into_owned(), index, line_no));
+                    return Ok(());
+                }
+            };
+            if *first_key != key && reported.insert((digest, key.to_string())) {
+                collisions.push(HashCollision {
+                    digest: digest_hex(digest),
+                    first: (file_paths[*first_file].clone(), *first_line),
+                    first_key: first_key.clone(),
+                    second: (file_path.clone(), line_no),
+                    second_key: key.into_owned(),
+                });
+            }
+            Ok(())
+        })?;
+        if is_set(count_options.interrupted.as_ref()) {
+            break;
+        }
+    }
+    Ok(collisions)
+}
+
+// 在输入的第一行中查找 column_name 指定的列，返回按列号统计、跳过表头的选项
+// 没有指定列名时原样返回；列名不存在或在表头中出现多次时返回错误
+pub(crate) fn resolve_column_names<'a>(
+    file_path: &str,
+    count_options: &'a CountOptions,
+) -> Result<Cow<'a, CountOptions>> {
+    if count_options.column_name.is_empty() {
+        return Ok(Cow::Borrowed(count_options));
+    }
+    // 标准输入无法先读出表头再从头统计
+    if file_path == "-" {
+        return Err(Error::Open {
+            path: file_path.to_string(),
+            source: std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "column names cannot be resolved from standard input",
+            ),
+        });
+    }
+    let header = read_header(file_path, count_options)?.unwrap_or_default();
+    // 表头中的列名允许带首尾空白或用双引号包裹
+    let delimiter = count_options.input_delimiter.as_str();
+    let names: Vec<_> = if count_options.strip_quotes {
+        split_quoted(&header, delimiter)
+            .into_iter()
+            .map(|name| unquote(name, true))
+            .collect()
+    } else {
+        header
+            .split(delimiter)
+            .map(|name| Cow::Borrowed(name.trim().trim_matches('"')))
+            .collect()
+    };
+    let mut column = Vec::with_capacity(count_options.column_name.len());
+    for column_name in &count_options.column_name {
+        let parse_error = |message| Error::Parse {
+            path: file_path.to_string(),
+            line: 1,
+            message,
+        };
+        let mut indexes = (0..names.len()).filter(|index| names[*index] == *column_name);
+        let index = indexes.next().ok_or_else(|| {
+            parse_error(format!(
+                "column '{}' not found in header, available columns: {}",
+                column_name,
+                names.join(", ")
+            ))
+        })?;
+        if indexes.next().is_some() {
+            return Err(parse_error(format!(
+                "column '{}' appears more than once in header",
+                column_name
+            )));
+        }
+        column.push(index);
+    }
+    Ok(Cow::Owned(CountOptions {
+        column,
+        skip_header: true,
+        ..count_options.clone()
+    }))
+}
+
+// 只有未压缩的 UTF-8 普通文件才能按字节切块，按行号截取输入时需要从头读取
+fn can_split(file_path: &str, count_options: &CountOptions) -> Result<bool> {
+    Ok(file_path != "-"
+        && count_options.skip == 0
+        && count_options.limit.is_none()
+        && count_options.encoding == UTF_8
+        && !is_zip(file_path)
+        && !is_url(file_path)
+        && is_regular_file(file_path)
+        && !is_compressed(file_path)?)
+}
+
+// 在线程池中并行统计每个分块，再按分块顺序修正行号并归并
+fn count_chunks<T: Copy + Sync>(
+    pool: &ThreadPool,
+    ranges: &[T],
+    count_range: impl Fn(T, bool) -> Result<(Counts, CountStats)> + Sync,
+) -> Result<(Counts, CountStats)> {
+    let results = pool.install(|| {
+        ranges
+            .par_iter()
+            .enumerate()
+            .map(|(index, range)| count_range(*range, index == 0))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    // 每个分块的行号从 1 开始，需要加上前面所有分块的行数
+    let mut offsets = Vec::with_capacity(results.len());
+    let mut stats = CountStats::default();
+    for (_, chunk_stats) in &results {
+        offsets.push(stats.lines);
+        stats.merge(chunk_stats);
+    }
+    let data_count = pool.install(|| {
+        results
+            .into_par_iter()
+            .zip(offsets)
+            .map(|((chunk_count, _), offset)| {
+                let mut data_count = Counts::default();
+                data_count.merge(chunk_count, offset);
+                data_count
+            })
+            .reduce(Counts::default, |mut a, mut b| {
+                // 把较小的 map 合并进较大的 map
+                if a.len() < b.len() {
+                    std::mem::swap(&mut a, &mut b);
+                }
+                a.merge(b, 0);
+                a
+            })
+    });
+    Ok((data_count, stats))
+}
+
+// 统计文件中 [start, end) 范围内的行，first 表示是否为文件的第一个分块
+fn count_file_range(
+    file_path: &str,
+    start: u64,
+    end: u64,
+    first: bool,
+    count_options: &CountOptions,
+    template: &Counter,
+    pb: &ProgressBar,
+) -> Result<(Counts, CountStats)> {
+    let mut file = File::open(file_path).map_err(|source| Error::Open {
+        path: file_path.to_string(),
+        source,
+    })?;
+    file.seek(SeekFrom::Start(start)).map_err(Error::Read)?;
+    let mut reader = BufReader::with_capacity(
+        count_options.read_buffer.max(1),
+        ProgressReader::new(file.take(end - start), pb),
+    );
+    if first {
+        skip_bom(&mut reader).map_err(Error::Read)?;
+    }
+    let mut counter = template.clone();
+    let lines = count_reader(
+        &mut counter,
+        reader,
+        first && count_options.skip_header,
+        count_options,
+        None,
+    )?;
+    Ok(counter.finish(lines))
+}
+
+// 由一个线程读取输入并经 channel 分发给 concurrency 个统计线程，返回统计结果及汇总信息
+// 每个统计线程从 template 复制出自己的 Counter
+fn count_streamed(
+    file_path: &str,
+    concurrency: usize,
+    count_options: &CountOptions,
+    template: &Counter,
+    pb: &ProgressBar,
+) -> Result<(Counts, CountStats)> {
+    // 创建有界 channel 用于传递数据，统计线程跟不上时读取线程阻塞等待
+    let (data_sender, data_receiver) =
+        std::sync::mpsc::sync_channel(count_options.channel_capacity);
+    // 使用 Arc<Mutex<_>> 包裹 data_receiver
+    let data_receiver = Arc::new(Mutex::new(data_receiver));
+
+    // 启动并发线程统计数据，每个线程维护自己的 map
+    let mut handles = vec![];
+    for _ in 0..concurrency {
+        // 克隆 data_receiver 的 Arc 指针
+        let data_receiver = data_receiver.clone();
+        let count_options = count_options.clone();
+        let counter = template.clone();
+        let handle = thread::spawn(move || count_data(counter, data_receiver, &count_options));
+        handles.push(handle);
+    }
+    // 读取文件并逐行发送数据，发送端释放后统计线程随之结束
+    let read_result = read_file(file_path, count_options, &data_sender, pb);
+    drop(data_sender);
+
+    // 等待所有线程完成，并合并各线程的统计结果
+    let mut data_count = Counts::default();
+    let mut stats = CountStats::default();
+    for handle in handles {
+        let (local_count, mut local_stats) = handle.join().map_err(|_| Error::Worker)?;
+        data_count.merge(local_count, 0);
+        // 各线程拿到的行号已经是文件中的行号，不需要加上偏移，取最靠前的一处
+        let malformed = local_stats.malformed.take();
+        stats.merge(&local_stats);
+        stats.malformed = (stats.malformed.take().into_iter())
+            .chain(malformed)
+            .min_by_key(|(line, _)| *line);
+    }
+    // 行数以读取线程为准，统计线程收不到被跳过的表头
+    stats.lines = read_result?;
+    Ok((data_count, stats))
+}
+
+// 统计一段内存中的内容，返回读取的行数；first 表示是否为文件开头
+fn count_mapped(
+    counter: &mut Counter,
+    data: &[u8],
+    first: bool,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<usize> {
+    let mut start = 0;
+    if first && data.starts_with(UTF8_BOM) {
+        start = UTF8_BOM.len();
+    }
+    let mut lines: usize = 0;
+    // 已经计入进度条的字节数，每 interval 行才推进一次
+    let mut reported = 0;
+    let interval = count_options.progress_interval.max(1);
+    let separator = count_options.separator();
+    for line in data[start..].split(|b| *b == separator) {
+        // 以换行结尾时 split 会多出一个空片段
+        if line.is_empty() && start == data.len() {
+            break;
+        }
+        if is_set(count_options.interrupted.as_ref()) {
+            break;
+        }
+        // 最后一行没有换行符时不存在分隔符，限制在数据末尾，进度条仍恰好走到总长
+        start = (start + line.len() + 1).min(data.len());
+        lines += 1;
+        if lines.is_multiple_of(interval) {
+            pb.inc((start - reported) as u64);
+            reported = start;
+        }
+        if first && count_options.skip_header && lines == 1 {
+            continue;
+        }
+        let line = strip_line_ending(line, separator);
+        let line = truncate_line(line, count_options.max_line_len);
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(_) if count_options.lossy => String::from_utf8_lossy(line),
+            // 严格模式下记下行号，由调用方报告
+            Err(err) if count_options.strict => {
+                counter.stats.malformed = Some((lines, err.to_string()));
+                break;
+            }
+            Err(err) => {
+                return Err(Error::Read(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    err,
+                )))
+            }
+        };
+        count_line(counter, lines, &line, count_options);
+    }
+    pb.inc((start - reported) as u64);
+    Ok(lines)
+}
+
+// 统计过程中定期调用的回调，参数为当前的 Counter 和已读取的行数
+type CounterHook<'a> = &'a mut dyn FnMut(&Counter, usize) -> Result<()>;
+
+// 逐行统计 reader 的内容，返回读取的行数
+// checkpoint 为 Some((every, f)) 时每读取 every 行调用一次 f，传入当前的 Counter 和已读取的行数
+fn count_reader<R: BufRead>(
+    counter: &mut Counter,
+    reader: R,
+    skip_header: bool,
+    count_options: &CountOptions,
+    mut checkpoint: Option<(usize, CounterHook<'_>)>,
+) -> Result<usize> {
+    let mut lines = 0;
+    for line in crate::input::lines(reader, count_options) {
+        if reached_limit(lines, count_options) {
+            break;
+        }
+        let line = match line {
+            Err(err) if count_options.strict && err.kind() == std::io::ErrorKind::InvalidData => {
+                counter.stats.malformed = Some((lines + 1, err.to_string()));
+                break;
+            }
+            line => line.map_err(Error::Read)?,
+        };
+        lines += 1;
+        if (skip_header && lines == 1) || lines <= count_options.skip {
+            continue;
+        }
+        count_line(counter, lines, &line, count_options);
+        if let Some((every, f)) = &mut checkpoint {
+            if lines.is_multiple_of((*every).max(1)) {
+                f(counter, lines)?;
+            }
+        }
+    }
+    Ok(lines)
+}
+
+// 统计每行数据的出现次数，返回本线程的统计结果及汇总信息
+fn count_data(
+    mut counter: Counter,
+    data_receiver: Arc<Mutex<Receiver<(usize, String)>>>,
+    count_options: &CountOptions,
+) -> (Counts, CountStats) {
+    let mut processed = 0;
+    loop {
+        // 每次只取一行，取完立即释放锁，让其他线程也能拿到数据
+        let next = data_receiver.lock().unwrap().recv();
+        let (line_no, line) = match next {
+            Ok(next) => next,
+            Err(_) => break,
+        };
+        processed += 1;
+        count_line(&mut counter, line_no, &line, count_options);
+    }
+    counter.finish(processed)
+}
+
+// Counter 单个线程的统计状态
+// 抽样时 threshold 为已淘汰的最小哈希，哈希不小于它的新 key 一定不会被抽中，直接丢弃
+// 两遍统计时 repeats 为第一遍找出的可能重复的 key，不在其中的新 key 直接丢弃
+// 不同的 key 达到上限后，新 key 的出现次数累加到 other，结束时记为 OTHER_KEY
+// 按相邻行对统计时 previous 为上一行的 key 及其原始写法
+#[derive(Clone, Default)]
+struct Counter {
+    data_count: Counts,
+    threshold: Option<u64>,
+    stats: CountStats,
+    repeats: Option<Arc<BloomFilter>>,
+    other: Option<LineCount>,
+    previous: Option<(String, Option<String>)>,
+}
+
+impl Counter {
+    // 结束统计，lines 为本线程读取的行数
+    fn finish(mut self, lines: usize) -> (Counts, CountStats) {
+        self.stats.lines = lines;
+        if let Some(other) = self.other {
+            merge_line_count(&mut self.data_count.text, OTHER_KEY.to_string(), other);
+        }
+        (self.data_count, self.stats)
+    }
+}
+
+// 统计一行数据，line_no 为从 1 开始的行号
+fn count_line(counter: &mut Counter, line_no: usize, line: &str, count_options: &CountOptions) {
+    let Some((key, original)) = extract_key(line, count_options, &mut counter.stats) else {
+        return;
+    };
+    if count_options.strict {
+        if let Some(reason) = malformed_fields(line, count_options) {
+            counter.stats.malformed.get_or_insert((line_no, reason));
+            return;
+        }
+    }
+    let weight = if let Some(column) = count_options.weight_column {
+        parse_weight(line, column, count_options)
+    } else if let Some(weight_regex) = &count_options.weight_regex {
+        capture_weight(line, weight_regex)
+    } else {
+        Some(1)
+    };
+    let Some(weight) = weight else {
+        counter.stats.invalid_weights += 1;
+        if count_options.strict {
+            let reason = String::from("weight is not a non-negative integer");
+            counter.stats.malformed.get_or_insert((line_no, reason));
+        }
+        return;
+    };
+    let (key, original) = match count_options.time_bucket {
+        Some(width) => {
+            let bucket = time_bucket(line, width, count_options);
+            let delimiter = count_options.input_delimiter.as_str();
+            let join = |key: &str| Cow::Owned(format!("{}{}{}", bucket, delimiter, key));
+            (join(&key), original.map(|original| join(&original)))
+        }
+        None => (key, original),
+    };
+    let Some((key, original)) = bigram(&mut counter.previous, key, original, count_options) else {
+        return;
+    };
+    for_each_unit(key, original, count_options, |key, original| {
+        count_key(counter, line_no, key, original, weight, count_options)
+    });
+}
+
+// 严格模式下检查一行的字段数：指定了 expected_columns 时必须与之相同，否则选中的列都必须存在
+fn malformed_fields(line: &str, count_options: &CountOptions) -> Option<String> {
+    let delimiter = count_options.input_delimiter.as_str();
+    let fields = || {
+        if count_options.strip_quotes {
+            split_quoted(line, delimiter).len()
+        } else {
+            line.split(delimiter).count()
+        }
+    };
+    if let Some(expected_columns) = count_options.expected_columns {
+        let fields = fields();
+        return (fields != expected_columns)
+            .then(|| format!("expected {} fields, found {}", expected_columns, fields));
+    }
+    let column = count_options.column.iter().max()?;
+    let fields = fields();
+    (*column >= fields).then(|| format!("column {} is missing, found {} fields", column, fields))
+}
+
+// 取出一行中时间戳列的值，返回它所在时间窗口的起始时间，时间戳无法解析时返回 UNKNOWN_BUCKET
+fn time_bucket(line: &str, width: u64, count_options: &CountOptions) -> String {
+    let delimiter = count_options.input_delimiter.as_str();
+    let column = count_options.timestamp_column;
+    let field = if count_options.strip_quotes {
+        split_quoted(line, delimiter)
+            .get(column)
+            .map(|field| unquote(field, true))
+    } else {
+        line.split(delimiter).nth(column).map(Cow::Borrowed)
+    };
+    let Some(timestamp) = field.and_then(|field| parse_timestamp(field.trim())) else {
+        return UNKNOWN_BUCKET.to_string();
+    };
+    let seconds = timestamp.and_utc().timestamp();
+    let start = seconds - seconds.rem_euclid(width as i64);
+    match DateTime::from_timestamp(start, 0) {
+        Some(start) => start.naive_utc().format("%Y-%m-%dT%H:%M:%S").to_string(),
+        None => UNKNOWN_BUCKET.to_string(),
+    }
+}
+
+// 依次按 RFC 3339、不带时区的 ISO 8601 日期时间（日期与时间之间为 T 或空格）及日期解析时间戳，
+// 带时区的时间戳换算为 UTC，不带时区的原样使用
+fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Some(timestamp.naive_utc());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(timestamp);
+        }
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    Some(date.and_time(NaiveTime::MIN))
+}
+
+// 取出一行中权重列的值，允许首尾空白，不是非负整数时返回 None
+fn parse_weight(line: &str, column: usize, count_options: &CountOptions) -> Option<usize> {
+    let delimiter = count_options.input_delimiter.as_str();
+    let field = if count_options.strip_quotes {
+        unquote(split_quoted(line, delimiter).get(column)?, true)
+    } else {
+        Cow::Borrowed(line.split(delimiter).nth(column)?)
+    };
+    field.trim().parse().ok()
+}
+
+// 取出正则在一行中捕获的权重，允许首尾空白，不匹配或不是非负整数时返回 None
+fn capture_weight(line: &str, weight_regex: &Regex) -> Option<usize> {
+    let group = if weight_regex.captures_len() > 1 {
+        1
+    } else {
+        0
+    };
+    weight_regex
+        .captures(line)?
+        .get(group)?
+        .as_str()
+        .trim()
+        .parse()
+        .ok()
+}
+
+// 按相邻行对统计时把 key 与上一行的 key 连接为一对并记下当前行，第一行没有上一行时返回 None
+// 其他统计模式原样返回；其中一行有原始写法时，行对的原始写法由两行的原始写法连接而成
+fn bigram<'a>(
+    previous: &mut Option<(String, Option<String>)>,
+    key: Cow<'a, str>,
+    original: Option<Cow<'a, str>>,
+    count_options: &CountOptions,
+) -> Option<(Cow<'a, str>, Option<Cow<'a, str>>)> {
+    if count_options.mode != CountMode::Bigrams {
+        return Some((key, original));
+    }
+    let current = (key.into_owned(), original.map(Cow::into_owned));
+    let pair = previous.as_ref().map(|(previous_key, previous_original)| {
+        let key = format!("{}{}{}", previous_key, BIGRAM_SEPARATOR, current.0);
+        let original = (previous_original.is_some() || current.1.is_some()).then(|| {
+            format!(
+                "{}{}{}",
+                previous_original.as_deref().unwrap_or(previous_key),
+                BIGRAM_SEPARATOR,
+                current.1.as_deref().unwrap_or(&current.0)
+            )
+        });
+        (Cow::Owned(key), original.map(Cow::Owned))
+    });
+    *previous = Some(current);
+    pair
+}
+
+// 按统计模式把一行的 key 拆成实际统计的单元，按行统计时就是 key 本身
+fn for_each_unit(
+    key: Cow<'_, str>,
+    original: Option<Cow<'_, str>>,
+    count_options: &CountOptions,
+    mut f: impl FnMut(Cow<'_, str>, Option<Cow<'_, str>>),
+) {
+    match count_options.mode {
+        // 行对已经由 bigram 连接好
+        CountMode::Bigrams => f(key, original),
+        CountMode::Lines => match &count_options.explode {
+            None => f(key, original),
+            // 与按词统计相同，忽略大小写时从原始写法中拆分，每一段单独转换为小写
+            Some(delimiter) => {
+                let text = original.as_deref().unwrap_or(&key);
+                for token in text.split(delimiter.as_str()) {
+                    let token = if count_options.trim {
+                        token.trim()
+                    } else {
+                        token
+                    };
+                    if token.is_empty() {
+                        continue;
+                    }
+                    if original.is_some() {
+                        let key = token.to_lowercase();
+                        let original = (key != token).then_some(Cow::Borrowed(token));
+                        f(Cow::Owned(key), original);
+                    } else {
+                        f(Cow::Borrowed(token), None);
+                    }
+                }
+            }
+        },
+        // 大小写转换可能改变字符数，逐个字符时无法对应原始写法，只统计转换后的字符
+        CountMode::Chars => {
+            for (index, char) in key.char_indices() {
+                if char.is_control() {
+                    f(Cow::Owned(char.escape_debug().to_string()), None);
+                } else {
+                    f(Cow::Borrowed(&key[index..index + char.len_utf8()]), None);
+                }
+            }
+        }
+        CountMode::Words => {
+            // 忽略大小写时从原始写法中拆词，每个词单独转换为小写，以便保留各自的原始写法
+            let text = original.as_deref().unwrap_or(&key);
+            let words: Box<dyn Iterator<Item = &str>> = match &count_options.word_regex {
+                Some(word_regex) => Box::new(word_regex.find_iter(text).map(|word| word.as_str())),
+                None => Box::new(text.unicode_words()),
+            };
+            for word in words.filter(|word| !word.is_empty()) {
+                let (key, original) = if original.is_some() {
+                    let key = word.to_lowercase();
+                    let original = (key != word).then_some(Cow::Borrowed(word));
+                    (Cow::Owned(key), original)
+                } else {
+                    (Cow::Borrowed(word), None)
+                };
+                if !count_options.stopwords.contains(key.as_ref()) {
+                    f(key, original);
+                }
+            }
+        }
+    }
+}
+
+// 统计一个 key，line_no 为它所在的行号，出现次数增加 weight
+fn count_key(
+    counter: &mut Counter,
+    line_no: usize,
+    key: Cow<'_, str>,
+    original: Option<Cow<'_, str>>,
+    weight: usize,
+    count_options: &CountOptions,
+) {
+    // 开启 hash_keys 时只保存摘要，不为 key 分配 String
+    let digest = count_options.hash_keys.then(|| xxh3_128(key.as_bytes()));
+    let data_count = &mut counter.data_count;
+    let existing = match digest {
+        Some(digest) => data_count.digests.get_mut(&digest),
+        None => data_count.text.get_mut(key.as_ref()),
+    };
+    // 只有第一次出现的 key 才需要分配新的 String
+    if let Some(line_count) = existing {
+        line_count.count += weight;
+        if count_options
+            .positions
+            .is_some_and(|max| line_count.positions.len() < max)
+        {
+            line_count.positions.push(line_no);
+        }
+        if line_no < line_count.first_seen {
+            line_count.first_seen = line_no;
+            line_count.original = original.map(Cow::into_owned);
+        }
+        return;
+    }
+    if let Some(repeats) = &counter.repeats {
+        if !repeats.contains(&key) {
+            return;
+        }
+    }
+    if let Some(threshold) = counter.threshold {
+        let hash = match digest {
+            Some(digest) => digest_sample_hash(digest, count_options.seed),
+            None => sample_hash(&key, count_options.seed),
+        };
+        if hash >= threshold {
+            return;
+        }
+    }
+    let positions = match count_options.positions {
+        Some(max) if max > 0 => vec![line_no],
+        _ => Vec::new(),
+    };
+    if count_options
+        .max_distinct
+        .is_some_and(|max| data_count.len() >= max)
+    {
+        let overflow = LineCount {
+            count: weight,
+            first_seen: line_no,
+            original: None,
+            positions,
+        };
+        match &mut counter.other {
+            Some(other) => {
+                other.merge(overflow);
+                if let Some(max) = count_options.positions {
+                    other.positions.truncate(max);
+                }
+            }
+            None => counter.other = Some(overflow),
+        }
+        return;
+    }
+    let line_count = LineCount {
+        count: weight,
+        first_seen: line_no,
+        original: original.map(Cow::into_owned),
+        positions,
+    };
+    match digest {
+        Some(digest) => data_count.digests.insert(digest, line_count),
+        None => data_count.text.insert(key.into_owned(), line_count),
+    };
+    // 积累到两倍样本数时淘汰一半，均摊下来每行只需常数时间
+    if let Some(sample) = count_options.sample {
+        if data_count.len() >= sample.max(1) * 2 {
+            counter.threshold = prune_sample(data_count, count_options).or(counter.threshold);
+        }
+    }
+}
+
+// 抽样使用的哈希，seed 不同时抽中的行也不同
+fn sample_hash(key: &str, seed: u64) -> u64 {
+    xxh3_64_with_seed(key.as_bytes(), seed)
+}
+
+// hash_keys 时抽样使用的哈希，由摘要计算，与 sample_hash 一样随 seed 变化
+fn digest_sample_hash(digest: u128, seed: u64) -> u64 {
+    xxh3_64_with_seed(&digest.to_le_bytes(), seed)
+}
+
+// 抽样时只保留哈希最小的 sample 个 key，返回淘汰的 key 中最小的哈希
+// 被保留的 key 在任何时候都不会被淘汰，因此它们的出现次数是准确的
+fn prune_sample(data_count: &mut Counts, count_options: &CountOptions) -> Option<u64> {
+    let sample = count_options.sample?;
+    if data_count.len() <= sample {
+        return None;
+    }
+    let seed = count_options.seed;
+    let text = data_count.text.keys().map(|key| sample_hash(key, seed));
+    let digests = (data_count.digests.keys()).map(|digest| digest_sample_hash(*digest, seed));
+    let mut hashes: Vec<_> = text.chain(digests).collect();
+    let threshold = *hashes.select_nth_unstable(sample).1;
+    (data_count.text).retain(|key, _| sample_hash(key, seed) < threshold);
+    (data_count.digests).retain(|digest, _| digest_sample_hash(*digest, seed) < threshold);
+    Some(threshold)
+}
+
+// 根据统计选项把一行数据转换为统计用的 key，key 与输出写法不同时一并返回原始写法
+// 返回 None 表示该行不参与统计
+pub(crate) fn extract_key<'a>(
+    line: &'a str,
+    count_options: &CountOptions,
+    stats: &mut CountStats,
+) -> Option<(Cow<'a, str>, Option<Cow<'a, str>>)> {
+    if let Some(comment) = &count_options.comment {
+        // 开启 --trim 时允许注释前有空白
+        let head = if count_options.trim {
+            line.trim_start()
+        } else {
+            line
+        };
+        if !comment.is_empty() && head.starts_with(comment.as_str()) {
+            return None;
+        }
+    }
+    if let Some(include) = &count_options.include {
+        if include.is_match(line) == count_options.invert {
+            return None;
+        }
+    }
+    if let Some(exclude) = &count_options.exclude {
+        if exclude.is_match(line) {
+            return None;
+        }
+    }
+    if count_options.skip_empty {
+        let blank = if count_options.trim {
+            line.trim().is_empty()
+        } else {
+            line.is_empty()
+        };
+        if blank {
+            return None;
+        }
+    }
+
+    let delimiter = count_options.input_delimiter.as_str();
+    if let Some(expected_columns) = count_options.expected_columns {
+        let fields = if count_options.strip_quotes {
+            split_quoted(line, delimiter).len()
+        } else {
+            line.split(delimiter).count()
+        };
+        if fields != expected_columns {
+            stats.mismatched_columns += 1;
+            if count_options.skip_mismatched {
+                return None;
+            }
+        }
+    }
+
+    let selected = select_fields(line, count_options, count_options.trim)?;
+    // 原始内容只做列选择，去空白时需要重新选出未去空白的内容
+    let raw = if !count_options.keep_original || count_options.hash_keys {
+        None
+    } else if count_options.trim {
+        select_fields(line, count_options, false)
+    } else {
+        Some(selected.clone())
+    };
+    let line = normalize_line(selected, count_options);
+
+    // 按字符数（而不是字节数）过滤过短或过长的 key
+    if count_options.min_length.is_some() || count_options.max_length.is_some() {
+        let length = line.chars().count();
+        if count_options.min_length.is_some_and(|min| length < min)
+            || count_options.max_length.is_some_and(|max| length > max)
+        {
+            return None;
+        }
+    }
+
+    let (key, original) = line_key(line, count_options);
+    if count_options.ignore_lines.contains(key.as_ref()) {
+        return None;
+    }
+    let original = match raw {
+        Some(raw) => (raw != key).then_some(raw),
+        None => original,
+    };
+    Some((key, original))
+}
+
+// 按列、字符范围或正则捕获组选出参与统计的内容，trim_fields 为 true 时去掉（每一列的）首尾空白
+// 捕获组没有参与匹配或字符范围的起点超出行尾时返回 None
+fn select_fields<'a>(
+    line: &'a str,
+    count_options: &CountOptions,
+    trim_fields: bool,
+) -> Option<Cow<'a, str>> {
+    let delimiter = count_options.input_delimiter.as_str();
+    // 开启 --trim 时多列统计会分别去掉每一列的首尾空白
+    let trim = |field: &'a str| {
+        if trim_fields {
+            field.trim()
+        } else {
+            field
+        }
+    };
+    // 不存在的列，未指定 missing_placeholder 时为空字符串，不需要分配
+    let missing = || {
+        Cow::Owned(
+            count_options
+                .missing_placeholder
+                .clone()
+                .unwrap_or_default(),
+        )
+    };
+    let line = if let Some(field_range) = &count_options.field_range {
+        let mut chars = line.char_indices().map(|(index, _)| index);
+        let start = chars.nth(field_range.start)?;
+        let len = field_range.end.saturating_sub(field_range.start);
+        // 起点之后第 len 个字符的位置即为终点，不足时取到行尾
+        let end = match len {
+            0 => start,
+            len => chars.nth(len - 1).unwrap_or(line.len()),
+        };
+        Cow::Borrowed(trim(&line[start..end]))
+    } else if let Some(len) = count_options.key_prefix_len {
+        let end = line
+            .char_indices()
+            .nth(len)
+            .map_or(line.len(), |(index, _)| index);
+        Cow::Borrowed(trim(&line[..end]))
+    } else if let Some(capture) = &count_options.capture {
+        let group = if capture.captures_len() > 1 { 1 } else { 0 };
+        // 可选的捕获组没有参与匹配时同样不统计
+        Cow::Borrowed(trim(capture.captures(line)?.get(group)?.as_str()))
+    } else {
+        match count_options.column.as_slice() {
+            [] => Cow::Borrowed(trim(line)),
+            [column] if count_options.strip_quotes => {
+                let field = split_quoted(line, delimiter).get(*column).copied();
+                field.map_or_else(missing, |field| unquote(field, trim_fields))
+            }
+            columns if count_options.strip_quotes => {
+                let fields = split_quoted(line, delimiter);
+                let selected: Vec<_> = columns
+                    .iter()
+                    .map(|column| {
+                        let field = fields.get(*column).copied();
+                        field.map_or_else(missing, |field| unquote(field, trim_fields))
+                    })
+                    .collect();
+                Cow::Owned(selected.join(delimiter))
+            }
+            [column] => {
+                let field = line.split(delimiter).nth(*column);
+                field.map_or_else(missing, |field| Cow::Borrowed(trim(field)))
+            }
+            columns => {
+                let fields: Vec<_> = line.split(delimiter).collect();
+                let selected: Vec<_> = columns
+                    .iter()
+                    .map(|column| {
+                        let field = fields.get(*column).copied();
+                        field.map_or_else(missing, |field| Cow::Borrowed(trim(field)))
+                    })
+                    .collect();
+                Cow::Owned(selected.join(delimiter))
+            }
+        }
+    };
+    Some(line)
+}
+
+// 按分隔符拆分一行，双引号包裹的部分中的分隔符不拆分，字段保留原有的引号
+fn split_quoted<'a>(line: &'a str, delimiter: &str) -> Vec<&'a str> {
+    let (bytes, delimiter) = (line.as_bytes(), delimiter.as_bytes());
+    let mut fields = Vec::new();
+    let (mut start, mut pos) = (0, 0);
+    let mut quoted = false;
+    while pos < bytes.len() {
+        if bytes[pos] == b'"' {
+            quoted = !quoted;
+            pos += 1;
+        } else if !quoted && !delimiter.is_empty() && bytes[pos..].starts_with(delimiter) {
+            // 分隔符本身是合法的 UTF-8，匹配位置一定在字符边界上
+            fields.push(&line[start..pos]);
+            pos += delimiter.len();
+            start = pos;
+        } else {
+            pos += 1;
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
+// 去掉字段外层的双引号并把 "" 还原为 "，没有引号包裹的字段原样返回；
+// trim 为 true 时去掉引号外和引号内的首尾空白
+fn unquote(field: &str, trim: bool) -> Cow<'_, str> {
+    let field = if trim { field.trim() } else { field };
+    let Some(inner) = field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+    else {
+        return Cow::Borrowed(field);
+    };
+    let inner = if trim { inner.trim() } else { inner };
+    if inner.contains("\"\"") {
+        Cow::Owned(inner.replace("\"\"", "\""))
+    } else {
+        Cow::Borrowed(inner)
+    }
+}
+
+// 对选出的内容做 Unicode 规范化、合并空白、大小写转换
+fn normalize_line<'a>(line: Cow<'a, str>, count_options: &CountOptions) -> Cow<'a, str> {
+    // 已经是 NFC 形式的行（绝大多数情况）不需要重新分配
+    let line = if count_options.normalize_unicode && !is_nfc(&line) {
+        Cow::Owned(line.nfc().collect())
+    } else {
+        line
+    };
+    let line = if count_options.collapse_whitespace {
+        collapse_whitespace(line)
+    } else {
+        line
+    };
+    if count_options.to_lowercase {
+        Cow::Owned(line.to_lowercase())
+    } else if count_options.to_uppercase {
+        Cow::Owned(line.to_uppercase())
+    } else {
+        line
+    }
+}
+
+// 由处理后的内容得到统计用的 key，以及与 key 不同时的原始写法
+fn line_key<'a>(
+    line: Cow<'a, str>,
+    count_options: &CountOptions,
+) -> (Cow<'a, str>, Option<Cow<'a, str>>) {
+    // 开启 hash_keys 时统计阶段再计算摘要，不保留原文
+    if count_options.ignore_case {
+        let key = line.to_lowercase();
+        if key == line || count_options.hash_keys {
+            (Cow::Owned(key), None)
+        } else {
+            (Cow::Owned(key), Some(line))
+        }
+    } else {
+        (line, None)
+    }
+}
+
+// 把忽略列表或预期 key 列表中的一行按统计时相同的方式（去空白、大小写等）处理为 key，
+// 同时返回与 key 不同时的原始写法；不做列选择和过滤
+pub(crate) fn list_line_key(line: &str, count_options: &CountOptions) -> (String, Option<String>) {
+    let line = if count_options.trim {
+        line.trim()
+    } else {
+        line
+    };
+    let line = normalize_line(Cow::Borrowed(line), count_options);
+    let (key, original) = line_key(line, count_options);
+    (key.into_owned(), original.map(Cow::into_owned))
+}
+
+// 把连续的空白字符替换为一个空格并去掉首尾空白，内容不变时不重新分配
+fn collapse_whitespace(line: Cow<'_, str>) -> Cow<'_, str> {
+    let collapsed = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed == line {
+        line
+    } else {
+        Cow::Owned(collapsed)
+    }
+}
+
+// 把 src 合并进 dst，src 中的行号加上 line_offset
+fn merge_counts<K: Eq + Hash>(
+    dst: &mut FxHashMap<K, LineCount>,
+    src: FxHashMap<K, LineCount>,
+    line_offset: usize,
+) {
+    for (key, mut line_count) in src {
+        line_count.first_seen += line_offset;
+        for position in &mut line_count.positions {
+            *position += line_offset;
+        }
+        merge_line_count(dst, key, line_count);
+    }
+}
+
+/// 把 src 的统计结果累加到 dst 中，相同 key 保留首次出现行号更早的原始写法
+pub fn merge_count_maps(dst: &mut CountMap, src: CountMap) {
+    merge_counts(dst, src, 0);
+}
+
+// 将一条统计合并进 map，key 已存在时累加
+pub(crate) fn merge_line_count<K: Eq + Hash>(
+    data_count: &mut FxHashMap<K, LineCount>,
+    key: K,
+    line_count: LineCount,
+) {
+    match data_count.entry(key) {
+        Entry::Occupied(mut entry) => entry.get_mut().merge(line_count),
+        Entry::Vacant(entry) => {
+            entry.insert(line_count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFile;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // 在当前线程中统计 input，返回每个 key 的出现次数
+    fn counts(input: &str, count_options: &CountOptions) -> Vec<(String, usize)> {
+        let data_count = count_occurrences(input.as_bytes(), count_options).unwrap();
+        sorted_counts(data_count)
+    }
+
+    fn sorted_counts(data_count: CountMap) -> Vec<(String, usize)> {
+        let mut counts: Vec<_> = data_count
+            .into_iter()
+            .map(|(key, line_count)| (key, line_count.count))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn hash_keys_count_digests_without_text() {
+        let count_options = CountOptions {
+            hash_keys: true,
+            ..CountOptions::default()
+        };
+        let input = "a long line\nanother line\na long line\n";
+        let mut expected = vec![
+            (digest_hex(xxh3_128(b"a long line")), 2),
+            (digest_hex(xxh3_128(b"another line")), 1),
+        ];
+        expected.sort();
+        assert_eq!(counts(input, &count_options), expected);
+    }
+
+    #[test]
+    fn hash_keys_keep_digests_while_counting() {
+        let count_options = CountOptions {
+            hash_keys: true,
+            ignore_case: true,
+            ..CountOptions::default()
+        };
+        let mut counter = Counter::default();
+        for (line_no, line) in ["Abc", "abc", "ABC", "d"].into_iter().enumerate() {
+            count_line(&mut counter, line_no + 1, line, &count_options);
+        }
+        let (counts, _) = counter.finish(4);
+        // 统计过程中不保存任何字符串 key，忽略大小写后才计算摘要
+        assert!(counts.text.is_empty());
+        assert_eq!(counts.digests[&xxh3_128(b"abc")].count, 3);
+        assert_eq!(counts.digests[&xxh3_128(b"d")].count, 1);
+    }
+
+    // 用线性同余生成器产生 lines 行可重复的伪随机输入，每行从 distinct 个不同的值中选取
+    fn pseudo_random_lines(lines: usize, distinct: u64) -> String {
+        let mut state: u64 = 7;
+        let mut input = String::new();
+        for _ in 0..lines {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            input.push_str(&format!("line{}\n", (state >> 33) % distinct));
+        }
+        input
+    }
+
+    #[test]
+    fn every_worker_thread_receives_lines() {
+        let (data_sender, data_receiver) = std::sync::mpsc::sync_channel(16);
+        let data_receiver = Arc::new(Mutex::new(data_receiver));
+        let count_options = CountOptions::default();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let data_receiver = data_receiver.clone();
+                let count_options = count_options.clone();
+                thread::spawn(move || count_data(Counter::default(), data_receiver, &count_options))
+            })
+            .collect();
+
+        let lines = 200_000;
+        for line_no in 0..lines {
+            data_sender
+                .send((line_no + 1, format!("line{}", line_no % 100)))
+                .unwrap();
+        }
+        drop(data_sender);
+
+        let mut total = 0;
+        for handle in handles {
+            let (local_count, stats) = handle.join().unwrap();
+            let processed = stats.lines;
+            // 每个线程都应分到数据，而不是被第一个线程独占
+            assert!(processed > 0);
+            let counted: usize = local_count
+                .text
+                .values()
+                .map(|line_count| line_count.count)
+                .sum();
+            assert_eq!(counted, processed);
+            total += processed;
+        }
+        assert_eq!(total, lines);
+    }
+
+    #[test]
+    fn ignore_case_merges_mixed_case_duplicates() {
+        let count_options = CountOptions {
+            ignore_case: true,
+            ..CountOptions::default()
+        };
+        let data_count =
+            count_occurrences("Foo\nFOO\nfoo\nfOo\n".as_bytes(), &count_options).unwrap();
+        assert_eq!(data_count.len(), 1);
+        assert_eq!(data_count["foo"].count, 4);
+        // 输出保留首次出现的原始写法
+        assert_eq!(data_count["foo"].original.as_deref(), Some("Foo"));
+    }
+
+    #[test]
+    fn trim_merges_carriage_return_and_spaces() {
+        let count_options = CountOptions {
+            trim: true,
+            ..CountOptions::default()
+        };
+        let input = "foo\r\r\nfoo\n  foo\t\n";
+        assert_eq!(counts(input, &count_options), [(String::from("foo"), 3)]);
+        // 不去掉空白时只有换行符被去掉
+        assert_eq!(counts(input, &CountOptions::default()).len(), 3);
+    }
+
+    #[test]
+    fn column_counts_one_field() {
+        let count_options = CountOptions {
+            column: vec![1],
+            ..CountOptions::default()
+        };
+        let input = "1,red,x\n2,blue,y\n3,red,z\n4,,w\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::new(), 1),
+                (String::from("blue"), 1),
+                (String::from("red"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn gzip_input_counts_match_plain_input() {
+        let input = pseudo_random_lines(50_000, 300);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let plain = TestFile::new("corpus.txt", &input);
+        // 不以 .gz 结尾，按魔数识别
+        let compressed = TestFile::new("corpus.bin", encoder.finish().unwrap());
+        let count_options = CountOptions::default();
+        let pb = ProgressBar::hidden();
+        let (expected, _) =
+            count_file(&[plain.path().to_string()], 4, &count_options, &pb).unwrap();
+        let (data_count, _) =
+            count_file(&[compressed.path().to_string()], 4, &count_options, &pb).unwrap();
+        assert_eq!(sorted_counts(data_count), sorted_counts(expected));
+    }
+
+    #[test]
+    fn skip_empty_drops_blank_lines() {
+        let count_options = CountOptions {
+            skip_empty: true,
+            ..CountOptions::default()
+        };
+        let input = "a\n\nb\n\r\n\na\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("a"), 2), (String::from("b"), 1)]
+        );
+        // 默认仍统计空行
+        assert!(counts(input, &CountOptions::default()).contains(&(String::new(), 3)));
+    }
+
+    #[test]
+    fn skip_header_does_not_count_the_first_line() {
+        let count_options = CountOptions {
+            skip_header: true,
+            ..CountOptions::default()
+        };
+        let input = "name\nbob\nname\nalice\n";
+        // 只跳过第一行，之后与表头相同的行照常统计
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("alice"), 1),
+                (String::from("bob"), 1),
+                (String::from("name"), 1),
+            ]
+        );
+        let file = TestFile::new("header.txt", input);
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let (data_count, stats) = count_file_with_checkpoint(
+                &[file.path().to_string()],
+                2,
+                use_mmap,
+                &count_options,
+                &pb,
+                None,
+            )
+            .unwrap();
+            assert_eq!(data_count["name"].count, 1);
+            assert_eq!(stats.lines, 4);
+        }
+    }
+
+    #[test]
+    fn include_and_exclude_filter_lines() {
+        let input = "GET /a\nPOST /a\nGET /b\nGET /a\nDELETE /b\n";
+        let include = CountOptions {
+            include: Some(Regex::new("^GET").unwrap()),
+            ..CountOptions::default()
+        };
+        assert_eq!(
+            counts(input, &include),
+            [(String::from("GET /a"), 2), (String::from("GET /b"), 1)]
+        );
+        let exclude = CountOptions {
+            exclude: Some(Regex::new("/a$").unwrap()),
+            ..CountOptions::default()
+        };
+        assert_eq!(
+            counts(input, &exclude),
+            [(String::from("DELETE /b"), 1), (String::from("GET /b"), 1)]
+        );
+        // 同时指定时先要求匹配 include，再去掉匹配 exclude 的行
+        let both = CountOptions {
+            include: include.include.clone(),
+            exclude: exclude.exclude.clone(),
+            ..CountOptions::default()
+        };
+        assert_eq!(counts(input, &both), [(String::from("GET /b"), 1)]);
+    }
+
+    #[test]
+    fn mmap_counts_match_buffered_reading() {
+        let count_options = CountOptions {
+            skip_header: true,
+            trim: true,
+            ..CountOptions::default()
+        };
+        // 包含 \r\n 换行及末尾没有换行符的最后一行
+        let input = format!("{}a\r\nlast", pseudo_random_lines(20_000, 300));
+        let file = TestFile::new("mmap.txt", &input);
+        let pb = ProgressBar::hidden();
+        let (expected, _) = count_file(&[file.path().to_string()], 2, &count_options, &pb).unwrap();
+        let (data_count, _) =
+            count_file_mmap(&[file.path().to_string()], 2, &count_options, &pb).unwrap();
+        assert_eq!(sorted_counts(data_count), sorted_counts(expected));
+    }
+
+    #[test]
+    fn counts_are_added_across_files() {
+        let first = TestFile::new("first.txt", "shared\nonly-first\nshared\n");
+        let second = TestFile::new("second.txt", "shared\nonly-second\n");
+        let paths = [first.path().to_string(), second.path().to_string()];
+        let pb = ProgressBar::hidden();
+        let (data_count, stats) = count_file(&paths, 2, &CountOptions::default(), &pb).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [
+                (String::from("only-first"), 1),
+                (String::from("only-second"), 1),
+                (String::from("shared"), 3),
+            ]
+        );
+        assert_eq!(stats.lines, 5);
+    }
+
+    #[test]
+    fn gbk_input_is_decoded() {
+        let (encoded, _, _) = encoding_rs::GBK.encode("中文\n测试\n中文\n");
+        let file = TestFile::new("gbk.txt", encoded);
+        let count_options = CountOptions {
+            encoding: crate::parse_encoding("gbk").unwrap(),
+            ..CountOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let (data_count, _) = count_file_with_checkpoint(
+                &[file.path().to_string()],
+                2,
+                use_mmap,
+                &count_options,
+                &pb,
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                sorted_counts(data_count),
+                [(String::from("中文"), 2), (String::from("测试"), 1)]
+            );
+        }
+    }
+
+    #[test]
+    fn bom_is_stripped_from_the_first_line() {
+        let file = TestFile::new("bom.txt", "\u{feff}id\nx\nid\n");
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let (data_count, _) = count_file_with_checkpoint(
+                &[file.path().to_string()],
+                2,
+                use_mmap,
+                &CountOptions::default(),
+                &pb,
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                sorted_counts(data_count),
+                [(String::from("id"), 2), (String::from("x"), 1)]
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_counts_match_a_single_thread() {
+        // 超过切块的最小字节数，会被切成多块并行统计
+        let input = pseudo_random_lines(300_000, 5000);
+        assert!(input.len() as u64 > 2 * crate::chunk::MIN_CHUNK_SIZE);
+        let file = TestFile::new("chunks.txt", &input);
+        let paths = [file.path().to_string()];
+        let count_options = CountOptions::default();
+        let pb = ProgressBar::hidden();
+        let first_seen = |data_count: CountMap| {
+            let mut rows: Vec<_> = data_count
+                .into_iter()
+                .map(|(key, line_count)| (key, line_count.count, line_count.first_seen))
+                .collect();
+            rows.sort();
+            rows
+        };
+        let expected = first_seen(count_file(&paths, 1, &count_options, &pb).unwrap().0);
+        for concurrency in [2, 4, 8] {
+            let (data_count, _) = count_file(&paths, concurrency, &count_options, &pb).unwrap();
+            assert_eq!(first_seen(data_count), expected);
+            let (data_count, _) =
+                count_file_mmap(&paths, concurrency, &count_options, &pb).unwrap();
+            assert_eq!(first_seen(data_count), expected);
+        }
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let count_options = CountOptions {
+            max_line_len: Some(4),
+            ..CountOptions::default()
+        };
+        let long = "x".repeat(100_000);
+        let input = format!("abcdef\nabcd\n{long}\nab\n");
+        assert_eq!(
+            counts(&input, &count_options),
+            [
+                (String::from("ab"), 1),
+                (String::from("abcd"), 2),
+                (String::from("xxxx"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn comment_lines_are_excluded() {
+        let count_options = CountOptions {
+            comment: Some(String::from("#")),
+            ..CountOptions::default()
+        };
+        let input = "# header\na\n#a\na # not a comment\na\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("a"), 2),
+                (String::from("a # not a comment"), 1)
+            ]
+        );
+        // 空前缀表示不过滤
+        let count_options = CountOptions {
+            comment: Some(String::new()),
+            ..CountOptions::default()
+        };
+        assert_eq!(counts(input, &count_options).len(), 4);
+    }
+
+    #[test]
+    fn crlf_and_lf_lines_merge() {
+        let input = "same\r\nsame\nother\r\nsame";
+        assert_eq!(
+            counts(input, &CountOptions::default()),
+            [(String::from("other"), 1), (String::from("same"), 3)]
+        );
+        let file = TestFile::new("crlf.txt", input);
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let (data_count, _) = count_file_with_checkpoint(
+                &[file.path().to_string()],
+                2,
+                use_mmap,
+                &CountOptions::default(),
+                &pb,
+                None,
+            )
+            .unwrap();
+            assert_eq!(data_count["same"].count, 3);
+        }
+    }
+
+    #[test]
+    fn case_transforms_change_the_key() {
+        let input = "Foo\nFOO\nbar\n";
+        let lower = CountOptions {
+            to_lowercase: true,
+            ..CountOptions::default()
+        };
+        assert_eq!(
+            counts(input, &lower),
+            [(String::from("bar"), 1), (String::from("foo"), 2)]
+        );
+        let upper = CountOptions {
+            to_uppercase: true,
+            ..CountOptions::default()
+        };
+        assert_eq!(
+            counts(input, &upper),
+            [(String::from("BAR"), 1), (String::from("FOO"), 2)]
+        );
+    }
+
+    #[test]
+    fn multiple_columns_form_one_key() {
+        let count_options = CountOptions {
+            column: vec![0, 2],
+            ..CountOptions::default()
+        };
+        let input = "us,a,web\nus,b,web\nde,c,web\nus,d,app\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("de,web"), 1),
+                (String::from("us,app"), 1),
+                (String::from("us,web"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn nul_separated_records() {
+        let count_options = CountOptions {
+            null_data: true,
+            ..CountOptions::default()
+        };
+        let input = "a\0b\0a\0";
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("a"), 2), (String::from("b"), 1)]
+        );
+        let file = TestFile::new("records.bin", "a\0with\nnewline\0a\0");
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let (data_count, _) = count_file_with_checkpoint(
+                &[file.path().to_string()],
+                2,
+                use_mmap,
+                &count_options,
+                &pb,
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                sorted_counts(data_count),
+                [(String::from("a"), 2), (String::from("with\nnewline"), 1)]
+            );
+        }
+    }
+
+    #[test]
+    fn sample_with_seed_is_deterministic() {
+        let input = pseudo_random_lines(20_000, 1000);
+        let count_options = CountOptions {
+            sample: Some(10),
+            seed: 42,
+            ..CountOptions::default()
+        };
+        let expected = counts(&input, &CountOptions::default());
+        let first = counts(&input, &count_options);
+        assert_eq!(first.len(), 10);
+        // 抽中的行的次数是准确的
+        for line in &first {
+            assert!(expected.contains(line), "{line:?}");
+        }
+        let file = TestFile::new("sample.txt", &input);
+        let pb = ProgressBar::hidden();
+        for concurrency in [1, 4] {
+            let (data_count, _) =
+                count_file_mmap(&[file.path().to_string()], concurrency, &count_options, &pb)
+                    .unwrap();
+            assert_eq!(sorted_counts(data_count), first);
+        }
+    }
+
+    #[test]
+    fn collapse_whitespace_merges_spacing_variants() {
+        let count_options = CountOptions {
+            collapse_whitespace: true,
+            ..CountOptions::default()
+        };
+        let input = "a  b\t c\n a b c \na b c\n";
+        assert_eq!(counts(input, &count_options), [(String::from("a b c"), 3)]);
+    }
+
+    #[test]
+    fn length_bounds_filter_keys() {
+        let count_options = CountOptions {
+            min_length: Some(2),
+            max_length: Some(3),
+            ..CountOptions::default()
+        };
+        // 按字符而不是字节计算长度
+        let input = "a\nab\nabc\nabcd\n中文\n中文字符\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("ab"), 1),
+                (String::from("abc"), 1),
+                (String::from("中文"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn nfc_normalization_merges_accents() {
+        let count_options = CountOptions {
+            normalize_unicode: true,
+            ..CountOptions::default()
+        };
+        let input = "caf\u{e9}\ncafe\u{301}\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("caf\u{e9}"), 2)]
+        );
+        assert_eq!(counts(input, &CountOptions::default()).len(), 2);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced_in_lossy_mode() {
+        let input: &[u8] = b"ok\n\xff\xfe\nok\n";
+        let count_options = CountOptions {
+            lossy: true,
+            ..CountOptions::default()
+        };
+        let data_count = count_occurrences(input, &count_options).unwrap();
+        assert_eq!(data_count["ok"].count, 2);
+        assert_eq!(data_count["\u{fffd}\u{fffd}"].count, 1);
+        let err = count_occurrences(input, &CountOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let file = TestFile::new("invalid.txt", input);
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let (data_count, _) = count_file_with_checkpoint(
+                &[file.path().to_string()],
+                2,
+                use_mmap,
+                &count_options,
+                &pb,
+                None,
+            )
+            .unwrap();
+            assert_eq!(data_count.len(), 2);
+        }
+    }
+
+    #[test]
+    fn ragged_rows_are_reported() {
+        let count_options = CountOptions {
+            expected_columns: Some(3),
+            ..CountOptions::default()
+        };
+        let input = "a,b,c\na,b\na,b,c,d\nx,y,z\n\n";
+        let file = TestFile::new("ragged.csv", input);
+        let pb = ProgressBar::hidden();
+        let (data_count, stats) =
+            count_file(&[file.path().to_string()], 2, &count_options, &pb).unwrap();
+        assert_eq!(stats.mismatched_columns, 3);
+        // 不一致的行默认照常统计
+        assert_eq!(data_count.len(), 5);
+        let skipping = CountOptions {
+            skip_mismatched: true,
+            ..count_options
+        };
+        let (data_count, stats) =
+            count_file(&[file.path().to_string()], 2, &skipping, &pb).unwrap();
+        assert_eq!(stats.mismatched_columns, 3);
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("a,b,c"), 1), (String::from("x,y,z"), 1)]
+        );
+    }
+
+    #[test]
+    fn zip_archive_is_counted() {
+        let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        archive.start_file("data.txt", options).unwrap();
+        archive.write_all(b"a\nb\na\n").unwrap();
+        archive.start_file("other.txt", options).unwrap();
+        archive.write_all(b"c\n").unwrap();
+        let file = TestFile::new("archive.zip", archive.finish().unwrap().into_inner());
+        let paths = [file.path().to_string()];
+        let pb = ProgressBar::hidden();
+        let count_options = CountOptions {
+            zip_entry: Some(String::from("data.txt")),
+            ..CountOptions::default()
+        };
+        let (data_count, _) = count_file(&paths, 2, &count_options, &pb).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("a"), 2), (String::from("b"), 1)]
+        );
+        // 有多个条目却没有指定读取哪一个
+        assert!(count_file(&paths, 2, &CountOptions::default(), &pb).is_err());
+    }
+
+    #[test]
+    fn repeats_only_memory_keeps_exact_repeat_counts() {
+        let mut input = pseudo_random_lines(100_000, 50_000);
+        input.push_str("twice\nsingle\ntwice\n");
+        let expected: Vec<_> = counts(&input, &CountOptions::default())
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .collect();
+        let count_options = CountOptions {
+            repeats_only_memory: true,
+            ..CountOptions::default()
+        };
+        let file = TestFile::new("repeats.txt", &input);
+        let paths = [file.path().to_string()];
+        let pb = ProgressBar::hidden();
+        let (data_count, _) = count_file(&paths, 4, &count_options, &pb).unwrap();
+        assert_eq!(sorted_counts(data_count), expected);
+        let (data_count, _) = count_file_mmap(&paths, 4, &count_options, &pb).unwrap();
+        assert!(data_count.contains_key("twice") && !data_count.contains_key("single"));
+        assert_eq!(sorted_counts(data_count), expected);
+    }
+
+    #[test]
+    fn capture_group_becomes_the_key() {
+        let count_options = CountOptions {
+            capture: Some(Regex::new(r#"" (\d{3}) "#).unwrap()),
+            ..CountOptions::default()
+        };
+        let input = concat!(
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /a HTTP/1.0\" 200 2326\n",
+            "127.0.0.1 - - [10/Oct/2000:13:55:37 -0700] \"GET /b HTTP/1.0\" 404 512\n",
+            "127.0.0.1 - - [10/Oct/2000:13:55:38 -0700] \"GET /a HTTP/1.0\" 200 2326\n",
+            "not a log line\n",
+        );
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("200"), 2), (String::from("404"), 1)]
+        );
+    }
+
+    #[test]
+    fn column_name_selects_by_header() {
+        let first = TestFile::new("named-1.csv", "id,status,user\n1,ok,a\n2,fail,b\n");
+        // 每个文件分别查找列名，列的位置可以不同
+        let second = TestFile::new("named-2.csv", "status,id\nok,3\n");
+        let count_options = CountOptions {
+            column_name: vec![String::from("status")],
+            ..CountOptions::default()
+        };
+        let paths = [first.path().to_string(), second.path().to_string()];
+        let pb = ProgressBar::hidden();
+        let (data_count, _) = count_file(&paths, 2, &count_options, &pb).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("fail"), 1), (String::from("ok"), 2)]
+        );
+        let missing = CountOptions {
+            column_name: vec![String::from("missing")],
+            ..CountOptions::default()
+        };
+        assert!(count_file(&paths, 2, &missing, &pb).is_err());
+    }
+
+    #[test]
+    fn checkpoint_partway_through_a_file_resumes_to_the_full_count() {
+        let first = TestFile::new("part-1.txt", pseudo_random_lines(500, 50));
+        let second = TestFile::new("part-2.txt", pseudo_random_lines(1000, 50));
+        let paths = [first.path().to_string(), second.path().to_string()];
+        let saved_file = TestFile::empty("checkpoint.csv");
+        let pb = ProgressBar::hidden();
+        let (expected, _) = count_file(&paths, 4, &CountOptions::default(), &pb).unwrap();
+        let mut saved = Vec::new();
+        let err = count_file_with_checkpoint(
+            &paths,
+            4,
+            false,
+            &CountOptions::default(),
+            &pb,
+            Some((300, &mut |checkpoint: &Checkpoint| {
+                crate::write_checkpoint(saved_file.path(), checkpoint)?;
+                saved.push((checkpoint.file_path.to_string(), checkpoint.lines));
+                // 模拟在第二个文件统计到一半时崩溃
+                if saved.len() == 3 {
+                    return Err(Error::Worker);
+                }
+                Ok(())
+            })),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Worker));
+        assert_eq!(
+            saved,
+            [
+                (paths[0].clone(), 300),
+                (paths[1].clone(), 300),
+                (paths[1].clone(), 600),
+            ]
+        );
+
+        // 从检查点继续，只统计第二个文件中剩下的行
+        let position = crate::read_checkpoint_position(saved_file.path()).unwrap();
+        assert_eq!(position, Some((paths[1].clone(), 600)));
+        let count_options = CountOptions {
+            resume: position,
+            ..CountOptions::default()
+        };
+        let mut resumed = crate::load_counts(saved_file.path(), &count_options).unwrap();
+        let (rest, stats) = count_file(&paths[1..], 4, &count_options, &pb).unwrap();
+        assert_eq!(stats.lines, 1000);
+        merge_count_maps(&mut resumed, rest);
+        assert_eq!(sorted_counts(resumed), sorted_counts(expected));
+    }
+
+    #[test]
+    fn invert_counts_non_matching_lines() {
+        let count_options = CountOptions {
+            include: Some(Regex::new("^#").unwrap()),
+            invert: true,
+            ..CountOptions::default()
+        };
+        let input = "# comment\na\n#x\nb\na\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("a"), 2), (String::from("b"), 1)]
+        );
+        // 没有 include 时不起作用
+        let count_options = CountOptions {
+            invert: true,
+            ..CountOptions::default()
+        };
+        assert_eq!(counts(input, &count_options).len(), 4);
+    }
+
+    #[test]
+    fn field_range_slices_fixed_width_records() {
+        let count_options = CountOptions {
+            field_range: Some(parse_field_range("5..8").unwrap()),
+            ..CountOptions::default()
+        };
+        let input = "0001 NYC 2020\n0002 LAX 2021\n0003 NYC 2022\n0004 SF\n0005\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("LAX"), 1),
+                (String::from("NYC"), 2),
+                (String::from("SF"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn words_mode_counts_words() {
+        let count_options = CountOptions {
+            mode: CountMode::Words,
+            ..CountOptions::default()
+        };
+        let input = "Hello, world! Hello again.\nThe world is big.\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("Hello"), 2),
+                (String::from("The"), 1),
+                (String::from("again"), 1),
+                (String::from("big"), 1),
+                (String::from("is"), 1),
+                (String::from("world"), 2),
+            ]
+        );
+        let count_options = CountOptions {
+            ignore_case: true,
+            ..count_options
+        };
+        assert!(counts("The the THE\n", &count_options)
+            .iter()
+            .all(|(_, count)| *count == 3));
+    }
+
+    // 在本地启动只响应一次请求的 HTTP 服务器，返回其地址
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = std::io::Read::read(&mut stream, &mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{address}/data.txt")
+    }
+
+    #[test]
+    fn http_input_is_counted() {
+        let url = serve_once("200 OK", "a\nb\na\n");
+        let pb = ProgressBar::hidden();
+        let (data_count, stats) = count_file(&[url], 2, &CountOptions::default(), &pb).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("a"), 2), (String::from("b"), 1)]
+        );
+        assert_eq!(stats.lines, 3);
+        // 服务器返回错误时报错而不是 panic
+        let url = serve_once("404 Not Found", "");
+        assert!(matches!(
+            count_file(&[url], 2, &CountOptions::default(), &pb),
+            Err(Error::Open { .. })
+        ));
+    }
+
+    // 每次读取只返回一行，读完 limit 行后置位中断标志
+    struct InterruptingReader {
+        lines_read: usize,
+        limit: usize,
+        interrupted: Arc<AtomicBool>,
+    }
+
+    impl std::io::Read for InterruptingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.lines_read == self.limit {
+                self.interrupted
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+            self.lines_read += 1;
+            let line = b"line\n";
+            buf[..line.len()].copy_from_slice(line);
+            Ok(line.len())
+        }
+    }
+
+    #[test]
+    fn interrupt_stops_counting_with_partial_results() {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let count_options = CountOptions {
+            interrupted: Some(interrupted.clone()),
+            ..CountOptions::default()
+        };
+        // 不中断时这个 reader 永远读不完
+        let reader = InterruptingReader {
+            lines_read: 0,
+            limit: 100,
+            interrupted,
+        };
+        let reader = std::io::BufReader::with_capacity(8, reader);
+        let data_count = count_occurrences(reader, &count_options).unwrap();
+        let count = data_count["line"].count;
+        assert!((100..=101).contains(&count), "{count}");
+    }
+
+    #[test]
+    fn positions_of_repeated_lines() {
+        let count_options = CountOptions {
+            positions: Some(3),
+            ..CountOptions::default()
+        };
+        let input = "a\nb\na\na\nc\na\nb\n";
+        let data_count = count_occurrences(input.as_bytes(), &count_options).unwrap();
+        assert_eq!(data_count["a"].positions, [1, 3, 4]);
+        assert_eq!(data_count["b"].positions, [2, 7]);
+        assert_eq!(data_count["c"].positions, [5]);
+        // 多线程统计时合并后仍是最早的几处
+        let file = TestFile::new("positions.txt", input);
+        let pb = ProgressBar::hidden();
+        let (data_count, _) =
+            count_file(&[file.path().to_string()], 4, &count_options, &pb).unwrap();
+        assert_eq!(data_count["a"].positions, [1, 3, 4]);
+        let data_count = count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+        assert!(data_count["a"].positions.is_empty());
+    }
+
+    #[test]
+    fn tab_input_delimiter() {
+        let count_options = CountOptions {
+            column: vec![1],
+            input_delimiter: crate::parse_delimiter("\\t").unwrap(),
+            ..CountOptions::default()
+        };
+        assert_eq!(count_options.input_delimiter, "\t");
+        let input = "1\tred\ta,b\n2\tblue\tc\n3\tred\td\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("blue"), 1), (String::from("red"), 2)]
+        );
+    }
+
+    // 按输入顺序保留最先出现的 max 个不同的行，其余计入 OTHER_KEY
+    fn capped_counts(input: &str, max: usize) -> Vec<(String, usize)> {
+        let mut counts: FxHashMap<String, usize> = FxHashMap::default();
+        for line in input.lines() {
+            let line = if counts.contains_key(line) || counts.len() < max {
+                line
+            } else {
+                OTHER_KEY
+            };
+            *counts.entry(line.to_string()).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn max_distinct_counts_new_keys_into_other() {
+        let count_options = CountOptions {
+            max_distinct: Some(2),
+            ..CountOptions::default()
+        };
+        let input = "a\nb\nc\na\nd\nb\nc\n";
+        let expected = vec![
+            (String::from("<OTHER>"), 3),
+            (String::from("a"), 2),
+            (String::from("b"), 2),
+        ];
+        assert_eq!(counts(input, &count_options), expected);
+    }
+
+    #[test]
+    fn max_distinct_is_exact_across_chunks_and_files() {
+        // 超过切块的最小字节数，不限制不同 key 的个数时会被切成多块并行统计
+        let input = pseudo_random_lines(300_000, 5000);
+        assert!(input.len() as u64 > 2 * crate::chunk::MIN_CHUNK_SIZE);
+        let expected = capped_counts(&input, 10);
+        let count_options = CountOptions {
+            max_distinct: Some(10),
+            ..CountOptions::default()
+        };
+        let file = TestFile::new("max-distinct.txt", &input);
+        let paths = [file.path().to_string()];
+        let pb = ProgressBar::hidden();
+        for concurrency in [1, 4] {
+            let (data_count, _) = count_file(&paths, concurrency, &count_options, &pb).unwrap();
+            assert_eq!(sorted_counts(data_count), expected);
+            let (data_count, _) =
+                count_file_mmap(&paths, concurrency, &count_options, &pb).unwrap();
+            assert_eq!(sorted_counts(data_count), expected);
+        }
+
+        // 分成两个文件时，第一个文件中保留的 key 在第二个文件中照常累加
+        let middle = input[..input.len() / 2].rfind('\n').unwrap() + 1;
+        let first = TestFile::new("max-distinct-1.txt", &input[..middle]);
+        let second = TestFile::new("max-distinct-2.txt", &input[middle..]);
+        let paths = [first.path().to_string(), second.path().to_string()];
+        let (data_count, _) = count_file_mmap(&paths, 4, &count_options, &pb).unwrap();
+        assert_eq!(sorted_counts(data_count), expected);
+    }
+
+    #[test]
+    fn explode_counts_every_token() {
+        let count_options = CountOptions {
+            explode: Some(String::from(";")),
+            trim: true,
+            ..CountOptions::default()
+        };
+        let input = "red; green ;blue\nred;;\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("blue"), 1),
+                (String::from("green"), 1),
+                (String::from("red"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn bigrams_count_adjacent_pairs() {
+        let count_options = CountOptions {
+            mode: CountMode::Bigrams,
+            ..CountOptions::default()
+        };
+        let pair = |a: &str, b: &str| format!("{a}{BIGRAM_SEPARATOR}{b}");
+        assert_eq!(
+            counts("a\nb\na\nb\nc\n", &count_options),
+            [
+                (pair("a", "b"), 2),
+                (pair("b", "a"), 1),
+                (pair("b", "c"), 1)
+            ]
+        );
+        // 行对不跨越文件
+        let first = TestFile::new("bigrams-1.txt", "a\nb\n");
+        let second = TestFile::new("bigrams-2.txt", "c\nd\n");
+        let paths = [first.path().to_string(), second.path().to_string()];
+        let pb = ProgressBar::hidden();
+        let (data_count, _) = count_file_mmap(&paths, 4, &count_options, &pb).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(pair("a", "b"), 1), (pair("c", "d"), 1)]
+        );
+    }
+
+    #[test]
+    fn tiny_channel_capacity_completes() {
+        let input = pseudo_random_lines(20_000, 100);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        // gzip 输入由一个线程读取后经 channel 分发
+        let file = TestFile::new("backpressure.txt.gz", encoder.finish().unwrap());
+        let count_options = CountOptions {
+            channel_capacity: 1,
+            ..CountOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        let (data_count, stats) =
+            count_file(&[file.path().to_string()], 4, &count_options, &pb).unwrap();
+        assert_eq!(stats.lines, 20_000);
+        assert_eq!(
+            sorted_counts(data_count),
+            counts(&input, &CountOptions::default())
+        );
+    }
+
+    #[test]
+    fn weight_column_sums_per_key() {
+        let count_options = CountOptions {
+            column: vec![0],
+            weight_column: Some(1),
+            ..CountOptions::default()
+        };
+        let input = "a,3\nb,1\na,2\nb,x\nc\n";
+        let data_count = count_occurrences(input.as_bytes(), &count_options).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("a"), 5), (String::from("b"), 1)]
+        );
+        let file = TestFile::new("weights.csv", input);
+        let pb = ProgressBar::hidden();
+        let (_, stats) = count_file(&[file.path().to_string()], 2, &count_options, &pb).unwrap();
+        assert_eq!(stats.invalid_weights, 2);
+    }
+
+    #[test]
+    fn skip_and_limit_select_a_line_range() {
+        let count_options = CountOptions {
+            skip: 2,
+            limit: Some(3),
+            ..CountOptions::default()
+        };
+        let input = "l1\nl2\nl3\nl4\nl5\nl6\nl7\n";
+        let data_count = count_occurrences(input.as_bytes(), &count_options).unwrap();
+        let mut lines: Vec<_> = data_count
+            .iter()
+            .map(|(line, line_count)| (line.as_str(), line_count.first_seen))
+            .collect();
+        lines.sort();
+        // 行号仍从输入的第一行算起
+        assert_eq!(lines, [("l3", 3), ("l4", 4), ("l5", 5)]);
+    }
+
+    #[test]
+    fn last_line_without_newline_is_counted_and_finishes_progress() {
+        // 一个很小的文件，以及超过切块最小字节数、会被切成多块的文件
+        let large = pseudo_random_lines(300_000, 5000);
+        for prefix in ["", "a\nb\n", large.as_str()] {
+            let input = format!("{prefix}last");
+            let lines = input.lines().count();
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(input.as_bytes()).unwrap();
+            let plain = TestFile::new("no-newline.txt", &input);
+            let compressed = TestFile::new("no-newline.txt.gz", encoder.finish().unwrap());
+            for progress_interval in [1, 1000] {
+                let count_options = CountOptions {
+                    progress_interval,
+                    ..CountOptions::default()
+                };
+                // gzip 文件只能由一个线程读取后分发，进度按压缩后的字节数推进
+                for (file, use_mmap) in [(&plain, false), (&plain, true), (&compressed, false)] {
+                    let len = std::fs::metadata(file.path()).unwrap().len();
+                    let pb = ProgressBar::hidden();
+                    pb.set_length(len);
+                    let paths = [file.path().to_string()];
+                    let (data_count, stats) =
+                        count_file_with_checkpoint(&paths, 4, use_mmap, &count_options, &pb, None)
+                            .unwrap();
+                    assert_eq!(stats.lines, lines);
+                    assert_eq!(data_count["last"].count, 1);
+                    assert_eq!(pb.position(), len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weight_regex_sums_captures() {
+        let count_options = CountOptions {
+            column: vec![0],
+            input_delimiter: String::from(" "),
+            weight_regex: Some(Regex::new(r"bytes=(\d+)").unwrap()),
+            ..CountOptions::default()
+        };
+        let input = "/a bytes=100\n/b bytes=5\n/a bytes=20\n/a no-bytes\n";
+        let data_count = count_occurrences(input.as_bytes(), &count_options).unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("/a"), 120), (String::from("/b"), 5)]
+        );
+    }
+
+    #[test]
+    fn strip_quotes_merges_quoted_fields() {
+        let count_options = CountOptions {
+            column: vec![1],
+            strip_quotes: true,
+            ..CountOptions::default()
+        };
+        let input = "1,foo,x\n2,\"foo\",y\n3,\"a,b\",z\n4,\"say \"\"hi\"\"\",w\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("a,b"), 1),
+                (String::from("foo"), 2),
+                (String::from("say \"hi\""), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_fields_differ_from_empty_fields() {
+        let input = "a,x\nb,\nc\nd,\n";
+        let count_options = CountOptions {
+            column: vec![1],
+            missing_placeholder: Some(String::from("<MISSING>")),
+            ..CountOptions::default()
+        };
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::new(), 2),
+                (String::from("<MISSING>"), 1),
+                (String::from("x"), 1),
+            ]
+        );
+        // 默认缺少的列与空列合并
+        let count_options = CountOptions {
+            column: vec![1],
+            ..CountOptions::default()
+        };
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::new(), 3), (String::from("x"), 1)]
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn count_stream_reads_async_input() {
+        let input: &[u8] = b"\xef\xbb\xbfa\nb\na";
+        let data_count = count_stream(tokio::io::BufReader::new(input), &CountOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            sorted_counts(data_count),
+            [(String::from("a"), 2), (String::from("b"), 1)]
+        );
+    }
+
+    #[test]
+    fn strict_mode_reports_the_malformed_line() {
+        let count_options = CountOptions {
+            strict: true,
+            expected_columns: Some(2),
+            ..CountOptions::default()
+        };
+        // 第二个文件中的行号从该文件的第一行算起
+        let valid = TestFile::new("valid.csv", "a,1\nb,2\n");
+        let ragged = TestFile::new("ragged.csv", "a,1\nb,2\nc\nd,4,5\n");
+        let paths = [valid.path().to_string(), ragged.path().to_string()];
+        let pb = ProgressBar::hidden();
+        for use_mmap in [false, true] {
+            let err = count_file_with_checkpoint(&paths, 4, use_mmap, &count_options, &pb, None)
+                .unwrap_err();
+            match err {
+                Error::Parse { path, line, .. } => {
+                    assert_eq!(path, paths[1]);
+                    assert_eq!(line, 3);
+                }
+                err => panic!("unexpected error: {err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn time_buckets_by_hour() {
+        let count_options = CountOptions {
+            column: vec![1],
+            time_bucket: Some(parse_time_bucket("1h").unwrap()),
+            timestamp_column: 0,
+            ..CountOptions::default()
+        };
+        let input = concat!(
+            "2024-01-01T10:05:00Z,GET\n",
+            "2024-01-01T10:55:00Z,GET\n",
+            "2024-01-01T11:10:00Z,GET\n",
+            "2024-01-01T11:20:00Z,POST\n",
+            "yesterday,GET\n",
+        );
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("2024-01-01T10:00:00,GET"), 2),
+                (String::from("2024-01-01T11:00:00,GET"), 1),
+                (String::from("2024-01-01T11:00:00,POST"), 1),
+                (format!("{UNKNOWN_BUCKET},GET"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_prefix_groups_by_prefix() {
+        let count_options = CountOptions {
+            key_prefix_len: Some(4),
+            ..CountOptions::default()
+        };
+        // 按字符计数，不足 4 个字符的行使用整行
+        let input = "2024-01-01\n2024-02-03\n2023-12-31\n中文字符串\nabc\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [
+                (String::from("2023"), 1),
+                (String::from("2024"), 2),
+                (String::from("abc"), 1),
+                (String::from("中文字符"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn contrived_collision_is_detected() {
+        let first = TestFile::new("collide-1.txt", "ab\nxy\nab\n");
+        let second = TestFile::new("collide-2.txt", "cd\nab\n");
+        let paths = [first.path().to_string(), second.path().to_string()];
+        let pb = ProgressBar::hidden();
+        // 长度相同的行摘要相同，ab、xy、cd 两两碰撞，每对不同的内容只报告一次
+        let digest = |key: &[u8]| key.len() as u128;
+        let collisions = collisions_with(&paths, &CountOptions::default(), &pb, digest).unwrap();
+        let found: Vec<_> = collisions
+            .iter()
+            .map(|collision| {
+                let keys = (collision.first_key.as_str(), collision.second_key.as_str());
+                (collision.first.clone(), collision.second.clone(), keys)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ((paths[0].clone(), 1), (paths[0].clone(), 2), ("ab", "xy")),
+                ((paths[0].clone(), 1), (paths[1].clone(), 1), ("ab", "cd")),
+            ]
+        );
+        assert_eq!(collisions[0].digest, digest_hex(2));
+        // 实际的 128 位摘要不会碰撞
+        assert!(find_hash_collisions(&paths, &CountOptions::default(), &pb)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn multi_byte_input_delimiter() {
+        let count_options = CountOptions {
+            column: vec![1],
+            input_delimiter: crate::parse_delimiter("||").unwrap(),
+            ..CountOptions::default()
+        };
+        let input = "a||b||c\nx||b\nsingle|pipe||c\n";
+        assert_eq!(
+            counts(input, &count_options),
+            [(String::from("b"), 2), (String::from("c"), 1)]
+        );
+        assert!(crate::parse_delimiter("").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+
+use indicatif::ProgressBar;
+use rustc_hash::FxHashSet;
+use xxhash_rust::xxh3::xxh3_128;
+
+use crate::count::{extract_key, resolve_column_names, CountOptions, CountStats};
+use crate::error::{Error, Result};
+use crate::input::{for_each_line, is_set};
+use crate::output::{open_output, OutputCompression};
+
+/// 去重而不统计：按输入顺序输出每个 key 第一次出现时的整行原文，相当于 `awk '!seen[$0]++'`
+///
+/// key 的计算方式与统计阶段相同，例如指定列时只按这些列去重，但输出的仍是整行。
+/// 输出没有表头和次数列，记录之间的分隔符与输入相同；只用一个线程顺序读取以保持顺序。
+/// 返回输出的行数及读取阶段的汇总信息
+pub fn dedup_file(
+    file_paths: &[String],
+    result_path: &str,
+    compression: OutputCompression,
+    append: bool,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<(usize, CountStats)> {
+    let write_error = |source| Error::Write {
+        path: result_path.to_string(),
+        source,
+    };
+    let mut result_file = open_output(result_path, compression, append).map_err(write_error)?;
+    let separator = [count_options.separator()];
+    let mut seen = FxHashSet::default();
+    // 开启 hash_keys 时只保存每个 key 的摘要
+    let mut digests = FxHashSet::default();
+    let mut stats = CountStats::default();
+    for file_path in file_paths {
+        let count_options = &*resolve_column_names(file_path, count_options)?;
+        let lines = for_each_line(file_path, count_options, pb, |_, line| {
+            let Some((key, _)) = extract_key(&line, count_options, &mut stats) else {
+                return Ok(());
+            };
+            // 只有第一次出现的 key 才需要分配新的 String
+            let first = if count_options.hash_keys {
+                digests.insert(xxh3_128(key.as_bytes()))
+            } else {
+                !seen.contains(key.as_ref()) && seen.insert(key.into_owned())
+            };
+            if first {
+                result_file
+                    .write_all(line.as_bytes())
+                    .and_then(|_| result_file.write_all(&separator))
+                    .map_err(write_error)?;
+            }
+            Ok(())
+        })?;
+        stats.lines += lines;
+        if is_set(count_options.interrupted.as_ref()) {
+            break;
+        }
+    }
+    result_file.finish().map_err(write_error)?;
+    Ok((seen.len() + digests.len(), stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestFile;
+
+    #[test]
+    fn dedup_preserves_first_seen_order() {
+        let input = TestFile::new("dedup.txt", "c\na\nc\nb\na\nd\n");
+        let output = TestFile::empty("dedup-out.txt");
+        let pb = ProgressBar::hidden();
+        dedup_file(
+            &[input.path().to_string()],
+            output.path(),
+            OutputCompression::None,
+            false,
+            &CountOptions::default(),
+            &pb,
+        )
+        .unwrap();
+        assert_eq!(output.read(), "c\na\nb\nd\n");
+    }
+}
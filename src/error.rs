@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// 运行过程中可能出现的错误
+#[derive(Debug)]
+pub enum Error {
+    /// 无法打开输入文件
+    Open {
+        path: String,
+        source: std::io::Error,
+    },
+    /// 读取输入时出错
+    Read(std::io::Error),
+    /// 无法创建或写入结果文件
+    Write {
+        path: String,
+        source: std::io::Error,
+    },
+    /// 已有结果文件中的某一行格式不正确，line 为从 1 开始的行号
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    /// 统计线程异常退出
+    Worker,
+}
+
+/// 本库使用的 Result 类型
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Open { path, source } => write!(f, "cannot open '{}': {}", path, source),
+            Error::Read(source) => write!(f, "failed to read input: {}", source),
+            Error::Write { path, source } => write!(f, "cannot write '{}': {}", path, source),
+            Error::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "invalid row {} in '{}': {}", line, path, message),
+            Error::Worker => write!(f, "a counting thread exited unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Open { source, .. } | Error::Write { source, .. } => Some(source),
+            Error::Read(source) => Some(source),
+            Error::Parse { .. } | Error::Worker => None,
+        }
+    }
+}
@@ -0,0 +1,782 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+
+use encoding_rs::{Decoder, Encoding, UTF_8};
+use flate2::read::MultiGzDecoder;
+use indicatif::ProgressBar;
+use memmap2::Mmap;
+use rustc_hash::FxHashSet;
+use zip::ZipArchive;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::count::{list_line_key, CountOptions, DEFAULT_READ_BUFFER};
+use crate::error::{Error, Result};
+
+// ProgressReader 包装输入源，每次读取后按字节数推进进度条
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    pb: ProgressBar,
+}
+
+impl<R> ProgressReader<R> {
+    pub(crate) fn new(inner: R, pb: &ProgressBar) -> Self {
+        ProgressReader {
+            inner,
+            pb: pb.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pb.inc(n as u64);
+        Ok(n)
+    }
+}
+
+// ZIP 压缩包需要随机访问，移动读取位置不推进进度条
+impl<R: Seek> Seek for ProgressReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// DecodeReader 把其他编码的输入转换为 UTF-8
+struct DecodeReader<R> {
+    inner: R,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl<R: BufRead> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            let input = self.inner.fill_buf()?;
+            let last = input.is_empty();
+            // 按最坏情况分配缓冲区，保证一次即可解码完 input
+            let capacity = self
+                .decoder
+                .max_utf8_buffer_length(input.len())
+                .unwrap_or(input.len() * 3 + 16);
+            self.buffer.resize(capacity, 0);
+            let (_, read, written, _) = self.decoder.decode_to_utf8(input, &mut self.buffer, last);
+            self.buffer.truncate(written);
+            self.pos = 0;
+            self.inner.consume(read);
+            self.finished = last;
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// Lines 按 separator 逐条读取输入，行尾的分隔符以及 Windows 换行的 \r 都会被去掉
+// 设置 max_len 时超出的字节在读取时直接丢弃，超长的行不会一次性读入内存
+// lossy 为 true 时无效的 UTF-8 字节替换为 U+FFFD，否则返回 InvalidData 错误
+// interrupted 被置位后不再读取，视为输入已经结束
+pub(crate) struct Lines<R> {
+    reader: R,
+    separator: u8,
+    max_len: Option<usize>,
+    lossy: bool,
+    interrupted: Option<Arc<AtomicBool>>,
+}
+
+pub(crate) fn lines<R: BufRead>(reader: R, count_options: &CountOptions) -> Lines<R> {
+    Lines {
+        reader,
+        separator: count_options.separator(),
+        max_len: count_options.max_line_len,
+        lossy: count_options.lossy,
+        interrupted: count_options.interrupted.clone(),
+    }
+}
+
+// 按换行逐行读取忽略列表等每行一项的列表文件，与输入一样去掉 Windows 换行的 \r
+pub(crate) fn list_lines<R: BufRead>(reader: R) -> Lines<R> {
+    Lines {
+        reader,
+        separator: b'\n',
+        max_len: None,
+        lossy: false,
+        interrupted: None,
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if is_set(self.interrupted.as_ref()) {
+            return None;
+        }
+        let mut line = Vec::new();
+        let mut truncated = false;
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Some(Err(err)),
+            };
+            if available.is_empty() {
+                if line.is_empty() && !truncated {
+                    return None;
+                }
+                break;
+            }
+            let (content, used, done) = match available.iter().position(|b| *b == self.separator) {
+                Some(i) => (&available[..i], i + 1, true),
+                None => (available, available.len(), false),
+            };
+            let room = self.max_len.map_or(content.len(), |max_len| {
+                max_len.saturating_sub(line.len()).min(content.len())
+            });
+            line.extend_from_slice(&content[..room]);
+            truncated |= room < content.len();
+            self.reader.consume(used);
+            if done {
+                break;
+            }
+        }
+        if truncated {
+            // 截断处可能落在多字节字符中间，退回到完整字符的边界
+            if let Err(err) = std::str::from_utf8(&line) {
+                if err.error_len().is_none() {
+                    line.truncate(err.valid_up_to());
+                }
+            }
+        } else {
+            let len = strip_line_ending(&line, self.separator).len();
+            line.truncate(len);
+        }
+        match String::from_utf8(line) {
+            Ok(line) => Some(Ok(line)),
+            Err(err) if self.lossy => {
+                Some(Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()))
+            }
+            Err(err) => Some(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                err,
+            ))),
+        }
+    }
+}
+
+// 去掉行尾的 \r，\r\n 与 \n 结尾的相同内容按同一行统计
+// 以 NUL 分隔时记录中的 \r 属于内容本身，原样保留
+pub(crate) fn strip_line_ending(line: &[u8], separator: u8) -> &[u8] {
+    if separator == b'\n' {
+        line.strip_suffix(b"\r").unwrap_or(line)
+    } else {
+        line
+    }
+}
+
+// 把一行截断到不超过 max_len 字节，并保证不会截断在多字节字符中间
+pub(crate) fn truncate_line(line: &[u8], max_len: Option<usize>) -> &[u8] {
+    match max_len {
+        Some(max_len) if line.len() > max_len => {
+            let mut end = max_len;
+            while end > 0 && line[end] & 0xc0 == 0x80 {
+                end -= 1;
+            }
+            &line[..end]
+        }
+        _ => line,
+    }
+}
+
+// 读取文件并将数据逐行发送到 channel，返回读取的行数
+// 附带从 1 开始的行号，便于合并时确定首次出现的位置
+pub(crate) fn read_file(
+    file_path: &str,
+    count_options: &CountOptions,
+    data_sender: &SyncSender<(usize, String)>,
+    pb: &ProgressBar,
+) -> Result<usize> {
+    for_each_line(file_path, count_options, pb, |line_no, line| {
+        // 所有统计线程都已退出时发送会失败
+        data_sender.send((line_no, line)).map_err(|_| Error::Worker)
+    })
+}
+
+// 逐行读取文件并交给 f 处理，返回读取的行数；开启 skip_header 时第一行不会交给 f
+pub(crate) fn for_each_line(
+    file_path: &str,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+    f: impl FnMut(usize, String) -> Result<()>,
+) -> Result<usize> {
+    with_input(file_path, count_options, pb, |reader| {
+        read_lines(file_path, reader, count_options, f)
+    })
+}
+
+/// 只数出所有输入的总行数而不统计，相当于会自动解压、转换编码的 `wc -l`
+///
+/// 最后一行没有结尾的分隔符时同样计为一行；不按行处理，也不受过滤、--skip 等选项影响
+pub fn count_lines(
+    file_paths: &[String],
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+) -> Result<usize> {
+    let separator = count_options.separator();
+    let mut total = 0;
+    for file_path in file_paths {
+        total += with_input(file_path, count_options, pb, |mut reader| {
+            let mut lines = 0;
+            let mut unterminated = false;
+            loop {
+                let buf = reader.fill_buf().map_err(Error::Read)?;
+                let Some(&last) = buf.last() else {
+                    break;
+                };
+                lines += buf.iter().filter(|&&byte| byte == separator).count();
+                unterminated = last != separator;
+                let len = buf.len();
+                reader.consume(len);
+            }
+            Ok(lines + usize::from(unterminated))
+        })?;
+        if is_set(count_options.interrupted.as_ref()) {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+// 读取文件的第一行，文件为空时返回 None
+pub(crate) fn read_header(file_path: &str, count_options: &CountOptions) -> Result<Option<String>> {
+    with_input(file_path, count_options, &ProgressBar::hidden(), |reader| {
+        self::lines(reader, count_options)
+            .next()
+            .transpose()
+            .map_err(Error::Read)
+    })
+}
+
+// 打开输入并交给 f 读取，ZIP 压缩包读取其中的一个条目，其余输入见 open_input
+pub(crate) fn with_input<T>(
+    file_path: &str,
+    count_options: &CountOptions,
+    pb: &ProgressBar,
+    f: impl for<'r> FnOnce(Box<dyn BufRead + 'r>) -> Result<T>,
+) -> Result<T> {
+    if !is_zip(file_path) {
+        return f(open_input(
+            file_path,
+            count_options.encoding,
+            count_options.read_buffer,
+            pb,
+        )?);
+    }
+    // 压缩包中的条目借用了 archive，只能在这里读完
+    let open_error = |source| Error::Open {
+        path: file_path.to_string(),
+        source,
+    };
+    // 读取 ZIP 的目录需要随机访问，HTTP 响应只能顺序读取
+    if is_url(file_path) {
+        return Err(open_error(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "zip archives cannot be read from a URL",
+        )));
+    }
+    let file = File::open(file_path).map_err(open_error)?;
+    let file = BufReader::with_capacity(count_options.read_buffer.max(1), file);
+    let mut archive =
+        ZipArchive::new(ProgressReader::new(file, pb)).map_err(|err| open_error(err.into()))?;
+    let name = zip_entry_name(&archive, count_options.zip_entry.as_deref()).map_err(open_error)?;
+    let entry = archive
+        .by_name(&name)
+        .map_err(|err| open_error(err.into()))?;
+    let reader = decode_input(Box::new(BufReader::new(entry)), count_options.encoding)?;
+    f(reader)
+}
+
+// 把 reader 的内容逐行交给 f 处理，返回读取的行数
+fn read_lines(
+    file_path: &str,
+    reader: Box<dyn BufRead + '_>,
+    count_options: &CountOptions,
+    mut f: impl FnMut(usize, String) -> Result<()>,
+) -> Result<usize> {
+    let mut lines = 0;
+    for line in self::lines(reader, count_options) {
+        if reached_limit(lines, count_options) {
+            break;
+        }
+        let line = match line {
+            // 严格模式下报告无效 UTF-8 所在的行号
+            Err(err) if count_options.strict && err.kind() == std::io::ErrorKind::InvalidData => {
+                return Err(Error::Parse {
+                    path: file_path.to_string(),
+                    line: lines + 1,
+                    message: err.to_string(),
+                })
+            }
+            line => line.map_err(Error::Read)?,
+        };
+        lines += 1;
+        if (count_options.skip_header && lines == 1) || lines <= count_options.skip {
+            continue;
+        }
+        f(lines, line)?;
+    }
+
+    Ok(lines)
+}
+
+// 已读取 lines 行后是否已经统计了 limit 限定的行数，达到后不再读取
+pub(crate) fn reached_limit(lines: usize, count_options: &CountOptions) -> bool {
+    count_options
+        .limit
+        .is_some_and(|limit| lines >= count_options.skip.saturating_add(limit))
+}
+
+// 打开输入源，"-" 表示标准输入；以 gzip 魔数 1f 8b 开头时自动解压
+// 非 UTF-8 编码的输入会在解压后转换为 UTF-8
+// 进度条按从源读取的原始字节数推进，压缩输入同样能走到 100%
+// buffer_size 为从输入源读取时的缓冲区字节数，识别压缩格式需要缓冲区能容纳文件开头的魔数
+pub(crate) fn open_input(
+    file_path: &str,
+    encoding: &'static Encoding,
+    buffer_size: usize,
+    pb: &ProgressBar,
+) -> Result<Box<dyn BufRead>> {
+    let source: Box<dyn Read> = if file_path == "-" {
+        Box::new(std::io::stdin().lock())
+    } else if is_url(file_path) {
+        Box::new(open_url(file_path)?)
+    } else {
+        Box::new(File::open(file_path).map_err(|source| Error::Open {
+            path: file_path.to_string(),
+            source,
+        })?)
+    };
+    decode_input(
+        Box::new(BufReader::with_capacity(
+            buffer_size.max(ZSTD_MAGIC.len()),
+            ProgressReader::new(source, pb),
+        )),
+        encoding,
+    )
+}
+
+// 以 gzip 魔数 1f 8b 或 zstd 魔数 28 b5 2f fd 开头时自动解压，非 UTF-8 编码的输入转换为 UTF-8
+fn decode_input<'a>(
+    mut reader: Box<dyn BufRead + 'a>,
+    encoding: &'static Encoding,
+) -> Result<Box<dyn BufRead + 'a>> {
+    let head = reader.fill_buf().map_err(Error::Read)?;
+    if head.starts_with(GZIP_MAGIC) {
+        reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
+    } else if head.starts_with(ZSTD_MAGIC) {
+        reader = Box::new(BufReader::new(
+            ZstdDecoder::with_buffer(reader).map_err(Error::Read)?,
+        ));
+    }
+    if encoding == UTF_8 {
+        skip_bom(&mut reader).map_err(Error::Read)?;
+    } else {
+        // 解码器会自行识别并去掉 BOM
+        reader = Box::new(BufReader::new(DecodeReader {
+            inner: reader,
+            decoder: encoding.new_decoder(),
+            buffer: Vec::new(),
+            pos: 0,
+            finished: false,
+        }));
+    }
+    Ok(reader)
+}
+
+// gzip 文件开头的魔数
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+// zstd 文件开头的魔数
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// 判断文件是否为 gzip 或 zstd 压缩文件
+pub(crate) fn is_compressed(file_path: &str) -> Result<bool> {
+    let file = File::open(file_path).map_err(|source| Error::Open {
+        path: file_path.to_string(),
+        source,
+    })?;
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    file.take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .map_err(Error::Read)?;
+    Ok(magic.starts_with(GZIP_MAGIC) || magic == ZSTD_MAGIC)
+}
+
+// 判断中断标志是否已被置位
+pub(crate) fn is_set(flag: Option<&Arc<AtomicBool>>) -> bool {
+    flag.is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+// 以 http:// 或 https:// 开头的输入从网络读取
+pub(crate) fn is_url(file_path: &str) -> bool {
+    let scheme = |prefix: &str| {
+        file_path
+            .get(..prefix.len())
+            .is_some_and(|head| head.eq_ignore_ascii_case(prefix))
+    };
+    scheme("http://") || scheme("https://")
+}
+
+// 发送 GET 请求并返回响应体，连接失败或状态码不是 2xx 时返回错误
+fn open_url(url: &str) -> Result<impl Read> {
+    let response = ureq::get(url).call().map_err(|err| Error::Open {
+        path: url.to_string(),
+        source: std::io::Error::other(err),
+    })?;
+    Ok(response.into_body().into_reader())
+}
+
+// 是否为普通文件，命名管道（例如 bash 的 <(...)）等只能顺序读取一次
+pub(crate) fn is_regular_file(file_path: &str) -> bool {
+    std::fs::metadata(file_path).is_ok_and(|metadata| metadata.is_file())
+}
+
+// 以 .zip 结尾（不区分大小写）的输入按 ZIP 压缩包读取
+pub(crate) fn is_zip(file_path: &str) -> bool {
+    Path::new(file_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+// 确定要读取的压缩包条目：指定了 entry 时按名称查找，否则压缩包中只能有一个文件
+// 找不到或有多个文件时返回的错误中列出所有可选的条目名
+fn zip_entry_name<R: Read + Seek>(
+    archive: &ZipArchive<R>,
+    entry: Option<&str>,
+) -> std::io::Result<String> {
+    let mut names = Vec::new();
+    for name in archive.file_names() {
+        let name = name?;
+        // 以 / 结尾的是目录
+        if !name.ends_with('/') {
+            names.push(name.into_owned());
+        }
+    }
+    match entry {
+        Some(entry) if names.iter().any(|name| name == entry) => Ok(entry.to_string()),
+        Some(entry) => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "entry '{}' not found in archive, available entries: {}",
+                entry,
+                names.join(", ")
+            ),
+        )),
+        None if names.len() == 1 => Ok(names.remove(0)),
+        None if names.is_empty() => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "archive contains no files",
+        )),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "archive contains multiple entries, choose one with --zip-entry: {}",
+                names.join(", ")
+            ),
+        )),
+    }
+}
+
+// 以只读方式把文件映射到内存
+pub(crate) fn map_file(file_path: &str) -> Result<Mmap> {
+    let open_error = |source| Error::Open {
+        path: file_path.to_string(),
+        source,
+    };
+    let file = File::open(file_path).map_err(open_error)?;
+    // SAFETY: 映射期间文件若被其他进程截断或修改，读取结果未定义；
+    // 这里只做只读统计，与 BufReader 读取正在被改写的文件一样由调用方负责
+    unsafe { Mmap::map(&file) }.map_err(open_error)
+}
+
+// UTF-8 BOM 不是有效数据，出现在开头时直接跳过
+pub(crate) const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// 跳过 reader 开头的 UTF-8 BOM
+pub(crate) fn skip_bom<R: BufRead + ?Sized>(reader: &mut R) -> std::io::Result<()> {
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(())
+}
+
+/// 解析分隔符中的转义序列：`\t`、`\n`、`\r`、`\0`、`\\` 以及 `\xHH`（两位十六进制，不超过 7f）
+///
+/// 便于在命令行中指定制表符、NUL、单元分隔符 `\x1f` 等控制字符，其余字符原样保留；
+/// 分隔符可以由多个字符组成，例如 `||` 或 `, `，但不能为空
+pub fn parse_delimiter(delimiter: &str) -> std::result::Result<String, String> {
+    let invalid = |escape: &str| format!("invalid escape sequence '\\{}' in delimiter", escape);
+    let mut parsed = String::with_capacity(delimiter.len());
+    let mut chars = delimiter.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            parsed.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => parsed.push('\t'),
+            Some('n') => parsed.push('\n'),
+            Some('r') => parsed.push('\r'),
+            Some('0') => parsed.push('\0'),
+            Some('\\') => parsed.push('\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                // 只接受 ASCII，更大的值不是单个字节的 UTF-8 字符
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && byte.is_ascii() => parsed.push(byte as char),
+                    _ => return Err(invalid(&format!("x{}", hex))),
+                }
+            }
+            Some(other) => return Err(invalid(&other.to_string())),
+            None => return Err(invalid("")),
+        }
+    }
+    if parsed.is_empty() {
+        return Err(String::from("delimiter must not be empty"));
+    }
+    Ok(parsed)
+}
+
+/// 按名称查找输入编码，例如 `utf-8`、`gbk`、`gb18030`
+pub fn parse_encoding(label: &str) -> std::result::Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding '{}'", label))
+}
+
+/// 获取输入文件的字节数，标准输入无法获取，返回 `None`；URL 取 HEAD 响应的 Content-Length
+pub fn input_len(file_path: &str) -> Result<Option<u64>> {
+    if file_path == "-" {
+        return Ok(None);
+    }
+    // 先用 HEAD 请求获取 Content-Length，请求失败或服务器没有返回长度时视为大小未知，
+    // 真正的错误留给读取时的 GET 请求报告
+    if is_url(file_path) {
+        return Ok(ureq::head(file_path).call().ok().and_then(|response| {
+            let length = response.headers().get("content-length")?;
+            length.to_str().ok()?.parse().ok()
+        }));
+    }
+    let metadata = std::fs::metadata(file_path).map_err(|source| Error::Open {
+        path: file_path.to_string(),
+        source,
+    })?;
+    // 命名管道、字符设备等的大小为 0，不代表实际能读到的字节数
+    Ok(metadata.is_file().then_some(metadata.len()))
+}
+
+/// 展开输入路径：目录展开为其中的所有文件，含 *、?、[ 的路径按 glob 模式匹配
+///
+/// 展开结果按路径排序，"-" 和普通文件原样保留；模式没有匹配到任何文件时返回错误
+pub fn expand_inputs(file_paths: &[String]) -> Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    for file_path in file_paths {
+        let open_error = |source| Error::Open {
+            path: file_path.clone(),
+            source,
+        };
+        // URL 中的 ? 是查询字符串而不是通配符
+        if file_path == "-" || is_url(file_path) {
+            expanded.push(file_path.clone());
+        } else if std::fs::metadata(file_path).is_ok_and(|metadata| metadata.is_dir()) {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(file_path).map_err(open_error)? {
+                let path = entry.map_err(open_error)?.path();
+                if path.is_file() {
+                    files.push(path.to_string_lossy().into_owned());
+                }
+            }
+            files.sort();
+            expanded.extend(files);
+        } else if file_path.contains(['*', '?', '[']) {
+            let paths = glob::glob(file_path).map_err(|err| {
+                open_error(std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+            })?;
+            let mut files = Vec::new();
+            for path in paths {
+                let path = path.map_err(|err| open_error(err.into()))?;
+                if path.is_file() {
+                    files.push(path.to_string_lossy().into_owned());
+                }
+            }
+            if files.is_empty() {
+                return Err(open_error(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "no files match the pattern",
+                )));
+            }
+            expanded.extend(files);
+        } else {
+            expanded.push(file_path.clone());
+        }
+    }
+    Ok(expanded)
+}
+
+/// 读取忽略列表，每行一个，按 count_options 中统计时相同的方式（去空白、大小写等）处理，
+/// 结果用作 [`CountOptions::ignore_lines`]；不做列选择，列表中的每行对应的是选出的内容
+pub fn load_ignore_lines(
+    file_path: &str,
+    count_options: &CountOptions,
+) -> Result<FxHashSet<String>> {
+    let reader = open_input(
+        file_path,
+        UTF_8,
+        count_options.read_buffer,
+        &ProgressBar::hidden(),
+    )?;
+    let mut ignore_lines = FxHashSet::default();
+    for line in list_lines(reader) {
+        let line = line.map_err(Error::Read)?;
+        ignore_lines.insert(list_line_key(&line, count_options).0);
+    }
+    Ok(ignore_lines)
+}
+
+/// 读取停用词表，每行一个词，去掉首尾空白后忽略空行；lowercase 为 true 时统一转换为小写
+pub fn load_stopwords(file_path: &str, lowercase: bool) -> Result<FxHashSet<String>> {
+    let reader = open_input(
+        file_path,
+        UTF_8,
+        DEFAULT_READ_BUFFER,
+        &ProgressBar::hidden(),
+    )?;
+    let mut stopwords = FxHashSet::default();
+    for line in reader.lines() {
+        let line = line.map_err(Error::Read)?;
+        let word = line.trim();
+        if word.is_empty() {
+            continue;
+        }
+        stopwords.insert(if lowercase {
+            word.to_lowercase()
+        } else {
+            word.to_string()
+        });
+    }
+    Ok(stopwords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::{count_occurrences, CountOptions};
+    use crate::test_util::TestFile;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn progress_follows_bytes_read_from_the_source() {
+        let input: String = (0..10_000).map(|i| format!("line{}\n", i % 300)).collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let plain = TestFile::new("progress.txt", &input);
+        let compressed = TestFile::new("progress.txt.gz", encoder.finish().unwrap());
+        for file in [&plain, &compressed] {
+            let pb = ProgressBar::hidden();
+            pb.set_length(input_len(file.path()).unwrap().unwrap());
+            assert_eq!(
+                open_input(file.path(), encoding_rs::UTF_8, DEFAULT_READ_BUFFER, &pb)
+                    .unwrap()
+                    .lines()
+                    .count(),
+                10_000
+            );
+            // 压缩输入按压缩后的字节数推进，同样正好走到总长度
+            assert_eq!(pb.position(), pb.length().unwrap());
+        }
+        assert_eq!(input_len("-").unwrap(), None);
+    }
+
+    #[test]
+    fn directories_and_globs_expand_to_files() {
+        let dir = TestFile::empty("inputs");
+        std::fs::create_dir(dir.path()).unwrap();
+        let dir_path = std::path::Path::new(dir.path());
+        std::fs::write(dir_path.join("b.txt"), "x\n").unwrap();
+        std::fs::write(dir_path.join("a.txt"), "y\n").unwrap();
+        std::fs::write(dir_path.join("c.log"), "z\n").unwrap();
+        std::fs::create_dir(dir_path.join("nested")).unwrap();
+        let file = |name: &str| dir_path.join(name).to_string_lossy().into_owned();
+
+        // 目录只展开其中的文件，按路径排序
+        let expanded = expand_inputs(&[dir.path().to_string()]).unwrap();
+        assert_eq!(expanded, [file("a.txt"), file("b.txt"), file("c.log")]);
+        let expanded = expand_inputs(&[file("*.txt"), String::from("-")]).unwrap();
+        assert_eq!(expanded, [file("a.txt"), file("b.txt"), String::from("-")]);
+        assert!(matches!(
+            expand_inputs(&[file("*.csv")]),
+            Err(Error::Open { .. })
+        ));
+    }
+
+    #[test]
+    fn ignored_lines_never_appear_in_output() {
+        // 忽略列表以 Windows 换行保存，与输入使用相同的去空白和大小写规则
+        let blocklist = TestFile::new("blocklist.txt", "Noise\r\n  heartbeat \r\n");
+        let count_options = CountOptions {
+            trim: true,
+            ignore_case: true,
+            ..CountOptions::default()
+        };
+        let count_options = CountOptions {
+            ignore_lines: load_ignore_lines(blocklist.path(), &count_options).unwrap(),
+            ..count_options
+        };
+        let input = "error\nnoise\r\nHEARTBEAT\n heartbeat\nerror\nkept\n";
+        let data_count = count_occurrences(input.as_bytes(), &count_options).unwrap();
+        let mut keys: Vec<_> = data_count.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["error", "kept"]);
+    }
+
+    #[test]
+    fn read_buffer_size_is_honored() {
+        let file = TestFile::new("buffer.txt", "line\n".repeat(100_000));
+        let pb = ProgressBar::hidden();
+        for buffer_size in [1024, 64 * 1024] {
+            let mut reader = open_input(file.path(), encoding_rs::UTF_8, buffer_size, &pb).unwrap();
+            // 普通文件一次读满整个缓冲区
+            assert_eq!(reader.fill_buf().unwrap().len(), buffer_size);
+        }
+    }
+
+    #[test]
+    fn count_lines_matches_fixture() {
+        let pb = ProgressBar::hidden();
+        let count_options = CountOptions::default();
+        let file = TestFile::new("lines.txt", "a\nb\n\nc\n");
+        assert_eq!(
+            count_lines(&[file.path().to_string()], &count_options, &pb).unwrap(),
+            4
+        );
+        // 最后一行没有换行符时同样计为一行，多个文件的行数相加
+        let partial = TestFile::new("partial.txt", "x\ny");
+        let paths = [file.path().to_string(), partial.path().to_string()];
+        assert_eq!(count_lines(&paths, &count_options, &pb).unwrap(), 6);
+        let empty = TestFile::new("empty.txt", "");
+        assert_eq!(
+            count_lines(&[empty.path().to_string()], &count_options, &pb).unwrap(),
+            0
+        );
+    }
+}
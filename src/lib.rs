@@ -0,0 +1,40 @@
+//! csvaction 的核心逻辑：统计每行数据的出现次数，排序后按指定格式输出。
+//!
+//! 命令行程序只是这些函数的一层包装，其他 Rust 代码可以直接调用
+//! [`count_occurrences`] 和 [`write_results`]，无需经过文件系统；
+//! 需要自行处理结果时可以用 [`sorted_results`] 直接遍历排序后的行。
+//! 启用 `tokio` feature 后可以用 `count_stream` 统计异步 reader 的内容。
+
+mod bloom;
+mod chunk;
+mod count;
+mod dedup;
+mod error;
+mod input;
+mod merge;
+mod output;
+mod spill;
+#[cfg(test)]
+mod test_util;
+
+#[cfg(feature = "tokio")]
+pub use count::count_stream;
+pub use count::{
+    count_file, count_file_mmap, count_file_with_checkpoint, count_occurrences,
+    find_hash_collisions, merge_count_maps, parse_field_range, parse_time_bucket, Checkpoint,
+    CheckpointFn, CountMap, CountMode, CountOptions, CountStats, HashCollision, LineCount,
+    BIGRAM_SEPARATOR, DEFAULT_READ_BUFFER, OTHER_KEY, UNKNOWN_BUCKET,
+};
+pub use dedup::dedup_file;
+pub use error::{Error, Result};
+pub use input::{
+    count_lines, expand_inputs, input_len, load_ignore_lines, load_stopwords, parse_delimiter,
+    parse_encoding,
+};
+pub use merge::{insert_expected_keys, load_counts, read_checkpoint_position};
+pub use output::{
+    sorted_results, write_checkpoint, write_comparison, write_histogram, write_manifest,
+    write_matrix, write_results, write_sort_and_merge_result, write_top_per_group, Collation,
+    DataCount, Manifest, OutputCompression, OutputFormat, Quoting, ResultColumn, SortKey, Tiebreak,
+    WriteOptions, WriteStats,
+};
@@ -1,157 +1,1912 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::collections::HashSet;
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, RandomState};
+use std::io::{IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
-
-// DataCount 用于存储每行数据及其出现次数
-#[derive(Clone)]
-struct DataCount {
-    line: String,
-    count: usize,
-}
+use chrono::{Local, SecondsFormat};
+use clap::{CommandFactory, Parser, ValueEnum};
+use csvaction::{
+    count_file, count_file_mmap, count_file_with_checkpoint, count_lines, dedup_file,
+    expand_inputs, find_hash_collisions, input_len, insert_expected_keys, load_counts,
+    load_ignore_lines, load_stopwords, merge_count_maps, parse_delimiter, parse_encoding,
+    parse_field_range, parse_time_bucket, read_checkpoint_position, write_checkpoint,
+    write_comparison, write_histogram, write_manifest, write_matrix, write_sort_and_merge_result,
+    write_top_per_group, Checkpoint, Collation, CountMap, CountMode, CountOptions, CountStats,
+    Error, HashCollision, Manifest, OutputCompression, OutputFormat, Quoting, Result, ResultColumn,
+    SortKey, Tiebreak, WriteOptions, WriteStats, DEFAULT_READ_BUFFER,
+};
+use encoding_rs::Encoding;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle, TermLike};
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use regex::Regex;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "退出码：0 表示成功；1 表示使用 --duplicates-only 时没有找到重复行；2 表示出错；\
+                  130 表示被 Ctrl-C 中断，已统计的部分仍会写入结果"
+)]
 struct Args {
-    /// 导入的源文件
-    #[arg(short, long, default_value_t = String::from("JXJ.txt"))]
-    file_path: String,
+    /// 导入的源文件，可以指定多个并合并统计，传入 "-" 时从标准输入读取
+    /// 以 .zip 结尾的文件按 ZIP 压缩包读取其中的一个条目，见 --zip-entry
+    /// 以 http:// 或 https:// 开头时从网络下载并统计，服务器返回 Content-Length 时按下载进度显示进度条
+    /// 传入目录时统计其中的所有文件，也可以使用 glob 模式，例如 'logs/*.txt'（需加引号避免被 shell 展开）
+    #[arg(short, long, num_args = 1.., default_value = "JXJ.txt")]
+    file_path: Vec<String>,
 
-    /// 输出的文件名
+    /// 输出的文件名，传入 "-" 时写到标准输出
     #[arg(short, long, default_value_t = String::from("result.csv"))]
     result_path: String,
 
-    /// 使用的线程数量
-    #[arg(short, long, default_value_t = 5)]
-    concurrency: usize,
+    /// 使用的线程数量，普通文件按行切块并行统计，从标准输入读取时同样作用于统计阶段
+    /// 默认为 CPU 核数
+    #[arg(short, long)]
+    concurrency: Option<usize>,
+
+    /// 保证相同的输入得到逐字节相同的输出：使用单线程统计，未指定 --seed 时使用固定的种子 0
+    /// 排序输出本身已按行内容打破平局，这个选项主要让 --no-sort 和 --sample 的结果也可以复现
+    #[arg(long, conflicts_with = "concurrency")]
+    deterministic: bool,
+
+    /// 只输出出现次数最多的前 N 行
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// 帕累托截断：按出现次数降序输出，累计出现次数达到总次数的该百分比后停止，例如 80
+    /// 达到该比例的那一行也会输出；与 --top 同时使用时取两者中较少的行
+    #[arg(long, value_parser = parse_percent, conflicts_with_all = ["no_sort", "sort_by", "ascending"])]
+    top_percent: Option<f64>,
+
+    /// 只输出出现次数不少于该值的行，默认为 1；指定 --expect-keys-from 时默认为 0
+    #[arg(long)]
+    min_count: Option<usize>,
+
+    /// 统计的单元：lines 按行统计，chars 统计每个字符（不包括换行符）的出现次数，控制字符转义后输出，
+    /// words 按 Unicode 词边界拆词后统计每个词，标点和空白不计入，
+    /// bigrams 统计相邻两行组成的行对，两行之间用制表符连接（每个文件只用一个线程统计）
+    #[arg(long, value_enum, default_value_t = CountMode::Lines)]
+    mode: CountMode,
+
+    /// 按词统计时用该正则的每个匹配作为一个词，代替 Unicode 词边界，例如 "[A-Za-z]+"；只能与 --mode words 一起使用
+    #[arg(long)]
+    word_regex: Option<Regex>,
+
+    /// 按词统计时不统计该文件中列出的词（每行一个），与 --ignore-case 一起使用时不区分大小写；只能与 --mode words 一起使用
+    #[arg(long)]
+    stopwords: Option<String>,
+
+    /// 把每行（经过列选择等处理后）按该分隔符拆开，每一段分别计数，一行可以计入多个 key，
+    /// 例如 --explode ';' 分别统计 tag1;tag2;tag3 中的每个标签；空段不计数，开启 --trim 时每段分别去掉首尾空白
+    #[arg(long, value_parser = parse_delimiter, conflicts_with_all = ["mode", "hash_keys", "dedup"])]
+    explode: Option<String>,
+
+    /// 忽略大小写合并统计，输出保留首次出现的原始写法
+    #[arg(long)]
+    ignore_case: bool,
+
+    /// 统计前把每行转换为 Unicode NFC 规范形式，使合成与分解两种写法的相同文字（如 é 与 e + ◌́）合并统计
+    #[arg(long)]
+    normalize_unicode: bool,
+
+    /// 统计前把连续的空白字符替换为一个空格并去掉首尾空白，例如 "foo   bar" 与 "foo bar" 合并统计
+    #[arg(long)]
+    collapse_whitespace: bool,
+
+    /// 统计前把每行转换为小写，输出也使用小写
+    #[arg(long)]
+    to_lowercase: bool,
+
+    /// 统计前把每行转换为大写，输出也使用大写，不能与 --to-lowercase 同时使用
+    #[arg(long, conflicts_with = "to_lowercase")]
+    to_uppercase: bool,
+
+    /// 统计前去掉每行首尾的空白字符（行尾的 \r 无论是否开启都会被去掉）
+    #[arg(long)]
+    trim: bool,
+
+    /// 只统计按分隔符拆分后的第 N 列（从 0 开始），列数不足的行按空值统计
+    /// 可以重复指定或用逗号分隔多列（如 0,2），按列的组合统计，相当于 GROUP BY
+    #[arg(long, value_delimiter = ',')]
+    column: Vec<usize>,
+
+    /// 按表头中的列名选择统计的列，可以重复指定或用逗号分隔多个列名；每个输入文件分别按其第一行查找，
+    /// 表头不参与统计；列名不存在时报错，不能与 --column 或标准输入同时使用
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["column", "capture"])]
+    column_name: Vec<String>,
+
+    /// 按 CSV 规则拆分列：双引号包裹的字段中的分隔符不拆分，选出的字段去掉外层双引号并把 "" 还原为 "，
+    /// 使 "foo" 与 foo 合并统计；用于 --column、--column-name、--expected-columns 和 --weight-column
+    #[arg(long)]
+    strip_quotes: bool,
+
+    /// 字段数不足、--column 或 --column-name 选中的列不存在时用该字符串作为这一列的值（只写选项名时为 <MISSING>），
+    /// 使缺失的列与存在但为空的列分开统计；默认两者都视为空字符串，合并为一行
+    #[arg(long, num_args = 0..=1, default_missing_value = "<MISSING>")]
+    missing_placeholder: Option<String>,
+
+    /// 用每行中 START..END 范围内的字符（从 0 开始，左闭右开，按字符而不是字节计数）作为 key，适用于定宽格式的文件；
+    /// 省略 END 表示到行尾。行比范围短时只取实际存在的部分（不补空格），字符数不超过 START 的行不参与统计
+    #[arg(long, value_parser = parse_field_range, conflicts_with_all = ["column", "column_name", "capture"])]
+    field_range: Option<Range<usize>>,
+
+    /// 用每行的前 N 个字符（按字符而不是字节计数）作为 key，例如按时间戳前缀或固定的行首分组，输出的也是这段前缀；
+    /// 不足 N 个字符的行使用整行，比 --field-range 0..N 更简单，后者不统计空行
+    #[arg(long, conflicts_with_all = ["column", "column_name", "capture", "field_range"])]
+    key_prefix_len: Option<usize>,
+
+    /// 用正则表达式的第一个捕获组作为统计的 key，没有捕获组时使用整个匹配，不匹配的行不参与统计
+    /// 例如 '" (\d{3}) ' 统计 Apache 日志中的状态码；不能与 --column 同时使用
+    #[arg(long, value_parser = Regex::new, conflicts_with = "column")]
+    capture: Option<Regex>,
+
+    /// 拆分列时使用的输入分隔符，支持 \t、\0、\x1f 等转义，例如 --input-delimiter '\t' 读取 TSV；
+    /// 可以是多个字符组成的字符串，例如 '||' 或 ', '，按整个字符串拆分，不能为空
+    #[arg(long, default_value = ",", value_parser = parse_delimiter)]
+    input_delimiter: String,
+
+    /// 每行应有的字段数（按输入分隔符拆分），结束时在 stderr 报告字段数不一致的行数，用于发现格式错误的 CSV
+    #[arg(long)]
+    expected_columns: Option<usize>,
+
+    /// 按时间窗口统计，窗口长度为正整数加单位 s、m、h 或 d，例如 1h 按小时统计；需要同时指定 --timestamp-column
+    /// 输出的每行为 --timestamp-column 列的时间戳所在窗口的起始时间（UTC 或不带时区的原始时间）与原来的内容用输入分隔符连接，
+    /// 时间戳支持 RFC 3339、不带时区的 ISO 8601 日期时间及日期，无法解析的行记入 <UNKNOWN> 窗口
+    #[arg(long, value_parser = parse_time_bucket, requires = "timestamp_column", conflicts_with_all = [
+        "mode", "explode", "hash_keys", "dedup",
+    ])]
+    time_bucket: Option<u64>,
+
+    /// 时间戳所在的列，从 0 开始，按输入分隔符拆分
+    #[arg(long, requires = "time_bucket")]
+    timestamp_column: Option<usize>,
+
+    /// 每行累加第 N 列（从 0 开始，按输入分隔符拆分）的数值而不是 1，Count 列变为该列之和，例如按商品统计销量；
+    /// 该列不是非负整数的行不参与统计，结束时在 stderr 报告行数
+    #[arg(long, conflicts_with = "dedup")]
+    weight_column: Option<usize>,
+
+    /// 每行累加该正则第一个捕获组（没有捕获组时为整个匹配）的数值而不是 1，key 仍为整行或选出的列，
+    /// 例如 'bytes=(\d+)' 按 URL 汇总日志中的字节数；不匹配或捕获的内容不是非负整数的行不参与统计，
+    /// 结束时在 stderr 报告行数
+    #[arg(long, value_parser = Regex::new, conflicts_with_all = ["weight_column", "dedup"])]
+    weight_regex: Option<Regex>,
+
+    /// 跳过字段数与 --expected-columns 不一致的行，不参与统计
+    #[arg(long, requires = "expected_columns")]
+    skip_mismatched: bool,
+
+    /// 输出格式，默认根据输出文件名推断：.db、.sqlite、.sqlite3 为 sqlite，.tsv 为 tsv，
+    /// .parquet 为 parquet，其余为 csv
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// 使用 gzip 压缩输出文件，输出文件名以 .gz 结尾时自动开启；
+    /// 输出文件名以 .zst 结尾时改用 zstd 压缩
+    #[arg(long)]
+    compress: bool,
+
+    /// 只输出排序后的前 N 行，与 --tail 同时使用时先输出前 N 行，
+    /// 再输出一行 "..."（仅 CSV、TSV），最后输出末尾的行
+    #[arg(long)]
+    head: Option<usize>,
+
+    /// 只输出排序后的最后 N 行，便于同时查看出现最多和最少的行
+    #[arg(long, conflicts_with = "no_sort")]
+    tail: Option<usize>,
+
+    /// 不排序直接输出，节省排序所需的时间和内存；输出顺序不确定，每次运行可能不同
+    /// 与 --top 同时使用时输出的是任意 N 行
+    #[arg(long, conflicts_with_all = ["sort_by", "ascending", "numeric"])]
+    no_sort: bool,
+
+    /// 不同的行多于 N 个时改用外部排序：每 N 行排序后写入临时目录中的分段文件，再归并写入结果，
+    /// 排序时内存中最多保存 N 行的副本，适合结果太大无法在内存中再复制一份的情况；
+    /// 只对 CSV、TSV、JSON、NDJSON 输出生效
+    #[arg(long, conflicts_with_all = ["no_sort", "tail", "top_percent"])]
+    sort_buffer: Option<usize>,
+
+    /// 使用稳定排序：排序依据相等的行（例如 --empty-placeholder 后输出相同的两行）保持首次出现的先后顺序，
+    /// 默认使用更快、更省内存的不稳定排序，这些行之间的顺序不确定
+    #[arg(long, conflicts_with = "no_sort")]
+    stable_sort: bool,
+
+    /// 排序依据，count 按出现次数，line 按行内容，length 按行内容的字符数（相同时按行内容升序），
+    /// first-seen 按在输入中首次出现的先后，保留输入的自然顺序，不受 --ascending 影响
+    #[arg(long, value_enum, default_value_t = SortKey::Count)]
+    sort_by: SortKey,
+
+    /// 按出现次数排序时，次数相同的行之间的顺序：line-asc 按行内容升序，line-desc 按行内容降序，
+    /// first-seen 按首次出现的先后
+    #[arg(long, value_enum, default_value_t = Tiebreak::LineAsc)]
+    tiebreak: Tiebreak,
+
+    /// 与 --sort-by line 同时使用时把行解析为数字排序，无法解析的行排在最后
+    #[arg(long)]
+    numeric: bool,
+
+    /// 与 --sort-by line 同时使用时比较行内容的方式：byte 按字节（默认，最快），
+    /// locale 按 LC_ALL、LC_COLLATE 或 LANG 指定语言的排序规则，带重音的字母和中日韩文字的顺序更符合习惯
+    #[arg(long, value_enum, default_value_t = Collation::Byte, conflicts_with = "no_sort")]
+    collation: Collation,
+
+    /// 升序排序，默认降序；与 --sort-by 组合可得到按次数或按行内容（字典序）的升序、降序四种排序
+    #[arg(long)]
+    ascending: bool,
+
+    /// 跳过空行，与 --trim 同时使用时也跳过只含空白的行
+    #[arg(long)]
+    skip_empty: bool,
+
+    /// 把输入的第一行视为表头，不参与统计（标准输入同样适用）
+    #[arg(long)]
+    skip_header: bool,
+
+    /// 每个输入的前 N 行不参与统计（包括表头），行号仍从第一行算起；与 --head、--tail 不同，截取的是输入而不是输出
+    #[arg(long, default_value_t = 0)]
+    skip: usize,
+
+    /// 每个输入跳过 --skip 行之后最多统计 M 行，之后不再读取；截取输入时需要逐行读取，不能分块并行
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// 增加 Percent 列，表示该行占全部行数的百分比
+    #[arg(long)]
+    percent: bool,
+
+    /// 增加 Cumulative 列，表示按输出顺序到该行为止的出现次数累计，便于做帕累托分析
+    #[arg(long)]
+    cumulative: bool,
+
+    /// 增加 FirstSeen 列，表示该行首次出现的行号（从 1 开始，多个文件连续编号，
+    /// 来自 --merge-with 的行记为 0）
+    #[arg(long)]
+    first_seen: bool,
+
+    /// 增加 Positions 列，列出每行出现过的所有行号（从 1 开始，多个文件连续编号），用逗号分隔
+    /// 需要为每次出现保存行号，内存占用随输入行数增长，可用 --max-positions 限制
+    #[arg(long)]
+    positions: bool,
+
+    /// 每行最多记录的行号个数，超出时只保留最早的几个；出现次数仍按全部统计
+    #[arg(long)]
+    max_positions: Option<usize>,
+
+    /// 增加 Tier 列，按逗号分隔的分界值把出现次数分档，例如 10,100,1000 得到 1-9、10-99、100-999、1000+ 四档
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u64).range(2..))]
+    tiers: Vec<u64>,
+
+    /// 在每行最后增加 Source 列，值都为该标签，例如数据来源的名称，使多次运行的结果拼接后仍能区分各行的出处；
+    /// 适用于所有输出格式
+    #[arg(long, conflicts_with_all = ["compare", "matrix", "dedup"])]
+    tag: Option<String>,
+
+    /// 在 Line 列之后增加 Key 列，输出分组用的 key（经过 --ignore-case、--trim、--normalize-unicode 等处理），
+    /// 此时 Line 列为每组首次出现时只做列选择的原始内容，便于核对不同写法是如何被合并的
+    #[arg(long, conflicts_with_all = ["hash_keys", "mode", "explode", "dedup"])]
+    show_key: bool,
+
+    /// 按逗号分隔的列名指定输出哪些列及其顺序，例如 count,line 把次数放在前面，默认为 line,count；
+    /// 可用的列名为 line、key、count、percent、cumulative、first-seen、tier（需要 --tiers）、positions、source（需要 --tag），
+    /// 指定后不能再用 --percent 等选项增加列
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with_all = ["show_key", "percent", "cumulative", "first_seen", "positions"])]
+    columns: Option<Vec<ResultColumn>>,
+
+    /// 只输出 Line 一列，不输出次数，仍按出现次数降序排列；与 --dedup 不同，--dedup 按首次出现的顺序输出，
+    /// 相当于 --columns line
+    #[arg(long, conflicts_with_all = [
+        "columns", "show_key", "percent", "cumulative", "first_seen", "positions", "tiers", "compare", "matrix", "dedup",
+    ])]
+    no_count: bool,
+
+    /// 只统计匹配该正则表达式的行
+    #[arg(long, value_parser = Regex::new)]
+    include: Option<Regex>,
+
+    /// 与 grep -v 类似，反转 --include 的匹配结果，只统计不匹配的行；没有指定 --include 时不起作用
+    #[arg(short = 'v', long)]
+    invert: bool,
+
+    /// 不统计匹配该正则表达式的行
+    #[arg(long, value_parser = Regex::new)]
+    exclude: Option<Regex>,
+
+    /// 不统计该文件中列出的行（每行一个），比较前按与统计时相同的方式处理，例如开启 --trim、--ignore-case 时同样去空白、忽略大小写；
+    /// 指定 --column 等选项时与选出的内容比较
+    #[arg(long)]
+    ignore_lines_from: Option<String>,
+
+    /// 使用内存映射读取输入文件，适合超大文件；标准输入、gzip 或 zstd 文件和非 UTF-8 编码会自动退回普通读取
+    #[arg(long)]
+    mmap: bool,
+
+    /// 只输出恰好出现一次的行
+    #[arg(long)]
+    unique_only: bool,
+
+    /// 只输出重复出现（次数大于 1）的行，不能与 --unique-only 同时使用
+    /// 没有输出任何行时以退出码 1 结束
+    #[arg(long, conflicts_with = "unique_only")]
+    duplicates_only: bool,
+
+    /// CSV 表头中行内容一列的名称，默认按行统计时为 Line，按字符统计时为 Char，按词统计时为 Word
+    #[arg(long)]
+    line_header: Option<String>,
+
+    /// CSV 表头中出现次数一列的名称
+    #[arg(long, default_value = "Count")]
+    count_header: String,
+
+    /// CSV 输出的字段分隔符，行内容包含分隔符时会用双引号包裹
+    #[arg(long, default_value = ",")]
+    output_delimiter: String,
+
+    /// 输出时用该字符串代替空的行内容（例如 --column 选中的字段为空），如 "<EMPTY>"；统计时所有空值仍合并为一行
+    #[arg(long)]
+    empty_placeholder: Option<String>,
+
+    /// CSV 字段的引号规则：minimal 只在需要时加引号，always 所有字段都加引号，
+    /// never 从不加引号，字段包含分隔符或换行时报错
+    #[arg(long, value_enum, default_value_t = Quoting::Minimal)]
+    quoting: Quoting,
+
+    /// 输出使用 Windows 风格的 \r\n 换行，供要求 CRLF 的旧系统读取
+    #[arg(long)]
+    crlf: bool,
+
+    /// 输出便于 Excel 直接打开的文件：开头写入 UTF-8 BOM 使非 ASCII 字符不会乱码，并使用 \r\n 换行；
+    /// 追加到非空文件时不再写入 BOM
+    #[arg(long)]
+    excel: bool,
+
+    /// 不写入 CSV 表头，便于追加到已有文件
+    #[arg(long)]
+    no_header: bool,
+
+    /// 输入文件的编码，例如 utf-8、gbk、gb18030
+    #[arg(long, default_value = "utf-8", value_parser = parse_encoding)]
+    encoding: &'static Encoding,
+
+    /// 跳过以该前缀开头的注释行，例如 "#"；与 --trim 同时使用时忽略前导空白
+    #[arg(long)]
+    comment: Option<String>,
+
+    /// 追加到已有的输出文件末尾而不是覆盖，文件非空时不再写入表头
+    #[arg(long)]
+    append: bool,
+
+    /// 追加到非空文件时仍然写入表头，默认只在文件为空或不存在时写入
+    #[arg(long, requires = "append")]
+    force_header: bool,
+
+    /// 在数据之后写入汇总：不同行数与出现次数总和
+    /// CSV、TSV 追加一行 TOTAL,<不同行数>,<总次数>，多出的列留空，输出不足 3 列（例如默认的 Line,Count）时
+    /// 放不下汇总行，改为输出到 stderr；JSON 输出改为带 data 与 summary 字段的对象
+    #[arg(long)]
+    summary: bool,
+
+    /// 输入为 .zip 压缩包时读取的条目名，不指定时压缩包中只能有一个文件
+    #[arg(long)]
+    zip_entry: Option<String>,
+
+    /// 另外输出出现次数的分布：每个出现次数有多少个不同的行（Count,Lines 两列，按次数升序），
+    /// 默认写到标准错误
+    #[arg(long)]
+    histogram: bool,
+
+    /// 把 --histogram 的分布写到该文件而不是标准错误，传入 "-" 时写到标准输出
+    #[arg(long, requires = "histogram")]
+    histogram_path: Option<String>,
+
+    /// 成功完成后把本次运行的元数据写成 JSON 文件：版本号、开始时间、完整的命令行参数、输入文件、结果文件、
+    /// 读取的行数、不同的行数、写入的行数和耗时，用于记录结果是如何得到的；被中断时不写入
+    #[arg(long, conflicts_with_all = ["dry_run", "count_lines_only"])]
+    manifest: Option<String>,
+
+    /// 以 NUL 字符（\0）而不是换行分隔输入记录，配合 find -print0 等命令使用
+    #[arg(short = 'z', long)]
+    null_data: bool,
+
+    /// 只统计长度不少于该值的行，长度按字符数计算，在列拆分、去空白等处理之后判断
+    #[arg(long)]
+    min_length: Option<usize>,
+
+    /// 只统计长度不超过该值的行，长度按字符数计算
+    #[arg(long)]
+    max_length: Option<usize>,
+
+    /// 只保存每行内容的 128 位哈希，输出中用十六进制摘要代替原文，适合大量很长且几乎不重复的行
+    /// 不同的行哈希碰撞时会被合并统计，但 128 位哈希的碰撞概率极低，实际可以忽略
+    #[arg(long, conflicts_with = "mode")]
+    hash_keys: bool,
+
+    /// 统计后重新读取一遍输入，检查 --hash-keys 下是否有内容不同的行因摘要相同被合并统计，发现时在 stderr 报告两行的位置和内容；
+    /// 检查时为每个不同的 key 保存一份原文用于逐字比较，内存占用与不加 --hash-keys 时相当，不能用于标准输入
+    #[arg(long, requires = "hash_keys")]
+    detect_collisions: bool,
+
+    /// 只随机抽取 N 个不同的行输出，并给出它们的准确出现次数，内存占用与 N 成正比
+    #[arg(long)]
+    sample: Option<usize>,
+
+    /// 最多单独统计 N 个不同的行，之后新出现的行合并计入 <OTHER> 一行，已有的行照常计数，用于限制内存占用
+    /// 保留的是最先出现的 N 个行，次数准确；为此只用一个线程顺序统计，忽略 --concurrency
+    #[arg(long, conflicts_with_all = ["sample", "dedup"])]
+    max_distinct: Option<usize>,
+
+    /// 抽样的随机种子，指定后每次运行的抽样结果相同，默认每次随机
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// 遇到无效的 UTF-8 字节时替换为 U+FFFD（�）继续统计，默认会报错退出
+    #[arg(long)]
+    lossy: bool,
+
+    /// 严格模式：遇到无效的 UTF-8、字段数与 --expected-columns 不一致（未指定时为 --column 选中的列不存在）
+    /// 或权重无效的行时不再跳过，而是报告文件名、行号及原因后以错误退出，不写入结果；用于在流水线中检查数据质量
+    #[arg(long, conflicts_with_all = ["lossy", "skip_mismatched", "dedup"])]
+    strict: bool,
+
+    /// 每行最多保留的字节数，超出的部分直接截断（不会截断在多字节字符中间）后再统计，
+    /// 避免没有换行的超大输入耗尽内存
+    #[arg(long)]
+    max_line_len: Option<usize>,
+
+    /// 分两遍统计以节省内存，只输出出现不止一次的行，适合绝大多数行都不重复的超大文件
+    /// 第一遍用布隆过滤器（按输入字节数分配，约占输入大小的 1/4 内存）找出可能重复的行，
+    /// 第二遍只统计这些行；误判只会多占内存，不影响结果的准确性；输入会被读取两次，不能与标准输入同时使用
+    #[arg(long)]
+    repeats_only_memory: bool,
+
+    /// 只去重不统计：按输入顺序输出每行第一次出现时的原文，没有表头和次数列，相当于 awk '!seen[$0]++'
+    /// 指定 --column、--ignore-case 等选项时按处理后的内容判断是否重复，输出的仍是整行原文
+    #[arg(long, conflicts_with_all = ["merge_with", "repeats_only_memory", "sample", "histogram", "mode"])]
+    dedup: bool,
+
+    /// 读取已有的结果文件（Line,Count 格式）作为初始统计，在其基础上累加本次输入
+    /// 传入 --checkpoint-every 保存的检查点时，检查点记录的那个文件只统计其中尚未统计过的行
+    #[arg(long)]
+    merge_with: Option<String>,
+
+    /// 预期出现的 key 列表文件，每行一个，按 --ignore-case、--trim 等选项处理，忽略空行；
+    /// 输入中没有出现的 key 也会输出一行，次数为 0，便于监控某个值的缺失
+    #[arg(long, conflicts_with = "dedup")]
+    expect_keys_from: Option<String>,
+
+    /// 把结果按 key 的哈希拆分到 N 个文件 <--shard-prefix>-0.csv … <--shard-prefix>-(N-1).csv，
+    /// 代替 --result-path；同一个 key 总在同一个文件中，每个文件各自带表头、各自排序，
+    /// 扩展名和压缩方式与不拆分时输出的文件相同
+    #[arg(long, conflicts_with_all = ["compare", "dedup"])]
+    shards: Option<usize>,
+
+    /// 分片文件名的前缀，可以包含目录
+    #[arg(long, default_value = "shard", requires = "shards")]
+    shard_prefix: String,
+
+    /// 与另一个输入对比：分别统计 --file-path 和该文件，输出 Line,CountA,CountB,Delta，
+    /// Delta 为前者的次数减去后者的次数，只在一边出现的行另一边记为 0，按 Delta 的绝对值降序排列
+    #[arg(long, conflicts_with_all = ["dedup", "dry_run"])]
+    compare: Option<String>,
+
+    /// 分别统计每个输入文件，输出一张宽表：第一列为行内容，之后每个文件一列，列名为文件名，
+    /// 值为该行在这个文件中的次数，没有出现时为 0；按各文件次数之和降序排列
+    #[arg(long, conflicts_with_all = [
+        "compare", "dedup", "shards", "dry_run", "checkpoint_every", "merge_with", "expect_keys_from",
+    ])]
+    matrix: bool,
+
+    /// 分组取前 K 名：按 --group-column 分组，统计每组中 --value-column 各个值的出现次数，
+    /// 输出 Group,Value,Count 三列，每组只保留次数最多的 K 行；分组按名称升序，组内按次数降序
+    #[arg(long, requires_all = ["group_column", "value_column"], conflicts_with_all = [
+        "column", "column_name", "capture", "field_range", "key_prefix_len", "strip_quotes", "mode", "explode",
+        "hash_keys", "time_bucket", "show_key", "compare", "matrix", "shards", "dedup",
+    ])]
+    top_per_group: Option<usize>,
+
+    /// --top-per-group 分组所在的列，从 0 开始，按输入分隔符拆分
+    #[arg(long, requires = "top_per_group")]
+    group_column: Option<usize>,
+
+    /// --top-per-group 在每组中统计的值所在的列，从 0 开始，按输入分隔符拆分
+    #[arg(long, requires = "top_per_group")]
+    value_column: Option<usize>,
+
+    /// 每读取 N 行，把目前为止的完整统计结果及读到的位置（文件和行号）保存到 --checkpoint-path
+    /// 先写临时文件再重命名，检查点不会只写了一半；运行中断或崩溃后用 --merge-with <检查点> 加上
+    /// 检查点记录的文件及其后尚未统计的文件即可继续，正常结束后检查点会被删除
+    /// 为使检查点恰好对应输入的前若干行，启用后在一个线程中顺序统计，--concurrency 与 --mmap 不再生效
+    #[arg(long, conflicts_with_all = ["sample", "repeats_only_memory", "dedup"])]
+    checkpoint_every: Option<usize>,
+
+    /// 检查点文件的路径，格式为 Line,Count 两列的 CSV
+    #[arg(long, default_value = "checkpoint.csv", requires = "checkpoint_every")]
+    checkpoint_path: String,
+
+    /// 监视模式：输入文件变化时重新统计并覆盖结果，按 Ctrl-C 退出；不能与标准输入同时使用
+    #[arg(long)]
+    watch: bool,
+
+    /// 只在 stdout 输出所有输入的总行数，不统计也不写入结果，相当于会自动解压、转换编码的 wc -l
+    #[arg(long, conflicts_with_all = ["dedup", "compare", "shards", "dry_run"])]
+    count_lines_only: bool,
+
+    /// 只统计不写入：在 stderr 输出不同行数和出现次数最多的 5 行，不会创建或修改结果文件
+    #[arg(long, conflicts_with_all = ["dedup", "checkpoint_every", "watch", "quiet"])]
+    dry_run: bool,
+
+    /// 在 stderr 输出各阶段的耗时：预扫描输入大小、读取统计、排序、写入，用于判断瓶颈所在；
+    /// 指定 --merge-with 时另外列出读取已有结果的耗时
+    #[arg(long)]
+    timing: bool,
+
+    /// 每处理多少行更新一次进度条（内存映射读取和写入结果时），数值越大开销越小，进度显示越不连续
+    #[arg(long, default_value_t = 10_000)]
+    progress_interval: usize,
+
+    /// 写入结果时每 N 行刷新一次输出缓冲区，结果写到标准输出供下游实时读取时尽快交付每一行，代价是吞吐量下降；
+    /// 默认只在写完后刷新
+    #[arg(long)]
+    flush_interval: Option<usize>,
+
+    /// 标准输入、gzip、zstd 等无法分块的输入由一个线程读取后分发给统计线程，读取线程最多领先统计线程这么多行，
+    /// 用于限制统计较慢时缓存的内存
+    #[arg(long, default_value_t = 4096)]
+    channel_capacity: usize,
+
+    /// 读取输入时的缓冲区字节数，网络文件系统等高延迟存储上调大（例如 1048576）可以大幅减少系统调用；
+    /// 对 --mmap 读取的文件无效
+    #[arg(long, default_value_t = DEFAULT_READ_BUFFER)]
+    read_buffer: usize,
+
+    /// 进度的显示方式：bar 在终端中绘制进度条（stderr 不是终端时不显示），
+    /// json 定期向 stderr 输出 {"phase":"read","processed":N,"total":M} 形式的 JSON 行，供其他程序解析；
+    /// 读取阶段的单位为字节，写入阶段为行，总数未知时 total 为 null
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bar, conflicts_with = "quiet")]
+    progress_format: ProgressFormat,
+
+    /// 不在终端显示进度，改为定期向该文件追加一行 "<时间戳> <阶段> 已处理/总数"，供无终端的自动化环境记录日志；
+    /// 阶段为 read 或 write，读取阶段的单位为字节，写入阶段为行，总数未知时为 ?；每个阶段结束时写入最终进度
+    #[arg(long, conflicts_with = "progress_format")]
+    progress_to_file: Option<String>,
+
+    /// 自定义进度条的 indicatif 模板，代替内置的模板，例如 "{wide_bar} {percent}% {msg}"；
+    /// 读取阶段的位置为字节数，写入阶段为行数，大小未知时同样用于 spinner
+    #[arg(long, value_parser = parse_progress_template, conflicts_with = "quiet")]
+    progress_template: Option<String>,
+
+    /// 安静模式，不显示进度条和结束时的汇总，只输出错误信息
+    #[arg(short, long)]
+    quiet: bool,
 }
 
-fn main() {
+// 退出码：0 表示成功，1 表示 --duplicates-only 没有找到重复行，2 表示出错，130 表示被中断
+fn main() -> ExitCode {
     let args = Args::parse();
+    let stdin = args.file_path.iter().any(|file_path| file_path == "-");
+    if args.watch && stdin {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--watch cannot be used with standard input",
+            )
+            .exit();
+    }
+    if (args.word_regex.is_some() || args.stopwords.is_some()) && args.mode != CountMode::Words {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--word-regex and --stopwords require --mode words",
+            )
+            .exit();
+    }
+    if args.explode.as_deref() == Some("") {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::InvalidValue,
+                "--explode requires a non-empty delimiter",
+            )
+            .exit();
+    }
+    if args.max_positions.is_some() && !output_positions(&args) {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--max-positions requires --positions or positions in --columns",
+            )
+            .exit();
+    }
+    if args.shards == Some(0) {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ValueValidation,
+                "--shards must be at least 1",
+            )
+            .exit();
+    }
+    if args.top_per_group == Some(0) {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ValueValidation,
+                "--top-per-group must be at least 1",
+            )
+            .exit();
+    }
+    if args.flush_interval == Some(0) {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ValueValidation,
+                "--flush-interval must be at least 1",
+            )
+            .exit();
+    }
+    if let Some(columns) = &args.columns {
+        if columns.contains(&ResultColumn::Tier) == args.tiers.is_empty() {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "tier in --columns and --tiers must be used together",
+                )
+                .exit();
+        }
+        if columns.contains(&ResultColumn::Source) == args.tag.is_none() {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "source in --columns and --tag must be used together",
+                )
+                .exit();
+        }
+    }
+    if args.repeats_only_memory && stdin {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--repeats-only-memory cannot be used with standard input",
+            )
+            .exit();
+    }
+    if args.detect_collisions && stdin {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--detect-collisions cannot be used with standard input",
+            )
+            .exit();
+    }
+
+    if !args.watch {
+        // 第一次 Ctrl-C 停止读取并写入已统计的部分，再按一次立即退出
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let flag = interrupted.clone();
+        if let Err(err) = ctrlc::set_handler(move || {
+            if flag.swap(true, Ordering::SeqCst) {
+                std::process::exit(INTERRUPTED_EXIT_CODE.into());
+            }
+        }) {
+            eprintln!("error: {}", err);
+            return ExitCode::from(2);
+        }
+        return report(run(&args, Some(&interrupted)));
+    }
+    report(run(&args, None));
+    match watch(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+// 输出错误信息并转换为退出码
+fn report(result: Result<ExitCode>) -> ExitCode {
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
 
-    // 获取文件行数
-    let total_lines = count_lines(&args.file_path).unwrap();
+// 监视模式下的事件：输入文件变化或按下 Ctrl-C
+enum WatchEvent {
+    Changed(notify::Result<notify::Event>),
+    Quit,
+}
 
-    // 创建 channel 用于传递数据
-    let (data_sender, data_receiver) = std::sync::mpsc::channel();
-    // 使用 Arc<Mutex<_>> 包裹 data_receiver
-    let data_receiver = Arc::new(Mutex::new(data_receiver));
-    // 创建进度条 (修正后的代码)
-    let pb = ProgressBar::new(total_lines as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
-            .unwrap() // 处理潜在的错误
-            .progress_chars("#>-"),
+// 等待多久没有新的变化才重新统计，避免连续写入时反复运行
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+// 监视输入文件，文件变化时重新统计并写入结果，按 Ctrl-C 在两次统计之间退出
+// 监视的是文件所在的目录，编辑器先写临时文件再重命名的保存方式同样能被发现
+fn watch(args: &Args) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let quit_sender = sender.clone();
+    ctrlc::set_handler(move || {
+        let _ = quit_sender.send(WatchEvent::Quit);
+    })?;
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(WatchEvent::Changed(event));
+    })?;
+
+    let mut inputs = HashSet::new();
+    for file_path in expand_inputs(&args.file_path)? {
+        let path = Path::new(&file_path);
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let parent = parent.canonicalize()?;
+        watcher.watch(&parent, RecursiveMode::NonRecursive)?;
+        if let Some(file_name) = path.file_name() {
+            inputs.insert(parent.join(file_name));
+        }
+    }
+
+    loop {
+        // 等待输入文件发生变化，结果文件等其他文件的变化会被忽略
+        match receiver.recv()? {
+            WatchEvent::Quit => return Ok(()),
+            WatchEvent::Changed(event) => {
+                if !is_input_change(&event?, &inputs) {
+                    continue;
+                }
+            }
+        }
+        // 直到一段时间内输入文件没有新的变化才重新统计；
+        // 同一目录中其他文件的事件（例如读取结果文件）不推迟统计，否则持续的访问会让统计一直无法开始
+        let mut deadline = Instant::now() + WATCH_DEBOUNCE;
+        loop {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(WatchEvent::Quit) => return Ok(()),
+                Ok(WatchEvent::Changed(Ok(event))) if is_input_change(&event, &inputs) => {
+                    deadline = Instant::now() + WATCH_DEBOUNCE;
+                }
+                Ok(WatchEvent::Changed(_)) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        // 单次统计出错（例如文件正在被替换）时只输出错误，继续监视
+        report(run(args, None));
+    }
+}
+
+// 判断事件是否修改了某个输入文件，只读访问不算修改
+fn is_input_change(event: &notify::Event, inputs: &HashSet<PathBuf>) -> bool {
+    let modified = matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Remove(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
     );
-    pb.set_message("写入中");
-    // 启动并发线程统计数据
-    let data_count = Arc::new(Mutex::new(HashMap::new()));
-    let mut handles = vec![];
-    for _ in 0..args.concurrency {
-        let data_count = data_count.clone();
-        // 克隆 data_receiver 的 Arc 指针
-        let data_receiver = data_receiver.clone();
-        let handle = thread::spawn(move || {
-            // 在循环中使用 lock() 获取 data_receiver
-            for line in data_receiver.lock().unwrap().iter() {
-                let mut data_count = data_count.lock().unwrap();
-                *data_count.entry(line).or_insert(0) += 1;
+    modified && event.paths.iter().any(|path| inputs.contains(path))
+}
+
+// 被 Ctrl-C 中断时的退出码，与 shell 中 128 + SIGINT 的约定一致
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+// 监视模式下每次统计都会完整执行，Ctrl-C 由 watch 处理，interrupted 为 None
+fn run(args: &Args, interrupted: Option<&Arc<AtomicBool>>) -> Result<ExitCode> {
+    let started = Instant::now();
+    let started_at = Local::now().to_rfc3339_opts(SecondsFormat::Secs, false);
+    let file_paths = expand_inputs(&args.file_path)?;
+    let concurrency = if args.deterministic {
+        1
+    } else {
+        args.concurrency.unwrap_or_else(default_concurrency).max(1)
+    };
+
+    // 以所有文件的字节数之和作为进度条总长，标准输入无法获取大小
+    // 预扫描只用于显示进度，获取大小失败时进度条退回 spinner，无法读取的文件留给统计阶段报错
+    let prescan = Instant::now();
+    let mut total_bytes = Some(0);
+    for file_path in file_paths.iter().chain(&args.compare) {
+        total_bytes = match (total_bytes, input_len(file_path).ok().flatten()) {
+            (Some(total), Some(len)) => Some(total + len),
+            _ => None,
+        };
+    }
+    let prescan_elapsed = prescan.elapsed();
+    // 汇总中与实际读取的字节数对比，用于发现读取不完整
+    let input_bytes = total_bytes;
+    // 只统计前几行时读取不到输入末尾，总长未知，进度条显示为 spinner
+    if args.limit.is_some() {
+        total_bytes = None;
+    }
+
+    // stderr 不是终端（例如 CI、cron 或重定向到文件）时同样不显示进度条，JSON 进度不受影响
+    let progress = match args.progress_format {
+        _ if args.quiet => None,
+        ProgressFormat::Bar if !std::io::stderr().is_terminal() => None,
+        progress_format => Some(progress_format),
+    };
+
+    let template = args.progress_template.as_deref();
+    let progress_log = match &args.progress_to_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|source| Error::Write {
+                    path: path.clone(),
+                    source,
+                })?;
+            Some(Arc::new(Mutex::new(file)))
+        }
+        None => None,
+    };
+    let progress_log = progress_log.as_ref();
+    let pb = read_progress_bar(total_bytes, progress, template, progress_log);
+    pb.set_message("读取中");
+    let mut count_options = CountOptions {
+        mode: args.mode,
+        word_regex: args.word_regex.clone(),
+        stopwords: match &args.stopwords {
+            Some(stopwords) => load_stopwords(stopwords, args.ignore_case || args.to_lowercase)?,
+            None => Default::default(),
+        },
+        explode: args.explode.clone(),
+        // 忽略列表需要按这些选项处理，在下面读取
+        ignore_lines: Default::default(),
+        ignore_case: args.ignore_case,
+        trim: args.trim,
+        normalize_unicode: args.normalize_unicode,
+        collapse_whitespace: args.collapse_whitespace,
+        to_lowercase: args.to_lowercase,
+        to_uppercase: args.to_uppercase,
+        // 分组取前 K 名时按分组列和值列的组合统计，写入时再拆开
+        column: match (args.group_column, args.value_column) {
+            (Some(group_column), Some(value_column)) => vec![group_column, value_column],
+            _ => args.column.clone(),
+        },
+        column_name: args.column_name.clone(),
+        input_delimiter: args.input_delimiter.clone(),
+        capture: args.capture.clone(),
+        field_range: args.field_range.clone(),
+        key_prefix_len: args.key_prefix_len,
+        expected_columns: args.expected_columns,
+        time_bucket: args.time_bucket,
+        timestamp_column: args.timestamp_column.unwrap_or_default(),
+        weight_column: args.weight_column,
+        weight_regex: args.weight_regex.clone(),
+        strip_quotes: args.strip_quotes,
+        missing_placeholder: args.missing_placeholder.clone(),
+        skip_mismatched: args.skip_mismatched,
+        skip_empty: args.skip_empty,
+        skip_header: args.skip_header,
+        skip: args.skip,
+        limit: args.limit,
+        include: args.include.clone(),
+        invert: args.invert,
+        exclude: args.exclude.clone(),
+        encoding: args.encoding,
+        comment: args.comment.clone(),
+        zip_entry: args.zip_entry.clone(),
+        null_data: args.null_data,
+        min_length: args.min_length,
+        max_length: args.max_length,
+        hash_keys: args.hash_keys,
+        sample: args.sample,
+        seed: args
+            .seed
+            .unwrap_or_else(|| if args.deterministic { 0 } else { random_seed() }),
+        lossy: args.lossy,
+        strict: args.strict,
+        max_line_len: args.max_line_len,
+        repeats_only_memory: args.repeats_only_memory,
+        progress_interval: args.progress_interval,
+        channel_capacity: args.channel_capacity,
+        read_buffer: args.read_buffer,
+        keep_original: output_key(args),
+        positions: output_positions(args).then(|| args.max_positions.unwrap_or(usize::MAX)),
+        max_distinct: args.max_distinct,
+        interrupted: interrupted.cloned(),
+        resume: None,
+    };
+    // 从检查点继续，标准输入只能读取一次，留给合并时读取
+    if let Some(merge_with) = args.merge_with.as_ref().filter(|path| *path != "-") {
+        count_options.resume = read_checkpoint_position(merge_with)?;
+    }
+    if let Some(ignore_lines_from) = &args.ignore_lines_from {
+        count_options.ignore_lines = load_ignore_lines(ignore_lines_from, &count_options)?;
+    }
+    // 统计结束后中断标志是否被置位，即结果是否只包含部分输入
+    let partial = || interrupted.is_some_and(|flag| flag.load(Ordering::SeqCst));
+    // 是否应当读完全部输入，中断或只读取前几行时读取的字节数本来就少于输入大小
+    let complete = || !partial() && args.limit.is_none();
+    if args.count_lines_only {
+        let lines = count_lines(&file_paths, &count_options, &pb)?;
+        pb.finish_and_clear();
+        println!("{}", lines);
+        return Ok(finish_code(args, partial(), ExitCode::SUCCESS));
+    }
+    if args.dedup {
+        let (written, stats) = dedup_file(
+            &file_paths,
+            &args.result_path,
+            output_compression(args),
+            args.append,
+            &count_options,
+            &pb,
+        )?;
+        pb.finish_with_message("完成");
+        warn_mismatched_columns(args, &stats);
+        if !args.quiet {
+            eprintln!(
+                "共读取 {} 行，其中不同的行 {} 个，耗时 {:.2?}",
+                stats.lines,
+                written,
+                started.elapsed()
+            );
+            print_throughput(stats.lines, pb.position(), started.elapsed());
+            print_bytes_read(pb.position(), input_bytes, complete());
+        }
+        if args.timing {
+            print_timing(&[
+                ("预扫描", prescan_elapsed),
+                ("读取去重写入", started.elapsed() - prescan_elapsed),
+            ]);
+        }
+        if let (Some(manifest_path), false) = (&args.manifest, partial()) {
+            let manifest = run_manifest(
+                args,
+                &file_paths,
+                &started_at,
+                started,
+                stats.lines,
+                written,
+                written,
+            );
+            write_manifest(manifest_path, &manifest)?;
+        }
+        return Ok(finish_code(args, partial(), ExitCode::SUCCESS));
+    }
+    // 吞吐量只按读取和统计阶段计算，不包括排序和写入
+    let counting = Instant::now();
+    // --matrix 时每个文件各自的统计结果
+    let mut matrix = Vec::new();
+    let (mut data_count, stats) = match args.checkpoint_every {
+        Some(every) => {
+            let mut checkpoint = |checkpoint: &Checkpoint| save_checkpoint(args, checkpoint, &pb);
+            count_file_with_checkpoint(
+                &file_paths,
+                concurrency,
+                args.mmap,
+                &count_options,
+                &pb,
+                Some((every, &mut checkpoint)),
+            )?
+        }
+        None if args.matrix => {
+            // 合计的结果只用于汇总信息
+            let mut data_count = CountMap::default();
+            let mut stats = CountStats::default();
+            for file_path in &file_paths {
+                let single = std::slice::from_ref(file_path);
+                let (file_count, file_stats) = if args.mmap {
+                    count_file_mmap(single, concurrency, &count_options, &pb)?
+                } else {
+                    count_file(single, concurrency, &count_options, &pb)?
+                };
+                merge_count_maps(&mut data_count, file_count.clone());
+                stats.merge(&file_stats);
+                matrix.push((file_path.clone(), file_count));
+                if partial() {
+                    break;
+                }
             }
-        });
-        handles.push(handle);
+            (data_count, stats)
+        }
+        None if args.mmap => count_file_mmap(&file_paths, concurrency, &count_options, &pb)?,
+        None => count_file(&file_paths, concurrency, &count_options, &pb)?,
+    };
+    let count_elapsed = counting.elapsed();
+    let mut timing = vec![("预扫描", prescan_elapsed), ("读取统计", count_elapsed)];
+    if let Some(merge_with) = &args.merge_with {
+        let merging = Instant::now();
+        merge_count_maps(&mut data_count, load_counts(merge_with, &count_options)?);
+        timing.push(("合并已有结果", merging.elapsed()));
+    }
+    if let Some(expect_keys_from) = &args.expect_keys_from {
+        insert_expected_keys(&mut data_count, expect_keys_from, &count_options)?;
     }
-    // 读取文件并分块发送数据
-    read_file(&args.file_path, data_sender, &pb).unwrap();
+    // 对比的文件用相同的选项单独统计
+    let other_count = match &args.compare {
+        Some(compare) => {
+            let comparing = Instant::now();
+            let compare = std::slice::from_ref(compare);
+            let (other_count, _) = if args.mmap {
+                count_file_mmap(compare, concurrency, &count_options, &pb)?
+            } else {
+                count_file(compare, concurrency, &count_options, &pb)?
+            };
+            timing.push(("统计对比文件", comparing.elapsed()));
+            Some(other_count)
+        }
+        None => None,
+    };
 
-    // 等待所有线程完成
-    for handle in handles {
-        handle.join().unwrap();
+    if args.detect_collisions && !partial() {
+        let checking = Instant::now();
+        // 第二遍读取重新从头推进进度条
+        pb.set_position(0);
+        let collisions = find_hash_collisions(&file_paths, &count_options, &pb)?;
+        timing.push(("检查哈希碰撞", checking.elapsed()));
+        warn_hash_collisions(args, &collisions);
     }
 
+    pb.finish_with_message("读取完成");
+
+    if args.dry_run {
+        print_dry_run(&data_count);
+        warn_mismatched_columns(args, &stats);
+        warn_invalid_weights(args, &stats);
+        print_throughput(stats.lines, pb.position(), count_elapsed);
+        if args.timing {
+            print_timing(&timing);
+        }
+        return Ok(finish_code(args, partial(), ExitCode::SUCCESS));
+    }
+
+    // 写入阶段使用单独的进度条，总数在过滤排序后确定
+    let write_pb = write_progress_bar(progress, template, progress_log);
+    write_pb.set_message("写入中");
+
     // 写入结果、排序并合并
-    write_sort_and_merge_result(
-        &args.result_path,
-        &mut data_count.lock().unwrap().clone(),
-        &pb,
-    )
-        .unwrap();
+    let write_options = WriteOptions {
+        top: args.top,
+        top_percent: args.top_percent,
+        head: args.head,
+        tail: args.tail,
+        no_sort: args.no_sort,
+        sort_buffer: args.sort_buffer,
+        stable_sort: args.stable_sort,
+        min_count: args
+            .min_count
+            .unwrap_or(if args.expect_keys_from.is_some() {
+                0
+            } else {
+                1
+            }),
+        format: args
+            .format
+            .unwrap_or_else(|| OutputFormat::from_path(&args.result_path)),
+        compression: output_compression(args),
+        sort_by: args.sort_by,
+        tiebreak: args.tiebreak,
+        numeric: args.numeric,
+        collation: args.collation,
+        ascending: args.ascending,
+        percent: args.percent,
+        cumulative: args.cumulative,
+        first_seen: args.first_seen,
+        positions: args.positions,
+        show_key: args.show_key,
+        shard: None,
+        unique_only: args.unique_only,
+        duplicates_only: args.duplicates_only,
+        line_header: args
+            .line_header
+            .clone()
+            .unwrap_or_else(|| default_line_header(args.mode).to_string()),
+        count_header: args.count_header.clone(),
+        no_header: args.no_header,
+        output_delimiter: args.output_delimiter.clone(),
+        quoting: args.quoting,
+        empty_placeholder: args.empty_placeholder.clone(),
+        append: args.append,
+        force_header: args.force_header,
+        summary: args.summary,
+        crlf: args.crlf || args.excel,
+        bom: args.excel,
+        tiers: sorted_tiers(&args.tiers),
+        tag: args.tag.clone(),
+        columns: if args.no_count {
+            let mut columns = vec![ResultColumn::Line];
+            columns.extend(args.tag.as_ref().map(|_| ResultColumn::Source));
+            Some(columns)
+        } else {
+            args.columns.clone()
+        },
+        progress_interval: args.progress_interval,
+        flush_interval: args.flush_interval,
+    };
+    let writing = Instant::now();
+    let (written, write_stats) = match &other_count {
+        Some(other_count) => (
+            write_comparison(
+                &args.result_path,
+                &data_count,
+                other_count,
+                &write_options,
+                &write_pb,
+            )?,
+            WriteStats::default(),
+        ),
+        None if args.matrix => (
+            write_matrix(&args.result_path, &matrix, &write_options, &write_pb)?,
+            WriteStats::default(),
+        ),
+        None if args.top_per_group.is_some() => (
+            write_top_per_group(
+                &args.result_path,
+                &data_count,
+                &args.input_delimiter,
+                args.top_per_group.unwrap_or_default(),
+                &write_options,
+                &write_pb,
+            )?,
+            WriteStats::default(),
+        ),
+        None => match args.shards {
+            Some(shards) => write_shards(
+                shards,
+                &data_count,
+                &write_options,
+                &args.shard_prefix,
+                &write_pb,
+            )?,
+            None => write_sort_and_merge_result(
+                &args.result_path,
+                &data_count,
+                &write_options,
+                &write_pb,
+            )?,
+        },
+    };
+    timing.push(("排序", write_stats.sort_elapsed));
+    timing.push(("写入", writing.elapsed() - write_stats.sort_elapsed));
+
+    write_pb.finish_with_message("完成");
+
+    // 汇总行与表头的列数不一致会让严格的 CSV 解析器报错，放不下时改为输出到 stderr
+    let summarized = other_count.is_none() && !args.matrix && args.top_per_group.is_none();
+    if args.summary && summarized && !write_options.summary_fits() {
+        let total: usize = data_count.values().map(|line_count| line_count.count).sum();
+        eprintln!("汇总：{} 个不同的行，共 {} 次", data_count.len(), total);
+    }
+
+    // 结果已经完整写入，检查点不再需要；中断时保留检查点以便继续统计
+    if args.checkpoint_every.is_some() && !partial() {
+        match std::fs::remove_file(&args.checkpoint_path) {
+            Err(source) if source.kind() != std::io::ErrorKind::NotFound => {
+                return Err(Error::Write {
+                    path: args.checkpoint_path.clone(),
+                    source,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    if args.histogram {
+        write_histogram(args.histogram_path.as_deref(), &data_count)?;
+    }
+
+    warn_mismatched_columns(args, &stats);
+    warn_invalid_weights(args, &stats);
+
+    // 在 stderr 输出一行汇总，安静模式下不输出
+    if !args.quiet {
+        let total: usize = data_count.values().map(|line_count| line_count.count).sum();
+        // 按权重统计时出现次数之和是权重之和，不是行数
+        let total = if args.weight_column.is_some() || args.weight_regex.is_some() {
+            format!("权重之和为 {}", total)
+        } else {
+            format!("共统计 {} 行", total)
+        };
+        eprintln!(
+            "{}，其中不同的行 {} 个，耗时 {:.2?}",
+            total,
+            data_count.len(),
+            started.elapsed()
+        );
+        print_throughput(stats.lines, pb.position(), count_elapsed);
+        print_bytes_read(pb.position(), input_bytes, complete());
+    }
+    if args.timing {
+        print_timing(&timing);
+    }
 
-    pb.finish_with_message("完成");
+    if let (Some(manifest_path), false) = (&args.manifest, partial()) {
+        let manifest = run_manifest(
+            args,
+            &file_paths,
+            &started_at,
+            started,
+            stats.lines,
+            data_count.len(),
+            written,
+        );
+        write_manifest(manifest_path, &manifest)?;
+    }
+
+    // 与 grep 类似，便于在脚本中用 if csvaction --duplicates-only ... 判断是否存在重复行
+    let code = if args.duplicates_only && written == 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    };
+    Ok(finish_code(args, partial(), code))
 }
 
-// 读取文件并将数据分块发送到 channel
-fn read_file(
-    file_path: &str,
-    data_sender: std::sync::mpsc::Sender<String>,
-    pb: &ProgressBar,
-) -> std::io::Result<()> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
+// 汇总本次运行的元数据，耗时从 started 算到现在
+fn run_manifest(
+    args: &Args,
+    file_paths: &[String],
+    started_at: &str,
+    started: Instant,
+    lines: usize,
+    distinct: usize,
+    written: usize,
+) -> Manifest {
+    Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started_at: started_at.to_string(),
+        arguments: std::env::args().skip(1).collect(),
+        inputs: file_paths.to_vec(),
+        result_path: args.result_path.clone(),
+        lines,
+        distinct,
+        written,
+        elapsed: started.elapsed(),
+    }
+}
 
-    for line in reader.lines() {
-        let line = line?;
-        data_sender.send(line).unwrap();
-        pb.inc(1);
+// 被中断时提示结果不完整并改用中断的退出码，否则原样返回 code
+fn finish_code(args: &Args, partial: bool, code: ExitCode) -> ExitCode {
+    if !partial {
+        return code;
+    }
+    if !args.quiet {
+        eprintln!("warning: 统计被中断，结果只包含中断前读取的部分");
     }
+    ExitCode::from(INTERRUPTED_EXIT_CODE)
+}
 
+// 保存检查点：结果文件总是先写临时文件再重命名，中途崩溃时检查点要么是上一次的，要么是这一次的
+fn save_checkpoint(args: &Args, checkpoint: &Checkpoint, pb: &ProgressBar) -> Result<()> {
+    write_checkpoint(&args.checkpoint_path, checkpoint)?;
+    if !args.quiet {
+        pb.suspend(|| {
+            eprintln!(
+                "已保存检查点 '{}'：已统计到 '{}' 的第 {} 行",
+                args.checkpoint_path, checkpoint.file_path, checkpoint.lines
+            )
+        });
+    }
     Ok(())
 }
 
-// 统计每行数据的出现次数
-fn count_data(
-    data_receiver: std::sync::mpsc::Receiver<String>,
-    data_count: Arc<Mutex<HashMap<String, usize>>>,
-) {
-    for line in data_receiver {
-        let mut data_count = data_count.lock().unwrap();
-        *data_count.entry(line).or_insert(0) += 1;
+// 在 stderr 逐行输出各阶段的耗时及其占比
+fn print_timing(phases: &[(&str, Duration)]) {
+    eprint!("{}", timing_report(phases));
+}
+
+// 各阶段耗时的报告，标题之后每个阶段一行
+fn timing_report(phases: &[(&str, Duration)]) -> String {
+    let total = phases
+        .iter()
+        .map(|(_, elapsed)| elapsed.as_secs_f64())
+        .sum::<f64>()
+        .max(f64::EPSILON);
+    let mut report = String::from("各阶段耗时：\n");
+    for (phase, elapsed) in phases {
+        let _ = writeln!(
+            report,
+            "  {:>12.2?} {:>6.1}%  {}",
+            elapsed,
+            elapsed.as_secs_f64() / total * 100.0,
+            phase
+        );
     }
+    report
 }
 
-// 将结果写入 CSV 文件、按 count 降序排序并合并重复数据
-fn write_sort_and_merge_result(
-    result_path: &str,
-    data_count: &mut HashMap<String, usize>,
-    pb: &ProgressBar,
-) -> std::io::Result<()> {
-    let mut data_count_list: Vec<DataCount> = data_count
+// 试运行时在 stderr 输出不同行数和出现次数最多的几行，次数相同时按行内容排序
+fn print_dry_run(data_count: &CountMap) {
+    const TOP: usize = 5;
+    let total: usize = data_count.values().map(|line_count| line_count.count).sum();
+    eprintln!(
+        "共统计 {} 行，其中不同的行 {} 个（试运行，未写入结果）",
+        total,
+        data_count.len()
+    );
+    let mut entries: Vec<_> = data_count
         .iter()
-        .map(|(line, count)| DataCount {
-            line: line.clone(),
-            count: *count,
-        })
+        .map(|(line, line_count)| (line_count.count, line))
         .collect();
+    let by_count =
+        |a: &(usize, &String), b: &(usize, &String)| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1));
+    // 只需要前几行，先部分选择再排序，避免对所有不同的行排序
+    if entries.len() > TOP {
+        entries.select_nth_unstable_by(TOP - 1, by_count);
+        entries.truncate(TOP);
+    }
+    entries.sort_unstable_by(by_count);
+    for (count, line) in entries {
+        eprintln!("{:>10}  {}", count, line);
+    }
+}
 
-    // 按 count 降序排序
-    data_count_list.sort_by(|a, b| b.count.cmp(&a.count));
+// 没有指定 --line-header 时按统计单元命名第一列
+fn default_line_header(mode: CountMode) -> &'static str {
+    match mode {
+        CountMode::Lines => "Line",
+        CountMode::Chars => "Char",
+        CountMode::Words => "Word",
+        CountMode::Bigrams => "Bigram",
+    }
+}
 
-    // 创建结果文件
-    let mut result_file = File::create(result_path)?;
+// 检查进度条模板能否被 indicatif 解析
+fn parse_progress_template(template: &str) -> std::result::Result<String, String> {
+    ProgressStyle::with_template(template)
+        .map(|_| template.to_string())
+        .map_err(|err| format!("invalid progress bar template: {}", err))
+}
 
-    // 写入 CSV 头部
-    writeln!(result_file, "Line,Count")?;
+// 解析 0 到 100 之间的百分比
+fn parse_percent(percent: &str) -> std::result::Result<f64, String> {
+    let percent: f64 = percent.parse().map_err(|err| format!("{}", err))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(String::from(
+            "percentage must be greater than 0 and at most 100",
+        ));
+    }
+    Ok(percent)
+}
 
-    // 写入排序后的数据
-    for data_count in data_count_list {
-        writeln!(
-            result_file,
-            "{},{}",
-            data_count.line, data_count.count
-        )?;
-        pb.inc(1);
+// 输出文件的压缩方式，文件名以 .zst 结尾时用 zstd，--compress 或以 .gz 结尾时用 gzip
+fn output_compression(args: &Args) -> OutputCompression {
+    match OutputCompression::from_path(&args.result_path) {
+        OutputCompression::None if args.compress => OutputCompression::Gzip,
+        compression => compression,
     }
+}
 
-    Ok(())
+// 依次写入每个分片，返回写入的总行数，排序耗时为各分片之和
+fn write_shards(
+    shards: usize,
+    data_count: &CountMap,
+    write_options: &WriteOptions,
+    shard_prefix: &str,
+    pb: &ProgressBar,
+) -> Result<(usize, WriteStats)> {
+    let mut written = 0;
+    let mut stats = WriteStats::default();
+    for index in 0..shards {
+        let shard_path = format!(
+            "{}-{}.{}{}",
+            shard_prefix,
+            index,
+            write_options.format.extension(),
+            write_options.compression.suffix()
+        );
+        let shard_options = WriteOptions {
+            shard: Some((index, shards)),
+            ..write_options.clone()
+        };
+        let (shard_written, shard_stats) =
+            write_sort_and_merge_result(&shard_path, data_count, &shard_options, pb)?;
+        written += shard_written;
+        stats.sort_elapsed += shard_stats.sort_elapsed;
+    }
+    Ok((written, stats))
+}
+
+// 是否输出 Key 列，输出时统计阶段需要保存每个 key 的原始内容
+fn output_key(args: &Args) -> bool {
+    args.show_key
+        || args
+            .columns
+            .as_ref()
+            .is_some_and(|columns| columns.contains(&ResultColumn::Key))
+}
+
+// 是否输出 Positions 列，输出时统计阶段需要记录行号
+fn output_positions(args: &Args) -> bool {
+    args.positions
+        || args
+            .columns
+            .as_ref()
+            .is_some_and(|columns| columns.contains(&ResultColumn::Positions))
+}
+
+// 分界值按升序排列并去重
+fn sorted_tiers(tiers: &[u64]) -> Vec<usize> {
+    let mut tiers: Vec<_> = tiers.iter().map(|tier| *tier as usize).collect();
+    tiers.sort_unstable();
+    tiers.dedup();
+    tiers
+}
+
+// 在 stderr 输出读取速度，bytes 为从输入源读取的字节数（压缩输入为压缩后的大小）
+fn print_throughput(lines: usize, bytes: u64, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    eprintln!(
+        "读取速度 {:.0} 行/秒，{:.2} MB/秒",
+        lines as f64 / seconds,
+        bytes as f64 / 1_000_000.0 / seconds
+    );
+}
+
+// 在 stderr 输出读取的字节数及输入文件的总大小，应当读完全部输入而两者不一致时提示，
+// 通常意味着读取被截断或文件在读取期间被修改
+fn print_bytes_read(bytes: u64, input_bytes: Option<u64>, complete: bool) {
+    match input_bytes {
+        Some(input_bytes) if complete && bytes != input_bytes => eprintln!(
+            "已读取 {} 字节，输入文件共 {} 字节，两者不一致，输入可能没有完整读取",
+            bytes, input_bytes
+        ),
+        Some(input_bytes) => eprintln!("已读取 {} 字节，输入文件共 {} 字节", bytes, input_bytes),
+        None => eprintln!("已读取 {} 字节，输入大小未知", bytes),
+    }
+}
+
+// 在 stderr 报告权重列无法解析而被跳过的行数
+fn warn_invalid_weights(args: &Args, stats: &CountStats) {
+    if stats.invalid_weights == 0 || args.quiet {
+        return;
+    }
+    match args.weight_column {
+        Some(column) => eprintln!(
+            "warning: {} 行的第 {} 列不是非负整数，已跳过",
+            stats.invalid_weights, column
+        ),
+        None => eprintln!(
+            "warning: {} 行不匹配 --weight-regex 或捕获的内容不是非负整数，已跳过",
+            stats.invalid_weights
+        ),
+    }
+}
+
+// 哈希碰撞会使不同的行被合并统计，在 stderr 列出每一处
+fn warn_hash_collisions(args: &Args, collisions: &[HashCollision]) {
+    if args.quiet {
+        return;
+    }
+    for collision in collisions {
+        eprintln!("{}", collision_warning(collision));
+    }
+}
+
+// 一处哈希碰撞的警告，指出内容不同的两行各自所在的文件、行号及内容
+fn collision_warning(collision: &HashCollision) -> String {
+    format!(
+        "warning: {} 第 {} 行的 {:?} 与 {} 第 {} 行的 {:?} 内容不同，但摘要同为 {}，已被合并统计",
+        collision.first.0,
+        collision.first.1,
+        collision.first_key,
+        collision.second.0,
+        collision.second.1,
+        collision.second_key,
+        collision.digest
+    )
+}
+
+// 字段数不一致通常意味着 CSV 格式有误，在 stderr 提示
+fn warn_mismatched_columns(args: &Args, stats: &CountStats) {
+    if let (Some(expected_columns), false) = (args.expected_columns, args.quiet) {
+        if stats.mismatched_columns > 0 {
+            eprintln!(
+                "warning: {} 行的字段数不是 {}{}",
+                stats.mismatched_columns,
+                expected_columns,
+                if args.skip_mismatched {
+                    "，已跳过"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+}
+
+// 默认线程数为可用的 CPU 核数，无法获取时使用单线程
+fn default_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// 未指定 --seed 时使用随机种子，借用标准库 HashMap 的随机键避免引入额外依赖
+fn random_seed() -> u64 {
+    RandomState::new().hash_one(0)
 }
 
-// 统计文件行数
-fn count_lines(file_path: &str) -> std::io::Result<u64> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    Ok(reader.lines().count() as u64)
+// 是否设置了非空的 NO_COLOR 环境变量，见 https://no-color.org
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+// 进度条使用的模板，用户通过 --progress-template 指定了模板时用它代替内置的 template
+// no_color 时去掉所有占位符中的颜色和样式，包括用户模板中的
+fn progress_template(template: &str, custom: Option<&str>, no_color: bool) -> String {
+    let template = custom.unwrap_or(template);
+    if no_color {
+        strip_template_styles(template)
+    } else {
+        template.to_string()
+    }
+}
+
+// 去掉模板中每个 {key:...} 占位符的样式部分，即第一个 '.' 及之后的颜色、粗体等，保留对齐和宽度；
+// 样式为空时连同冒号一起去掉，"{{" 是转义的花括号，原样保留
+fn strip_template_styles(template: &str) -> String {
+    let mut plain = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        plain.push_str(&rest[..open]);
+        rest = &rest[open..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            plain.push_str("{{");
+            rest = after;
+            continue;
+        }
+        let Some(close) = rest.find('}') else {
+            break;
+        };
+        let placeholder = &rest[1..close];
+        plain.push('{');
+        match placeholder.split_once(':') {
+            Some((key, spec)) => {
+                let layout = spec.split_once('.').map_or(spec, |(layout, _)| layout);
+                plain.push_str(key);
+                if !layout.is_empty() {
+                    plain.push(':');
+                    plain.push_str(layout);
+                }
+            }
+            None => plain.push_str(placeholder),
+        }
+        plain.push('}');
+        rest = &rest[close + 1..];
+    }
+    plain.push_str(rest);
+    plain
+}
+
+// 创建读取阶段的进度条，大小未知时退化为不定长的 spinner
+// 进度条始终绘制到 stderr，结果写到标准输出时不会混入 CSV
+fn read_progress_bar(
+    total_bytes: Option<u64>,
+    progress: Option<ProgressFormat>,
+    template: Option<&str>,
+    progress_log: Option<&Arc<Mutex<File>>>,
+) -> ProgressBar {
+    if let Some(progress_log) = progress_log {
+        return log_progress_bar(progress_log, total_bytes, "read");
+    }
+    match progress {
+        None => return ProgressBar::hidden(),
+        Some(ProgressFormat::Json) => return json_progress_bar(total_bytes, "read"),
+        Some(ProgressFormat::Bar) => {}
+    }
+    match total_bytes {
+        Some(total_bytes) => {
+            let pb = ProgressBar::new(total_bytes);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(&progress_template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}", template, no_color()))
+                    .unwrap() // 处理潜在的错误
+                    .progress_chars("#>-"),
+            );
+            pb
+        }
+        None => {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(
+                ProgressStyle::default_spinner()
+                    .template(&progress_template(
+                        "{spinner:.green} [{elapsed_precise}] {bytes} {msg}",
+                        template,
+                        no_color(),
+                    ))
+                    .unwrap(),
+            );
+            pb
+        }
+    }
+}
+
+// 创建写入阶段的进度条，长度由写入时的实际行数决定
+fn write_progress_bar(
+    progress: Option<ProgressFormat>,
+    template: Option<&str>,
+    progress_log: Option<&Arc<Mutex<File>>>,
+) -> ProgressBar {
+    if let Some(progress_log) = progress_log {
+        return log_progress_bar(progress_log, Some(0), "write");
+    }
+    match progress {
+        None => return ProgressBar::hidden(),
+        Some(ProgressFormat::Json) => return json_progress_bar(Some(0), "write"),
+        Some(ProgressFormat::Bar) => {}
+    }
+    let pb = ProgressBar::new(0);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&progress_template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}", template, no_color()))
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb
+}
+
+// 进度的显示方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ProgressFormat {
+    /// 在终端中绘制进度条
+    Bar,
+    /// 定期向 stderr 输出一行 JSON
+    Json,
+}
+
+// 每秒最多输出的 JSON 进度行数
+const JSON_PROGRESS_HZ: u8 = 2;
+
+// 创建以 JSON 行输出进度的进度条，phase 标明当前阶段
+fn json_progress_bar(len: Option<u64>, phase: &'static str) -> ProgressBar {
+    let target = ProgressDrawTarget::term_like_with_hz(
+        Box::new(LineProgress { log: None }),
+        JSON_PROGRESS_HZ,
+    );
+    let pb = ProgressBar::with_draw_target(len, target);
+    pb.set_style(ProgressStyle::with_template("{json}").unwrap().with_key(
+        "json",
+        move |state: &ProgressState, w: &mut dyn FmtWrite| {
+            let total = state
+                .len()
+                .map_or_else(|| String::from("null"), |len| len.to_string());
+            let _ = write!(
+                w,
+                "{{\"phase\":\"{}\",\"processed\":{},\"total\":{}}}",
+                phase,
+                state.pos(),
+                total
+            );
+        },
+    ));
+    pb
+}
+
+// 每秒最多写入日志文件的进度行数
+const PROGRESS_LOG_HZ: u8 = 1;
+
+// 创建把进度定期追加到日志文件的进度条，每行为本地时间戳、阶段及已处理/总数
+fn log_progress_bar(
+    progress_log: &Arc<Mutex<File>>,
+    len: Option<u64>,
+    phase: &'static str,
+) -> ProgressBar {
+    let log = Some(progress_log.clone());
+    let target =
+        ProgressDrawTarget::term_like_with_hz(Box::new(LineProgress { log }), PROGRESS_LOG_HZ);
+    let pb = ProgressBar::with_draw_target(len, target);
+    pb.set_style(ProgressStyle::with_template("{log}").unwrap().with_key(
+        "log",
+        move |state: &ProgressState, w: &mut dyn FmtWrite| {
+            let total = state
+                .len()
+                .map_or_else(|| String::from("?"), |len| len.to_string());
+            let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Secs, false);
+            let _ = write!(w, "{} {} {}/{}", timestamp, phase, state.pos(), total);
+        },
+    ));
+    pb
+}
+
+// LineProgress 把 indicatif 每次绘制的内容作为一行写到 log 文件，没有 log 时写到 stderr，忽略光标移动和清行
+// indicatif 会用空格把最后一行补齐到终端宽度，只含空白的内容直接丢弃
+#[derive(Debug)]
+struct LineProgress {
+    log: Option<Arc<Mutex<File>>>,
+}
+
+impl TermLike for LineProgress {
+    fn width(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn move_cursor_up(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, line: &str) -> std::io::Result<()> {
+        self.write_str(line)
+    }
+
+    fn write_str(&self, line: &str) -> std::io::Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        match &self.log {
+            Some(log) => writeln!(log.lock().unwrap(), "{}", line.trim_end()),
+            None => writeln!(std::io::stderr().lock(), "{}", line),
+        }
+    }
+
+    fn clear_line(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        match &self.log {
+            Some(log) => log.lock().unwrap().flush(),
+            None => std::io::stderr().flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_transforms_are_mutually_exclusive() {
+        let err =
+            Args::try_parse_from(["csvaction", "--to-lowercase", "--to-uppercase"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(Args::try_parse_from(["csvaction", "--to-uppercase"]).is_ok());
+    }
+
+    #[test]
+    fn default_concurrency_matches_available_cores() {
+        let cores = std::thread::available_parallelism().unwrap().get();
+        assert_eq!(default_concurrency(), cores);
+        let args = Args::try_parse_from(["csvaction"]).unwrap();
+        assert_eq!(args.concurrency, None);
+        let args = Args::try_parse_from(["csvaction", "-c", "3"]).unwrap();
+        assert_eq!(args.concurrency, Some(3));
+    }
+
+    // 把进度条绘制到内存中，便于检查写到终端的内容
+    #[derive(Clone, Debug, Default)]
+    struct CapturedTerm(Arc<Mutex<String>>);
+
+    impl TermLike for CapturedTerm {
+        fn width(&self) -> u16 {
+            120
+        }
+
+        fn move_cursor_up(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor_down(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor_right(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor_left(&self, _: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn write_line(&self, s: &str) -> std::io::Result<()> {
+            let mut output = self.0.lock().unwrap();
+            output.push_str(s);
+            output.push('\n');
+            Ok(())
+        }
+
+        fn write_str(&self, s: &str) -> std::io::Result<()> {
+            self.0.lock().unwrap().push_str(s);
+            Ok(())
+        }
+
+        fn clear_line(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn flush(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 按 template 绘制一个走到 40/100 的进度条，返回写到终端的全部内容
+    fn render_progress(template: &str) -> String {
+        let term = CapturedTerm::default();
+        let target = ProgressDrawTarget::term_like(Box::new(term.clone()));
+        let pb = ProgressBar::with_draw_target(Some(100), target);
+        pb.set_style(ProgressStyle::default_bar().template(template).unwrap());
+        pb.set_message("读取中");
+        pb.set_position(40);
+        pb.abandon();
+        let output = term.0.lock().unwrap().clone();
+        output
+    }
+
+    #[test]
+    fn no_color_emits_no_ansi_sequences() {
+        // console 只在认为终端支持颜色时输出转义序列，测试中强制开启，作为对照的彩色模板才会带颜色
+        console::set_colors_enabled(true);
+        let custom = "{spinner:.green} [{bar:40.red/white}] {msg:.bold} {pos:>3.cyan}/{len}";
+        let colored = render_progress(&progress_template("{bar}", Some(custom), false));
+        assert!(colored.contains("\x1b["), "{colored:?}");
+
+        let plain = progress_template("{bar}", Some(custom), true);
+        assert_eq!(plain, "{spinner} [{bar:40}] {msg} {pos:>3}/{len}");
+        let output = render_progress(&plain);
+        assert!(!output.contains("\x1b["), "{output:?}");
+        assert!(output.contains("读取中  40/100"), "{output:?}");
+        // 内置的模板同样不带颜色
+        let builtin =
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}";
+        let output = render_progress(&progress_template(builtin, None, true));
+        assert!(!output.contains("\x1b["), "{output:?}");
+    }
+
+    #[test]
+    fn timing_report_lists_every_phase() {
+        let report = timing_report(&[
+            ("预扫描", Duration::from_millis(1)),
+            ("读取统计", Duration::from_millis(6)),
+            ("排序", Duration::from_millis(1)),
+            ("写入", Duration::from_millis(2)),
+        ]);
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 5, "{report}");
+        for (line, phase) in lines[1..]
+            .iter()
+            .zip(["预扫描", "读取统计", "排序", "写入"])
+        {
+            assert!(line.ends_with(phase), "{line}");
+        }
+        assert!(lines[2].contains("60.0%"), "{report}");
+    }
+
+    #[test]
+    fn progress_template_is_validated() {
+        let template = "{wide_bar} {percent}% {msg}";
+        assert_eq!(parse_progress_template(template).unwrap(), template);
+        let err = parse_progress_template("{bar:abc}").unwrap_err();
+        assert!(err.starts_with("invalid progress bar template"), "{err}");
+        let err =
+            Args::try_parse_from(["csvaction", "--progress-template", "{bar:abc}"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn collision_warning_names_both_lines() {
+        let collision = HashCollision {
+            digest: String::from("00ff"),
+            first: (String::from("a.txt"), 3),
+            first_key: String::from("foo"),
+            second: (String::from("b.txt"), 7),
+            second_key: String::from("bar"),
+        };
+        assert_eq!(
+            collision_warning(&collision),
+            "warning: a.txt 第 3 行的 \"foo\" 与 b.txt 第 7 行的 \"bar\" 内容不同，但摘要同为 00ff，已被合并统计"
+        );
+    }
 }
@@ -0,0 +1,192 @@
+use indicatif::ProgressBar;
+
+use crate::count::{
+    digest_hex, list_line_key, merge_line_count, CountMap, CountOptions, LineCount,
+    DEFAULT_READ_BUFFER,
+};
+use crate::error::{Error, Result};
+use crate::input::{list_lines, open_input};
+use crate::output::{RESUME_FILE_PREFIX, RESUME_LINE_PREFIX};
+
+/// 读取已有的 Line,Count 结果文件作为初始统计，"-" 表示标准输入，gzip 压缩的文件会自动解压
+///
+/// 只使用前两列，其余列（如 Percent）会被忽略；第一行的次数列不是数字时视为表头。
+/// 开启忽略大小写时按统计阶段相同的规则合并，结果文件中的写法优先于新输入
+pub fn load_counts(result_path: &str, count_options: &CountOptions) -> Result<CountMap> {
+    let reader = open_input(
+        result_path,
+        encoding_rs::UTF_8,
+        count_options.read_buffer,
+        &ProgressBar::hidden(),
+    )?;
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+
+    let mut data_count = CountMap::default();
+    for (index, record) in csv_reader.records().enumerate() {
+        let parse_error = |message: String| Error::Parse {
+            path: result_path.to_string(),
+            line: index + 1,
+            message,
+        };
+        let record = record.map_err(|err| match err.kind() {
+            csv::ErrorKind::Io(_) => Error::Read(err.into()),
+            _ => parse_error(err.to_string()),
+        })?;
+        let (Some(line), Some(count)) = (record.get(0), record.get(1)) else {
+            return Err(parse_error(String::from("expected at least 2 columns")));
+        };
+        let count = match count.trim().parse::<usize>() {
+            Ok(count) => count,
+            Err(_) if index == 0 => continue,
+            Err(err) => return Err(parse_error(format!("invalid count '{}': {}", count, err))),
+        };
+
+        // 行号记为 0，保证合并时排在所有新输入之前
+        let (key, original) = if count_options.ignore_case {
+            let key = line.to_lowercase();
+            let original = (key != line).then(|| line.to_string());
+            (key, original)
+        } else {
+            (line.to_string(), None)
+        };
+        let line_count = LineCount {
+            count,
+            first_seen: 0,
+            original,
+            positions: Vec::new(),
+        };
+        merge_line_count(&mut data_count, key, line_count);
+    }
+    Ok(data_count)
+}
+
+/// 读取 [`write_checkpoint`](crate::write_checkpoint) 在检查点表头中记录的继续位置，
+/// 返回正在统计的文件及其中已经统计过的行数；普通的结果文件没有这两列，返回 None
+pub fn read_checkpoint_position(checkpoint_path: &str) -> Result<Option<(String, usize)>> {
+    let reader = open_input(
+        checkpoint_path,
+        encoding_rs::UTF_8,
+        DEFAULT_READ_BUFFER,
+        &ProgressBar::hidden(),
+    )?;
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let Some(header) = csv_reader.records().next() else {
+        return Ok(None);
+    };
+    let header = header.map_err(|err| match err.kind() {
+        csv::ErrorKind::Io(_) => Error::Read(err.into()),
+        _ => Error::Parse {
+            path: checkpoint_path.to_string(),
+            line: 1,
+            message: err.to_string(),
+        },
+    })?;
+    let lines = (header.get(2))
+        .and_then(|field| field.strip_prefix(RESUME_LINE_PREFIX))
+        .and_then(|lines| lines.parse().ok());
+    let file_path = (header.get(3)).and_then(|field| field.strip_prefix(RESUME_FILE_PREFIX));
+    Ok(file_path
+        .zip(lines)
+        .map(|(file_path, lines)| (file_path.to_string(), lines)))
+}
+
+/// 读取预期出现的 key 列表，每行一个，按统计时相同的方式（去空白、大小写等）处理，忽略空行；
+/// 统计结果中没有的 key 以次数 0 加入 data_count，便于发现本应出现却没有出现的值
+///
+/// 已经统计到的 key 保持不变；加入的 key 首次出现行号记为 0
+pub fn insert_expected_keys(
+    data_count: &mut CountMap,
+    file_path: &str,
+    count_options: &CountOptions,
+) -> Result<()> {
+    let reader = open_input(
+        file_path,
+        encoding_rs::UTF_8,
+        count_options.read_buffer,
+        &ProgressBar::hidden(),
+    )?;
+    for line in list_lines(reader) {
+        let line = line.map_err(Error::Read)?;
+        if line.is_empty() {
+            continue;
+        }
+        let (key, original) = list_line_key(&line, count_options);
+        // hash_keys 时统计结果以摘要为 key
+        let key = if count_options.hash_keys {
+            digest_hex(xxhash_rust::xxh3::xxh3_128(key.as_bytes()))
+        } else {
+            key
+        };
+        data_count.entry(key).or_insert(LineCount {
+            count: 0,
+            first_seen: 0,
+            original,
+            positions: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::count_occurrences;
+    use crate::output::{write_results, WriteOptions};
+    use crate::test_util::TestFile;
+
+    #[test]
+    fn expected_keys_absent_from_input_are_written_with_zero_count() {
+        // 列表以 Windows 换行保存，行尾的 \r 不属于 key
+        let expected = TestFile::new("expected.txt", "ok\r\nmissing\r\n\r\n");
+        let count_options = CountOptions::default();
+        let mut data_count =
+            count_occurrences("ok\nok\nextra\n".as_bytes(), &count_options).unwrap();
+        insert_expected_keys(&mut data_count, expected.path(), &count_options).unwrap();
+        let write_options = WriteOptions {
+            min_count: 0,
+            ..WriteOptions::default()
+        };
+        let mut output = Vec::new();
+        write_results(&mut output, &data_count, &write_options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Line,Count\nok,2\nextra,1\nmissing,0\n"
+        );
+    }
+
+    #[test]
+    fn expected_keys_are_hashed_with_hash_keys() {
+        let expected = TestFile::new("expected-hashed.txt", "present\nabsent\n");
+        let count_options = CountOptions {
+            hash_keys: true,
+            ..CountOptions::default()
+        };
+        let mut data_count = count_occurrences("present\n".as_bytes(), &count_options).unwrap();
+        insert_expected_keys(&mut data_count, expected.path(), &count_options).unwrap();
+        let digest = |key: &str| digest_hex(xxhash_rust::xxh3::xxh3_128(key.as_bytes()));
+        assert_eq!(data_count.len(), 2);
+        assert_eq!(data_count[&digest("present")].count, 1);
+        assert_eq!(data_count[&digest("absent")].count, 0);
+    }
+
+    #[test]
+    fn seed_counts_are_summed_with_new_input() {
+        let seed = TestFile::new("seed.csv", "Line,Count,Percent\na,5,83.33\nb,1,16.67\n");
+        let mut data_count = load_counts(seed.path(), &CountOptions::default()).unwrap();
+        let new_count =
+            crate::count_occurrences("a\nc\n".as_bytes(), &CountOptions::default()).unwrap();
+        crate::merge_count_maps(&mut data_count, new_count);
+        let mut counts: Vec<_> = data_count
+            .iter()
+            .map(|(line, line_count)| (line.as_str(), line_count.count))
+            .collect();
+        counts.sort();
+        assert_eq!(counts, [("a", 6), ("b", 1), ("c", 1)]);
+    }
+}
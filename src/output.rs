@@ -0,0 +1,2980 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use icu_collator::options::CollatorOptions;
+use icu_collator::{CollatorBorrowed, CollatorPreferences};
+use icu_locale_core::Locale;
+use indicatif::ProgressBar;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rayon::slice::ParallelSliceMut;
+use rustc_hash::FxHashMap;
+use xxhash_rust::xxh3::xxh3_64;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+use crate::count::{Checkpoint, CountMap};
+use crate::error::{Error, Result};
+use crate::input::UTF8_BOM;
+use crate::spill::sort_spilled;
+
+/// 排序后输出的一行结果
+#[derive(Clone, Debug)]
+pub struct DataCount {
+    /// 行内容，忽略大小写时为首次出现的原始写法
+    pub line: String,
+    /// 统计用的 key，只在输出 Key 列时填充
+    pub key: String,
+    /// 出现次数
+    pub count: usize,
+    /// 首次出现的行号，从 1 开始
+    pub first_seen: usize,
+    /// 排序后到该行为止的出现次数累计
+    pub cumulative: usize,
+    /// 出现过的行号，只在 [`WriteOptions::positions`] 开启时填充
+    pub positions: Vec<usize>,
+}
+
+// OutputRows 需要输出的行及其行数，同时给出 --head 与 --tail 时 gap 为省略处之后第一行的序号
+// sort_elapsed 为过滤、排序及截取所用的时间
+struct OutputRows<'a> {
+    len: usize,
+    gap: Option<usize>,
+    rows: Box<dyn Iterator<Item = DataCount> + 'a>,
+    sort_elapsed: Duration,
+}
+
+/// 写入阶段的汇总信息
+#[derive(Clone, Debug, Default)]
+pub struct WriteStats {
+    /// 过滤、排序及截取需要输出的行所用的时间，不包括序列化和写入
+    pub sort_elapsed: Duration,
+}
+
+/// 结果文件的输出格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Line,Count 两列的 CSV
+    Csv,
+    /// {"line": ..., "count": ...} 对象组成的 JSON 数组
+    Json,
+    /// 每行一个紧凑 JSON 对象，便于流式处理
+    Ndjson,
+    /// 以制表符分隔的 Line\tCount，行内容中的制表符、换行和反斜杠用反斜杠转义
+    Tsv,
+    /// 写入 SQLite 数据库的 counts(line, count) 表，不支持输出到标准输出
+    Sqlite,
+    /// 列式存储的 Parquet 文件，行按排序后的顺序写入，不支持输出到标准输出和追加
+    Parquet,
+}
+
+impl OutputFormat {
+    /// 根据结果文件的扩展名推断输出格式，.db、.sqlite、.sqlite3 为 SQLite，.tsv 为 TSV，
+    /// .parquet 为 Parquet，其余为 CSV
+    pub fn from_path(result_path: &str) -> OutputFormat {
+        let extension = std::path::Path::new(result_path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        match extension {
+            Some("db" | "sqlite" | "sqlite3") => OutputFormat::Sqlite,
+            Some("tsv") => OutputFormat::Tsv,
+            Some("parquet") => OutputFormat::Parquet,
+            _ => OutputFormat::Csv,
+        }
+    }
+
+    /// 该格式的文件扩展名，不含点号，SQLite 为 db
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Sqlite => "db",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// 输出文件的压缩方式，只对 CSV、TSV、JSON、NDJSON 输出有效
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputCompression {
+    /// 不压缩
+    None,
+    /// gzip 压缩
+    Gzip,
+    /// Zstandard 压缩
+    Zstd,
+}
+
+impl OutputCompression {
+    /// 根据结果文件名的后缀推断压缩方式，.gz 为 gzip，.zst 为 zstd，其余不压缩
+    pub fn from_path(result_path: &str) -> OutputCompression {
+        if result_path.ends_with(".gz") {
+            OutputCompression::Gzip
+        } else if result_path.ends_with(".zst") {
+            OutputCompression::Zstd
+        } else {
+            OutputCompression::None
+        }
+    }
+
+    /// 该压缩方式的文件名后缀，含点号，不压缩时为空
+    pub fn suffix(self) -> &'static str {
+        match self {
+            OutputCompression::None => "",
+            OutputCompression::Gzip => ".gz",
+            OutputCompression::Zstd => ".zst",
+        }
+    }
+}
+
+/// 结果的排序依据
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// 按出现次数排序，次数相同按行内容升序
+    Count,
+    /// 按行内容的字典序排序，行内容互不相同，排序结果唯一
+    Line,
+    /// 按行内容的字符数排序，字符数相同按行内容升序
+    Length,
+    /// 按首次出现的先后输出，保留输入中的自然顺序，不受 ascending 影响；
+    /// 首次出现行号相同（例如都来自 --merge-with）时按行内容升序
+    FirstSeen,
+}
+
+/// 按行内容排序时比较字符串的方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Collation {
+    /// 按 UTF-8 字节比较，速度最快，但带重音的字母和中日韩文字的顺序不符合语言习惯
+    Byte,
+    /// 按环境变量 LC_ALL、LC_COLLATE、LANG 指定的语言的排序规则比较，未设置时使用 Unicode 默认规则
+    Locale,
+}
+
+/// 按出现次数排序时，次数相同的行之间的顺序
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Tiebreak {
+    /// 按行内容升序
+    LineAsc,
+    /// 按行内容降序
+    LineDesc,
+    /// 按首次出现的行号升序，首次出现行号相同（例如都来自 --merge-with）时按行内容升序
+    FirstSeen,
+}
+
+/// 结果中的一列，用于 [`WriteOptions::columns`] 指定输出哪些列及其顺序
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ResultColumn {
+    /// 行内容，表头为 [`WriteOptions::line_header`]
+    Line,
+    /// 分组用的 key，即经过去空白、大小写等处理后的内容
+    Key,
+    /// 出现次数，表头为 [`WriteOptions::count_header`]
+    Count,
+    /// 占全部出现次数的百分比
+    Percent,
+    /// 按输出顺序到该行为止的出现次数累计
+    Cumulative,
+    /// 首次出现的行号
+    FirstSeen,
+    /// 出现次数所在的档位，需要 [`WriteOptions::tiers`] 给出分界值
+    Tier,
+    /// 统计时记录的所有行号
+    Positions,
+    /// 每行都相同的来源标签，需要 [`WriteOptions::tag`] 给出标签
+    Source,
+}
+
+/// CSV 输出中字段的引号规则
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Quoting {
+    /// 只在字段包含分隔符、双引号或换行时用双引号包裹（RFC 4180）
+    Minimal,
+    /// 所有字段（包括表头和数字）都用双引号包裹
+    Always,
+    /// 从不加引号，字段包含分隔符或换行时报错，双引号原样输出
+    Never,
+}
+
+/// 输出阶段的排序、过滤及格式选项
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// 只输出前 N 行
+    pub top: Option<usize>,
+    /// 按出现次数降序输出，累计出现次数达到总次数的该百分比（0 到 100）后停止，
+    /// 达到该比例的那一行也会输出
+    pub top_percent: Option<f64>,
+    /// 输出排序后的前 N 行，与 tail 同时使用时两段之间写入省略行
+    pub head: Option<usize>,
+    /// 输出排序后的最后 N 行
+    pub tail: Option<usize>,
+    /// 不排序，按哈希表的顺序直接输出，顺序不确定但不需要额外保存排序列表
+    pub no_sort: bool,
+    /// 不同的行多于该值时改用外部排序：每这么多行排序后写入临时目录中的一个分段文件，再归并输出，
+    /// 排序时内存中最多保存这么多行的副本；只对 CSV、TSV、JSON、NDJSON 输出生效，
+    /// 设置 tail 或 top_percent 时仍在内存中排序
+    pub sort_buffer: Option<usize>,
+    /// 使用稳定排序：排序依据相等的行（例如设置 empty_placeholder 后输出相同的两行）保持首次出现的先后顺序；
+    /// 默认使用更快、更省内存的不稳定排序，这些行之间的顺序不确定
+    pub stable_sort: bool,
+    /// 只输出出现次数不少于该值的行
+    pub min_count: usize,
+    /// 输出格式
+    pub format: OutputFormat,
+    /// 输出文件的压缩方式
+    pub compression: OutputCompression,
+    /// 排序依据
+    pub sort_by: SortKey,
+    /// 按出现次数排序时次数相同的行之间的顺序
+    pub tiebreak: Tiebreak,
+    /// 按行内容排序时把行解析为数字比较，无法解析的行排在最后
+    pub numeric: bool,
+    /// 按行内容排序时比较字符串的方式
+    pub collation: Collation,
+    /// 升序排序，默认降序
+    pub ascending: bool,
+    /// 增加 Percent 列
+    pub percent: bool,
+    /// 增加 Cumulative 列，即按输出顺序到当前行为止的出现次数累计
+    pub cumulative: bool,
+    /// 增加 FirstSeen 列，即首次出现的行号（从 1 开始）
+    pub first_seen: bool,
+    /// 只输出恰好出现一次的行
+    pub unique_only: bool,
+    /// 只输出出现次数大于 1 的行
+    pub duplicates_only: bool,
+    /// CSV 表头中行内容一列的名称
+    pub line_header: String,
+    /// CSV 表头中出现次数一列的名称
+    pub count_header: String,
+    /// 不写入 CSV 表头
+    pub no_header: bool,
+    /// CSV 输出的字段分隔符
+    pub output_delimiter: String,
+    /// CSV 输出中字段的引号规则
+    pub quoting: Quoting,
+    /// 输出时用该字符串代替空的行内容，例如 `<EMPTY>`，统计时所有空值仍合并为一行
+    pub empty_placeholder: Option<String>,
+    /// 追加到已有的结果文件末尾而不是覆盖，文件非空时不再写入表头
+    pub append: bool,
+    /// 追加到非空文件时仍然写入表头
+    pub force_header: bool,
+    /// 在数据之后写入不同行数与出现次数总和的汇总；CSV、TSV 输出不足 3 列时放不下汇总行，
+    /// 不写入汇总，见 [`WriteOptions::summary_fits`]
+    pub summary: bool,
+    /// 每写入多少行更新一次进度条，避免逐行更新的加锁和重绘开销
+    pub progress_interval: usize,
+    /// 每写入 N 行数据就刷新一次缓冲区，让下游尽快读到结果，代价是吞吐量下降；为 None 时只在写完后刷新
+    pub flush_interval: Option<usize>,
+    /// 增加 Tier 列，按出现次数落在这些升序分界值划分的哪一档标注，为空时不输出
+    pub tiers: Vec<usize>,
+    /// 在最后增加 Source 列，每行的值都为该标签，便于合并多个来源的结果后区分各行的出处
+    pub tag: Option<String>,
+    /// 增加 Positions 列，列出统计时记录的所有行号，用逗号分隔
+    pub positions: bool,
+    /// 在 Line 列之后增加 Key 列，输出分组用的 key
+    pub show_key: bool,
+    /// 为 (i, N) 时只输出 key 的哈希值对 N 取余等于 i 的行，用于把结果拆分到 N 个文件，
+    /// 同一个 key 总是落在同一个分片中；Percent 列、汇总行等仍以全部结果计算
+    pub shard: Option<(usize, usize)>,
+    /// 按此顺序只输出这些列，此时由它代替 show_key、percent、cumulative、first_seen、tiers、positions 决定输出哪些列；
+    /// 为 None 时输出 Line、Count 及上述选项增加的列
+    pub columns: Option<Vec<ResultColumn>>,
+    /// 以 \r\n 而不是 \n 结束每一行，CSV 字段内部的换行保持原样
+    pub crlf: bool,
+    /// 在输出开头写入 UTF-8 BOM，使 Excel 能正确识别编码；追加到非空文件时不写入
+    pub bom: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            top: None,
+            top_percent: None,
+            head: None,
+            tail: None,
+            no_sort: false,
+            sort_buffer: None,
+            stable_sort: false,
+            min_count: 1,
+            format: OutputFormat::Csv,
+            compression: OutputCompression::None,
+            sort_by: SortKey::Count,
+            tiebreak: Tiebreak::LineAsc,
+            numeric: false,
+            collation: Collation::Byte,
+            ascending: false,
+            percent: false,
+            cumulative: false,
+            first_seen: false,
+            unique_only: false,
+            duplicates_only: false,
+            line_header: String::from("Line"),
+            count_header: String::from("Count"),
+            no_header: false,
+            output_delimiter: String::from(","),
+            quoting: Quoting::Minimal,
+            empty_placeholder: None,
+            append: false,
+            force_header: false,
+            summary: false,
+            progress_interval: 10_000,
+            flush_interval: None,
+            tiers: Vec::new(),
+            tag: None,
+            columns: None,
+            positions: false,
+            show_key: false,
+            shard: None,
+            crlf: false,
+            bom: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    /// 汇总能否写入输出：CSV、TSV 的汇总行 TOTAL,<不同行数>,<总次数> 与表头的列数相同，
+    /// 输出不足 3 列（例如默认的 Line,Count 两列）时放不下，由调用方另行报告
+    pub fn summary_fits(&self) -> bool {
+        !matches!(self.format, OutputFormat::Csv | OutputFormat::Tsv)
+            || result_columns(self).len() >= 3
+    }
+}
+
+// OutputColumn 输出的一列，header 用于 CSV 表头，key 用于 JSON 字段名
+struct OutputColumn {
+    header: String,
+    key: Cow<'static, str>,
+}
+
+// Field 输出行中的一个字段，文本需要按格式转义，数值原样输出
+enum Field {
+    Text(String),
+    Number(String),
+}
+
+// ResultWriter 结果输出目标，压缩输出需要在结束时显式写入尾部
+// 覆盖写入文件时 temp 为实际写入的临时文件，finish 成功后才替换结果文件
+pub(crate) struct ResultWriter {
+    encoder: Encoder,
+    temp: Option<TempFile>,
+}
+
+// Encoder 是否压缩输出及压缩方式
+enum Encoder {
+    Plain(Box<dyn Write>),
+    Gzip(Box<GzEncoder<Box<dyn Write>>>),
+    Zstd(Box<ZstdEncoder<'static, Box<dyn Write>>>),
+}
+
+impl ResultWriter {
+    // 完成写入，压缩输出会在此写入 gzip 尾部或 zstd 的最后一帧，随后用临时文件替换结果文件
+    pub(crate) fn finish(self) -> std::io::Result<()> {
+        match self.encoder {
+            Encoder::Plain(mut writer) => writer.flush()?,
+            Encoder::Gzip(encoder) => encoder.finish()?.flush()?,
+            Encoder::Zstd(encoder) => encoder.finish()?.flush()?,
+        }
+        match self.temp {
+            Some(temp) => temp.commit(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for ResultWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// TempFile 与目标文件在同一目录下的临时文件，commit 时重命名为目标文件
+// 没有 commit 就被丢弃（例如写入出错）时删除临时文件，目标文件保持原样
+struct TempFile {
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl TempFile {
+    // 创建 path 的临时文件，放在同一目录下以便重命名不跨文件系统
+    fn create(path: &str) -> std::io::Result<(File, TempFile)> {
+        let path = PathBuf::from(path);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+        let file = File::create(&temp_path)?;
+        Ok((file, TempFile { temp_path, path }))
+    }
+
+    // 用临时文件替换目标文件；重命名跨文件系统（例如目标是指向其他挂载点的符号链接）时退回到复制
+    fn commit(self) -> std::io::Result<()> {
+        match std::fs::rename(&self.temp_path, &self.path) {
+            Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+                std::fs::copy(&self.temp_path, &self.path)?;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // 重命名成功后临时文件已不存在，删除失败可以忽略
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
+}
+
+/// 将结果按指定格式写入文件，"-" 表示标准输出，返回写入的数据行数（不含表头和汇总）及写入阶段的汇总信息
+///
+/// 进度条长度会被设置为实际写入的行数，每写入一行推进一格
+pub fn write_sort_and_merge_result(
+    result_path: &str,
+    data_count: &CountMap,
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> Result<(usize, WriteStats)> {
+    let write_error = |source| Error::Write {
+        path: result_path.to_string(),
+        source,
+    };
+    if write_options.format == OutputFormat::Sqlite {
+        if result_path == "-" {
+            return Err(write_error(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "sqlite output cannot be written to stdout",
+            )));
+        }
+        return write_sqlite(result_path, data_count, write_options, pb)
+            .map_err(|err| write_error(std::io::Error::other(err)));
+    }
+    if write_options.format == OutputFormat::Parquet {
+        let unsupported = if result_path == "-" {
+            Some("parquet output cannot be written to stdout")
+        } else if write_options.append {
+            Some("parquet output cannot be appended to")
+        } else {
+            None
+        };
+        if let Some(message) = unsupported {
+            return Err(write_error(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                message,
+            )));
+        }
+        return write_parquet(result_path, data_count, write_options, pb)
+            .map_err(|err| write_error(std::io::Error::other(err)));
+    }
+
+    // 追加到非空文件时表头和 BOM 已经存在，不再重复写入，指定 force_header 时仍写入表头
+    let append_to_existing = write_options.append
+        && result_path != "-"
+        && std::fs::metadata(result_path).is_ok_and(|metadata| metadata.len() > 0);
+    let append_options;
+    let write_options = if append_to_existing {
+        append_options = WriteOptions {
+            no_header: write_options.no_header || !write_options.force_header,
+            bom: false,
+            ..write_options.clone()
+        };
+        &append_options
+    } else {
+        write_options
+    };
+
+    // 创建结果文件
+    let mut result_file = open_output(result_path, write_options.compression, write_options.append)
+        .map_err(write_error)?;
+    let written =
+        write_rows(&mut result_file, data_count, write_options, pb).map_err(write_error)?;
+    result_file.finish().map_err(write_error)?;
+    Ok(written)
+}
+
+/// 按输出选项过滤、排序后以迭代器返回结果，不涉及序列化，便于调用方自行处理
+///
+/// 顺序与写入文件时相同；同时给出 head 与 tail 时两段直接相连，没有省略行。
+/// 格式相关的选项（例如 format、percent、quoting）不起作用
+pub fn sorted_results<'a>(
+    data_count: &'a CountMap,
+    write_options: &'a WriteOptions,
+) -> impl Iterator<Item = DataCount> + 'a {
+    output_rows(data_count, write_options).rows
+}
+
+/// 将结果按指定格式写入任意 writer，不涉及文件系统，不支持 SQLite 和 Parquet 格式
+pub fn write_results<W: Write>(
+    mut writer: W,
+    data_count: &CountMap,
+    write_options: &WriteOptions,
+) -> std::io::Result<()> {
+    if matches!(
+        write_options.format,
+        OutputFormat::Sqlite | OutputFormat::Parquet
+    ) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "sqlite and parquet output require a file path",
+        ));
+    }
+    write_rows(
+        &mut writer,
+        data_count,
+        write_options,
+        &ProgressBar::hidden(),
+    )?;
+    writer.flush()
+}
+
+/// 对比两份统计结果并写入文件：每行给出 Line,CountA,CountB,Delta 四列，Delta 为 CountA - CountB，
+/// 只在一份结果中出现的行在另一份中记为 0；按 Delta 的绝对值降序排列，相同时按行内容升序
+///
+/// 只支持 CSV、TSV、JSON 和 NDJSON 格式；top、line_header、output_delimiter、quoting 等格式选项照常生效，
+/// 其余的过滤、排序及增加列的选项不起作用。返回写入的数据行数
+pub fn write_comparison(
+    result_path: &str,
+    data_count: &CountMap,
+    other: &CountMap,
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> Result<usize> {
+    let write_error = |source| Error::Write {
+        path: result_path.to_string(),
+        source,
+    };
+    if matches!(
+        write_options.format,
+        OutputFormat::Sqlite | OutputFormat::Parquet
+    ) {
+        return Err(write_error(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "sqlite and parquet output are not supported when comparing",
+        )));
+    }
+    // 汇总行的格式与对比结果的列不一致，不写入
+    let write_options = &WriteOptions {
+        summary: false,
+        ..write_options.clone()
+    };
+
+    // 两份结果中所有的 key 及各自的出现次数，原始写法优先取第一份的
+    let mut rows: Vec<(&str, usize, usize)> = data_count
+        .iter()
+        .map(|(key, line_count)| {
+            let other_count = other.get(key).map_or(0, |line_count| line_count.count);
+            let line = line_count.original.as_deref().unwrap_or(key);
+            (line, line_count.count, other_count)
+        })
+        .collect();
+    rows.extend(
+        other
+            .iter()
+            .filter(|(key, _)| !data_count.contains_key(*key))
+            .map(|(key, line_count)| {
+                let line = line_count.original.as_deref().unwrap_or(key);
+                (line, 0, line_count.count)
+            }),
+    );
+    rows.sort_unstable_by(|a, b| {
+        b.1.abs_diff(b.2)
+            .cmp(&a.1.abs_diff(a.2))
+            .then_with(|| a.0.cmp(b.0))
+    });
+    rows.truncate(write_options.top.unwrap_or(usize::MAX));
+    pb.set_length(rows.len() as u64);
+    pb.set_position(0);
+
+    let columns = [
+        OutputColumn {
+            header: write_options.line_header.clone(),
+            key: Cow::Borrowed("line"),
+        },
+        OutputColumn {
+            header: String::from("CountA"),
+            key: Cow::Borrowed("count_a"),
+        },
+        OutputColumn {
+            header: String::from("CountB"),
+            key: Cow::Borrowed("count_b"),
+        },
+        OutputColumn {
+            header: String::from("Delta"),
+            key: Cow::Borrowed("delta"),
+        },
+    ];
+    let mut result_file = open_output(result_path, write_options.compression, write_options.append)
+        .map_err(write_error)?;
+    let write = |result_file: &mut ResultWriter| -> std::io::Result<()> {
+        write_header(result_file, write_options, &columns)?;
+        for (index, (line, count, other_count)) in rows.iter().enumerate() {
+            let delta = *count as i128 - *other_count as i128;
+            let fields = [
+                Field::Text(line.to_string()),
+                Field::Number(count.to_string()),
+                Field::Number(other_count.to_string()),
+                Field::Number(delta.to_string()),
+            ];
+            write_row(result_file, write_options, &columns, &fields, index)?;
+            advance_progress(pb, index + 1, write_options.progress_interval);
+        }
+        pb.set_position(rows.len() as u64);
+        write_footer(result_file, write_options, columns.len(), 0, 0)
+    };
+    write(&mut result_file).map_err(write_error)?;
+    result_file.finish().map_err(write_error)?;
+    Ok(rows.len())
+}
+
+/// 把多份统计结果写成一张宽表：第一列为行内容，之后每份结果一列，列名为对应的文件名，
+/// 值为该行在这份结果中的出现次数，没有出现时为 0；按各份次数之和降序排列，相同时按行内容升序
+///
+/// 只支持 CSV、TSV、JSON 和 NDJSON 格式，JSON 中各列的字段名同样为文件名；
+/// top、line_header、output_delimiter、quoting 等格式选项照常生效，其余选项不起作用。返回写入的数据行数
+pub fn write_matrix(
+    result_path: &str,
+    counts: &[(String, CountMap)],
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> Result<usize> {
+    let write_error = |source| Error::Write {
+        path: result_path.to_string(),
+        source,
+    };
+    if matches!(
+        write_options.format,
+        OutputFormat::Sqlite | OutputFormat::Parquet
+    ) {
+        return Err(write_error(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "sqlite and parquet output are not supported for a matrix",
+        )));
+    }
+    // 汇总行的格式与宽表的列不一致，不写入
+    let write_options = &WriteOptions {
+        summary: false,
+        ..write_options.clone()
+    };
+
+    // 所有结果中 key 的并集：原始写法取第一份包含该 key 的结果，以及每份结果中的次数
+    let mut union: FxHashMap<&str, (&str, Vec<usize>)> = FxHashMap::default();
+    for (index, (_, data_count)) in counts.iter().enumerate() {
+        for (key, line_count) in data_count {
+            let line = line_count.original.as_deref().unwrap_or(key);
+            let (_, row) = union
+                .entry(key.as_str())
+                .or_insert_with(|| (line, vec![0; counts.len()]));
+            row[index] = line_count.count;
+        }
+    }
+    let mut rows: Vec<_> = union
+        .into_values()
+        .map(|(line, row)| (line, row.iter().sum::<usize>(), row))
+        .collect();
+    rows.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    rows.truncate(write_options.top.unwrap_or(usize::MAX));
+    pb.set_length(rows.len() as u64);
+    pb.set_position(0);
+
+    let mut columns = vec![OutputColumn {
+        header: write_options.line_header.clone(),
+        key: Cow::Borrowed("line"),
+    }];
+    columns.extend(counts.iter().map(|(file_path, _)| OutputColumn {
+        header: file_path.clone(),
+        key: Cow::Owned(file_path.clone()),
+    }));
+    let mut result_file = open_output(result_path, write_options.compression, write_options.append)
+        .map_err(write_error)?;
+    let write = |result_file: &mut ResultWriter| -> std::io::Result<()> {
+        write_header(result_file, write_options, &columns)?;
+        for (index, (line, _, row)) in rows.iter().enumerate() {
+            let mut fields = vec![Field::Text(line.to_string())];
+            fields.extend(row.iter().map(|count| Field::Number(count.to_string())));
+            write_row(result_file, write_options, &columns, &fields, index)?;
+            advance_progress(pb, index + 1, write_options.progress_interval);
+        }
+        pb.set_position(rows.len() as u64);
+        write_footer(result_file, write_options, columns.len(), 0, 0)
+    };
+    write(&mut result_file).map_err(write_error)?;
+    result_file.finish().map_err(write_error)?;
+    Ok(rows.len())
+}
+
+/// 分组取前 K 名：每个 key 为分组列与值列用 delimiter 连接而成，按第一个 delimiter 拆回 Group 和 Value 两列，
+/// 输出 Group,Value,Count 三列，每组只保留出现次数最多的 per_group 行
+///
+/// 分组按名称升序，组内按出现次数降序、次数相同时按值升序。只支持 CSV、TSV、JSON 和 NDJSON 格式；
+/// count_header、output_delimiter、quoting 等格式选项照常生效，其余的过滤、排序及增加列的选项不起作用。返回写入的数据行数
+pub fn write_top_per_group(
+    result_path: &str,
+    data_count: &CountMap,
+    delimiter: &str,
+    per_group: usize,
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> Result<usize> {
+    let write_error = |source| Error::Write {
+        path: result_path.to_string(),
+        source,
+    };
+    if matches!(
+        write_options.format,
+        OutputFormat::Sqlite | OutputFormat::Parquet
+    ) {
+        return Err(write_error(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "sqlite and parquet output are not supported for top per group",
+        )));
+    }
+    // 汇总行的格式与分组结果的列不一致，不写入
+    let write_options = &WriteOptions {
+        summary: false,
+        ..write_options.clone()
+    };
+
+    let mut rows: Vec<(&str, &str, usize)> = data_count
+        .iter()
+        .map(|(key, line_count)| {
+            let line = line_count.original.as_deref().unwrap_or(key);
+            let (group, value) = line.split_once(delimiter).unwrap_or((line, ""));
+            (group, value, line_count.count)
+        })
+        .collect();
+    rows.sort_unstable_by(|a, b| {
+        a.0.cmp(b.0)
+            .then_with(|| b.2.cmp(&a.2))
+            .then_with(|| a.1.cmp(b.1))
+    });
+    // 排序后同一组的行相邻，记下当前组已保留的行数
+    let mut current: Option<(&str, usize)> = None;
+    rows.retain(|(group, _, _)| {
+        let kept = match current {
+            Some((current_group, kept)) if current_group == *group => kept,
+            _ => 0,
+        };
+        current = Some((group, kept + 1));
+        kept < per_group
+    });
+    pb.set_length(rows.len() as u64);
+    pb.set_position(0);
+
+    let columns = [
+        OutputColumn {
+            header: String::from("Group"),
+            key: Cow::Borrowed("group"),
+        },
+        OutputColumn {
+            header: String::from("Value"),
+            key: Cow::Borrowed("value"),
+        },
+        OutputColumn {
+            header: write_options.count_header.clone(),
+            key: Cow::Borrowed("count"),
+        },
+    ];
+    let mut result_file = open_output(result_path, write_options.compression, write_options.append)
+        .map_err(write_error)?;
+    let write = |result_file: &mut ResultWriter| -> std::io::Result<()> {
+        write_header(result_file, write_options, &columns)?;
+        for (index, (group, value, count)) in rows.iter().enumerate() {
+            let fields = [
+                Field::Text(group.to_string()),
+                Field::Text(value.to_string()),
+                Field::Number(count.to_string()),
+            ];
+            write_row(result_file, write_options, &columns, &fields, index)?;
+            advance_progress(pb, index + 1, write_options.progress_interval);
+        }
+        pb.set_position(rows.len() as u64);
+        write_footer(result_file, write_options, columns.len(), 0, 0)
+    };
+    write(&mut result_file).map_err(write_error)?;
+    result_file.finish().map_err(write_error)?;
+    Ok(rows.len())
+}
+
+/// 一次运行的元数据，由 [`write_manifest`] 写成 JSON，记录结果文件是如何得到的
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    /// 程序的版本号
+    pub version: String,
+    /// 开始运行的时间，RFC 3339 格式
+    pub started_at: String,
+    /// 完整的命令行参数，不含程序名
+    pub arguments: Vec<String>,
+    /// 展开通配符后的输入文件
+    pub inputs: Vec<String>,
+    /// 结果文件的路径
+    pub result_path: String,
+    /// 读取的总行数
+    pub lines: usize,
+    /// 不同的行数
+    pub distinct: usize,
+    /// 写入结果的数据行数
+    pub written: usize,
+    /// 从开始运行到写完结果的耗时
+    pub elapsed: Duration,
+}
+
+/// 把一次运行的元数据写成一个 JSON 对象，先写临时文件再重命名，不会留下只写了一半的文件
+pub fn write_manifest(manifest_path: &str, manifest: &Manifest) -> Result<()> {
+    let write_error = |source| Error::Write {
+        path: manifest_path.to_string(),
+        source,
+    };
+    let strings = |values: &[String]| {
+        let values: Vec<_> = values
+            .iter()
+            .map(|value| escape_json_string(value))
+            .collect();
+        format!("[{}]", values.join(", "))
+    };
+    let members = [
+        ("version", escape_json_string(&manifest.version)),
+        ("started_at", escape_json_string(&manifest.started_at)),
+        ("arguments", strings(&manifest.arguments)),
+        ("inputs", strings(&manifest.inputs)),
+        ("result_path", escape_json_string(&manifest.result_path)),
+        ("lines", manifest.lines.to_string()),
+        ("distinct", manifest.distinct.to_string()),
+        ("written", manifest.written.to_string()),
+        (
+            "elapsed_seconds",
+            format!("{:.3}", manifest.elapsed.as_secs_f64()),
+        ),
+    ];
+    let members: Vec<_> = members
+        .iter()
+        .map(|(key, value)| format!("  \"{}\": {}", key, value))
+        .collect();
+    let (mut file, temp) = TempFile::create(manifest_path).map_err(write_error)?;
+    writeln!(file, "{{\n{}\n}}", members.join(",\n"))
+        .and_then(|_| temp.commit())
+        .map_err(write_error)
+}
+
+// 检查点表头中记录继续位置的两列的前缀，之后分别为已经统计过的行数和正在统计的文件
+pub(crate) const RESUME_LINE_PREFIX: &str = "ResumeLine=";
+pub(crate) const RESUME_FILE_PREFIX: &str = "ResumeFile=";
+
+/// 把检查点写成 Line,Count 两列的 CSV，可以直接用作 --merge-with 的输入；表头在 Line,Count 之后
+/// 还有 ResumeLine=<行数>、ResumeFile=<文件> 两列，记录继续的位置，见 [`read_checkpoint_position`](crate::read_checkpoint_position)
+///
+/// 先写临时文件再重命名，写到一半崩溃时原来的检查点保持完整，统计结果与继续位置总是一同更新
+pub fn write_checkpoint(checkpoint_path: &str, checkpoint: &Checkpoint) -> Result<()> {
+    let write_error = |source| Error::Write {
+        path: checkpoint_path.to_string(),
+        source,
+    };
+    // 检查点只用于继续统计，不需要排序
+    let write_options = WriteOptions {
+        no_sort: true,
+        no_header: true,
+        ..WriteOptions::default()
+    };
+    let (file, temp) = TempFile::create(checkpoint_path).map_err(write_error)?;
+    let mut writer = BufWriter::new(file);
+    let header = [
+        String::from("Line"),
+        String::from("Count"),
+        format!("{}{}", RESUME_LINE_PREFIX, checkpoint.lines),
+        format!("{}{}", RESUME_FILE_PREFIX, checkpoint.file_path),
+    ];
+    csv::Writer::from_writer(&mut writer)
+        .write_record(&header)
+        .map_err(|err| write_error(err.into()))?;
+    write_rows(
+        &mut writer,
+        checkpoint.data_count,
+        &write_options,
+        &ProgressBar::hidden(),
+    )
+    .and_then(|_| writer.flush())
+    .and_then(|_| temp.commit())
+    .map_err(write_error)
+}
+
+/// 写入出现次数本身的分布（count of counts）：每个出现次数对应多少个不同的行，按次数升序
+///
+/// 输出为 Count,Lines 两列的 CSV，histogram_path 为 `None` 时写到标准错误，"-" 表示标准输出
+pub fn write_histogram(histogram_path: Option<&str>, data_count: &CountMap) -> Result<()> {
+    let write_error = |source| Error::Write {
+        path: histogram_path.unwrap_or("<stderr>").to_string(),
+        source,
+    };
+    let writer: Box<dyn Write> = match histogram_path {
+        None => Box::new(std::io::stderr().lock()),
+        Some("-") => Box::new(std::io::stdout().lock()),
+        Some(path) => Box::new(BufWriter::new(File::create(path).map_err(write_error)?)),
+    };
+    write_histogram_rows(writer, data_count).map_err(write_error)
+}
+
+// 统计每个出现次数的不同行数并写入
+fn write_histogram_rows(mut writer: impl Write, data_count: &CountMap) -> std::io::Result<()> {
+    let mut histogram = BTreeMap::new();
+    for line_count in data_count.values() {
+        *histogram.entry(line_count.count).or_insert(0) += 1;
+    }
+    writeln!(writer, "Count,Lines")?;
+    for (count, lines) in histogram {
+        writeln!(writer, "{},{}", count, lines)?;
+    }
+    writer.flush()
+}
+
+// 按 count 排序、过滤后写入每一行，返回写入的行数
+fn write_rows(
+    result_file: &mut impl Write,
+    data_count: &CountMap,
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> std::io::Result<(usize, WriteStats)> {
+    // 百分比以全部行的出现次数之和为分母
+    let total_count: usize = data_count.values().map(|line_count| line_count.count).sum();
+    let collator = row_collator(write_options);
+    let compare =
+        |a: &DataCount, b: &DataCount| compare_rows(a, b, write_options, collator.as_ref());
+    // 读取分段文件出错时迭代提前结束，写完后再返回错误
+    let mut spill_error = None;
+    let OutputRows {
+        len,
+        gap,
+        rows,
+        sort_elapsed,
+    } = match spill_len(data_count, write_options) {
+        Some(run_len) => {
+            let started = Instant::now();
+            let rows = filtered_rows(data_count, write_options);
+            let (len, merged) = if write_options.stable_sort {
+                // 先外部排序为首次出现的先后顺序，再稳定排序
+                let (_, by_first_seen) = sort_spilled(rows, run_len, compare_first_seen, true)?;
+                let mut first_error = None;
+                let rows =
+                    by_first_seen.map_while(|row| row.map_err(|err| first_error = Some(err)).ok());
+                let sorted = sort_spilled(rows, run_len, compare, true)?;
+                if let Some(err) = first_error {
+                    return Err(err);
+                }
+                sorted
+            } else {
+                sort_spilled(rows, run_len, compare, false)?
+            };
+            let top = output_top(write_options);
+            let rows = merged
+                .take(top)
+                .map_while(|row| row.map_err(|err| spill_error = Some(err)).ok())
+                .scan(0, accumulate);
+            OutputRows {
+                len: len.min(top),
+                gap: None,
+                rows: Box::new(rows),
+                sort_elapsed: started.elapsed(),
+            }
+        }
+        None => output_rows(data_count, write_options),
+    };
+
+    pb.set_length(len as u64);
+    pb.set_position(0);
+
+    let columns = output_columns(write_options);
+    write_header(result_file, write_options, &columns)?;
+
+    // 写入排序后的数据
+    for (index, fields) in output_fields(rows, total_count, write_options).enumerate() {
+        if gap == Some(index) {
+            write_gap(result_file, write_options)?;
+        }
+        write_row(result_file, write_options, &columns, &fields, index)?;
+        if (write_options.flush_interval).is_some_and(|every| (index + 1).is_multiple_of(every)) {
+            result_file.flush()?;
+        }
+        advance_progress(pb, index + 1, write_options.progress_interval);
+    }
+    if let Some(err) = spill_error {
+        return Err(err);
+    }
+    pb.set_position(len as u64);
+
+    // 汇总基于全部统计结果，不受过滤和 --top 影响
+    write_footer(
+        result_file,
+        write_options,
+        columns.len(),
+        data_count.len(),
+        total_count,
+    )?;
+    Ok((len, WriteStats { sort_elapsed }))
+}
+
+// 需要外部排序时返回每个分段的行数；不排序、需要知道全部行（tail、top_percent）时不使用外部排序
+fn spill_len(data_count: &CountMap, write_options: &WriteOptions) -> Option<usize> {
+    write_options.sort_buffer.filter(|&sort_buffer| {
+        data_count.len() > sort_buffer
+            && !write_options.no_sort
+            && write_options.tail.is_none()
+            && write_options.top_percent.is_none()
+    })
+}
+
+// 结果行数不少于该值时使用多线程排序，行数较少时线程调度的开销比排序本身更大
+const PARALLEL_SORT_MIN_LEN: usize = 100_000;
+
+// 按输出选项过滤、排序并截取需要输出的行
+// no_sort 时直接按 map 的顺序迭代，不再额外保存一份完整的结果列表
+fn output_rows<'a>(data_count: &'a CountMap, write_options: &'a WriteOptions) -> OutputRows<'a> {
+    let started = Instant::now();
+    let filtered = || filtered_rows(data_count, write_options);
+    // --head 与 --top 一样只保留前 N 行
+    let top = output_top(write_options);
+
+    if write_options.no_sort {
+        let len = filtered().take(top).count();
+        let rows = filtered().take(top).scan(0, accumulate);
+        return OutputRows {
+            len,
+            gap: None,
+            rows: Box::new(rows),
+            sort_elapsed: started.elapsed(),
+        };
+    }
+
+    let mut data_count_list: Vec<DataCount> = filtered().collect();
+
+    let collator = row_collator(write_options);
+    let compare =
+        |a: &DataCount, b: &DataCount| compare_rows(a, b, write_options, collator.as_ref());
+    let parallel = data_count_list.len() >= PARALLEL_SORT_MIN_LEN;
+    // 稳定排序前先排成首次出现的先后顺序，map 的迭代顺序本身没有意义
+    match (write_options.stable_sort, parallel) {
+        (true, true) => {
+            data_count_list.par_sort_by(compare_first_seen);
+            data_count_list.par_sort_by(compare);
+        }
+        (true, false) => {
+            data_count_list.sort_by(compare_first_seen);
+            data_count_list.sort_by(compare);
+        }
+        (false, true) => data_count_list.par_sort_unstable_by(compare),
+        (false, false) => data_count_list.sort_unstable_by(compare),
+    }
+
+    // 只保留前 N 行，N 大于总行数时保留全部
+    data_count_list.truncate(top);
+    let mut cumulative = 0;
+    for data_count in &mut data_count_list {
+        cumulative += data_count.count;
+        data_count.cumulative = cumulative;
+    }
+
+    // 累计占比以全部行的出现次数之和为分母，与 Percent 列一致
+    if let Some(top_percent) = write_options.top_percent {
+        let total_count: usize = data_count.values().map(|line_count| line_count.count).sum();
+        let threshold = top_percent / 100.0 * total_count as f64;
+        if let Some(last) = data_count_list
+            .iter()
+            .position(|data_count| data_count.cumulative as f64 >= threshold)
+        {
+            data_count_list.truncate(last + 1);
+        }
+    }
+
+    // 只给出 --tail 时保留最后 N 行；同时给出 --head 时保留首尾两段，两段重叠时输出全部
+    let mut gap = None;
+    if let Some(tail) = write_options.tail {
+        let head = write_options.head.unwrap_or(0);
+        if head + tail < data_count_list.len() {
+            data_count_list.drain(head..data_count_list.len() - tail);
+            if head > 0 {
+                gap = Some(head);
+            }
+        }
+    }
+    OutputRows {
+        len: data_count_list.len(),
+        gap,
+        rows: Box::new(data_count_list.into_iter()),
+        sort_elapsed: started.elapsed(),
+    }
+}
+
+// 过滤掉不需要输出的行（出现次数低于阈值、不在当前分片等），转换为输出的行，顺序为 map 的迭代顺序
+fn filtered_rows<'a>(
+    data_count: &'a CountMap,
+    write_options: &'a WriteOptions,
+) -> impl Iterator<Item = DataCount> + 'a {
+    let positions = result_columns(write_options).contains(&ResultColumn::Positions);
+    let show_key = result_columns(write_options).contains(&ResultColumn::Key);
+    data_count
+        .iter()
+        .filter(|(key, _)| {
+            write_options
+                .shard
+                .is_none_or(|(index, shards)| shard_of(key, shards) == index)
+        })
+        .filter(|(_, line_count)| line_count.count >= write_options.min_count)
+        .filter(|(_, line_count)| !write_options.unique_only || line_count.count == 1)
+        .filter(|(_, line_count)| !write_options.duplicates_only || line_count.count > 1)
+        .map(move |(key, line_count)| DataCount {
+            line: match (&line_count.original, &write_options.empty_placeholder) {
+                (Some(original), _) => original.clone(),
+                (None, Some(placeholder)) if key.is_empty() => placeholder.clone(),
+                (None, _) => key.clone(),
+            },
+            key: if show_key { key.clone() } else { String::new() },
+            count: line_count.count,
+            first_seen: line_count.first_seen,
+            cumulative: 0,
+            positions: if positions {
+                line_count.positions.clone()
+            } else {
+                Vec::new()
+            },
+        })
+}
+
+// 最多输出的行数，只给出 --head 时与 --top 作用相同
+fn output_top(write_options: &WriteOptions) -> usize {
+    let top = write_options.top.unwrap_or(usize::MAX);
+    match (write_options.head, write_options.tail) {
+        (Some(head), None) => top.min(head),
+        _ => top,
+    }
+}
+
+// 按输出顺序累加出现次数，填充每行的 cumulative，用于 Iterator::scan
+fn accumulate(cumulative: &mut usize, mut data_count: DataCount) -> Option<DataCount> {
+    *cumulative += data_count.count;
+    data_count.cumulative = *cumulative;
+    Some(data_count)
+}
+
+// 按行内容排序且要求按语言规则比较时创建排序规则
+fn row_collator(write_options: &WriteOptions) -> Option<CollatorBorrowed<'static>> {
+    match (write_options.sort_by, write_options.collation) {
+        (SortKey::Line, Collation::Locale) => locale_collator(),
+        _ => None,
+    }
+}
+
+// 稳定排序之前的顺序：按首次出现的先后
+fn compare_first_seen(a: &DataCount, b: &DataCount) -> Ordering {
+    a.first_seen.cmp(&b.first_seen)
+}
+
+// 按输出选项比较两行的先后
+// 默认按 count 降序排序，相同 count 默认按 line 字典序升序，保证输出稳定
+fn compare_rows(
+    a: &DataCount,
+    b: &DataCount,
+    write_options: &WriteOptions,
+    collator: Option<&CollatorBorrowed<'static>>,
+) -> Ordering {
+    match write_options.sort_by {
+        SortKey::Count => {
+            let by_count = if write_options.ascending {
+                a.count.cmp(&b.count)
+            } else {
+                b.count.cmp(&a.count)
+            };
+            by_count.then_with(|| match write_options.tiebreak {
+                Tiebreak::LineAsc => a.line.cmp(&b.line),
+                Tiebreak::LineDesc => b.line.cmp(&a.line),
+                Tiebreak::FirstSeen => a
+                    .first_seen
+                    .cmp(&b.first_seen)
+                    .then_with(|| a.line.cmp(&b.line)),
+            })
+        }
+        SortKey::Line => compare_lines(
+            &a.line,
+            &b.line,
+            write_options.numeric,
+            write_options.ascending,
+            collator,
+        ),
+        SortKey::Length => {
+            let (a_len, b_len) = (a.line.chars().count(), b.line.chars().count());
+            let by_length = if write_options.ascending {
+                a_len.cmp(&b_len)
+            } else {
+                b_len.cmp(&a_len)
+            };
+            by_length.then_with(|| a.line.cmp(&b.line))
+        }
+        SortKey::FirstSeen => a
+            .first_seen
+            .cmp(&b.first_seen)
+            .then_with(|| a.line.cmp(&b.line)),
+    }
+}
+
+// 把排序后的行转换为与 output_columns 顺序一致的各列的值
+fn output_fields<'a>(
+    rows: impl Iterator<Item = DataCount> + 'a,
+    total_count: usize,
+    write_options: &'a WriteOptions,
+) -> impl Iterator<Item = Vec<Field>> + 'a {
+    let columns = result_columns(write_options);
+    rows.map(move |mut data_count| {
+        columns
+            .iter()
+            .map(|column| match column {
+                ResultColumn::Line => Field::Text(std::mem::take(&mut data_count.line)),
+                ResultColumn::Key => Field::Text(std::mem::take(&mut data_count.key)),
+                ResultColumn::Count => Field::Number(data_count.count.to_string()),
+                ResultColumn::Percent => {
+                    let percent = if total_count == 0 {
+                        0.0
+                    } else {
+                        data_count.count as f64 / total_count as f64 * 100.0
+                    };
+                    Field::Number(format!("{:.2}", percent))
+                }
+                ResultColumn::Cumulative => Field::Number(data_count.cumulative.to_string()),
+                ResultColumn::FirstSeen => Field::Number(data_count.first_seen.to_string()),
+                // 没有分界值时无法分档
+                ResultColumn::Tier if write_options.tiers.is_empty() => Field::Text(String::new()),
+                ResultColumn::Tier => {
+                    Field::Text(tier_label(data_count.count, &write_options.tiers))
+                }
+                ResultColumn::Positions => {
+                    let positions: Vec<_> = data_count
+                        .positions
+                        .iter()
+                        .map(|position| position.to_string())
+                        .collect();
+                    Field::Text(positions.join(","))
+                }
+                ResultColumn::Source => Field::Text(write_options.tag.clone().unwrap_or_default()),
+            })
+            .collect()
+    })
+}
+
+// 按出现次数所在的区间生成 Tier 标签，tiers 为升序的分界值
+// 例如分界值 10,100 得到 1-9、10-99、100+ 三档，只含一个次数的档位写为该次数
+fn tier_label(count: usize, tiers: &[usize]) -> String {
+    let upper = tiers.iter().position(|tier| count < *tier);
+    let lower = match upper {
+        Some(0) => 1,
+        Some(index) => tiers[index - 1],
+        None => tiers[tiers.len() - 1],
+    };
+    match upper {
+        Some(index) if tiers[index] - 1 <= lower => lower.to_string(),
+        Some(index) => format!("{}-{}", lower, tiers[index] - 1),
+        None => format!("{}+", lower),
+    }
+}
+
+// 把结果写入 SQLite 数据库的 counts 表，并在 count 列上建立索引
+// 追加模式下保留已有的表，否则先删除旧表；数值列依靠 SQLite 的类型亲和性从文本转换
+fn write_sqlite(
+    result_path: &str,
+    data_count: &CountMap,
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> rusqlite::Result<(usize, WriteStats)> {
+    let total_count: usize = data_count.values().map(|line_count| line_count.count).sum();
+    let OutputRows {
+        len,
+        rows,
+        sort_elapsed,
+        ..
+    } = output_rows(data_count, write_options);
+    pb.set_length(len as u64);
+    pb.set_position(0);
+
+    let columns = output_columns(write_options);
+    let definitions: Vec<_> = columns
+        .iter()
+        .map(|column| {
+            let sql_type = match column.key.as_ref() {
+                "line" | "tier" | "positions" | "source" => "TEXT",
+                "percent" => "REAL",
+                _ => "INTEGER",
+            };
+            format!("{} {}", column.key, sql_type)
+        })
+        .collect();
+    let names: Vec<_> = columns.iter().map(|column| column.key.as_ref()).collect();
+    let placeholders = vec!["?"; columns.len()].join(", ");
+
+    let mut connection = rusqlite::Connection::open(result_path)?;
+    let transaction = connection.transaction()?;
+    if !write_options.append {
+        transaction.execute("DROP TABLE IF EXISTS counts", [])?;
+    }
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS counts ({})",
+            definitions.join(", ")
+        ),
+        [],
+    )?;
+    transaction.execute(
+        "CREATE INDEX IF NOT EXISTS counts_count ON counts (count)",
+        [],
+    )?;
+    {
+        let mut statement = transaction.prepare(&format!(
+            "INSERT INTO counts ({}) VALUES ({})",
+            names.join(", "),
+            placeholders
+        ))?;
+        for (index, fields) in output_fields(rows, total_count, write_options).enumerate() {
+            let values = fields.iter().map(|field| match field {
+                Field::Text(value) | Field::Number(value) => value.as_str(),
+            });
+            statement.execute(rusqlite::params_from_iter(values))?;
+            advance_progress(pb, index + 1, write_options.progress_interval);
+        }
+    }
+    pb.set_position(len as u64);
+    transaction.commit()?;
+    Ok((len, WriteStats { sort_elapsed }))
+}
+
+// Parquet 文件中每个行组的最大行数，写入时每次只需缓存一个行组
+const PARQUET_ROW_GROUP_SIZE: usize = 1 << 20;
+
+// ParquetColumn 一个行组中某一列的值
+enum ParquetColumn {
+    Text(Vec<ByteArray>),
+    Integer(Vec<i64>),
+    Real(Vec<f64>),
+}
+
+// 写入 Parquet 文件，列的类型与 SQLite 输出相同：line、tier、positions 为 UTF-8 字符串，percent 为 DOUBLE，其余为 INT64
+fn write_parquet(
+    result_path: &str,
+    data_count: &CountMap,
+    write_options: &WriteOptions,
+    pb: &ProgressBar,
+) -> parquet::errors::Result<(usize, WriteStats)> {
+    let total_count: usize = data_count.values().map(|line_count| line_count.count).sum();
+    let OutputRows {
+        len,
+        rows,
+        sort_elapsed,
+        ..
+    } = output_rows(data_count, write_options);
+    pb.set_length(len as u64);
+    pb.set_position(0);
+
+    let columns = output_columns(write_options);
+    let definitions: Vec<_> = columns
+        .iter()
+        .map(|column| match column.key.as_ref() {
+            "line" | "tier" | "positions" | "source" => {
+                format!("REQUIRED BYTE_ARRAY {} (UTF8);", column.key)
+            }
+            "percent" => format!("REQUIRED DOUBLE {};", column.key),
+            _ => format!("REQUIRED INT64 {};", column.key),
+        })
+        .collect();
+    let schema = parse_message_type(&format!("message counts {{ {} }}", definitions.join(" ")))?;
+    let (file, temp) = TempFile::create(result_path)?;
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Default::default())?;
+
+    let mut fields = output_fields(rows, total_count, write_options).peekable();
+    let mut written = 0;
+    while fields.peek().is_some() {
+        // 按列收集一个行组的值
+        let mut values: Vec<_> = columns
+            .iter()
+            .map(|column| match column.key.as_ref() {
+                "line" | "tier" | "positions" | "source" => ParquetColumn::Text(Vec::new()),
+                "percent" => ParquetColumn::Real(Vec::new()),
+                _ => ParquetColumn::Integer(Vec::new()),
+            })
+            .collect();
+        for row in fields.by_ref().take(PARQUET_ROW_GROUP_SIZE) {
+            for (field, column) in row.into_iter().zip(&mut values) {
+                let (Field::Text(value) | Field::Number(value)) = field;
+                match column {
+                    ParquetColumn::Text(column) => column.push(ByteArray::from(value.into_bytes())),
+                    ParquetColumn::Integer(column) => column.push(value.parse().unwrap_or(0)),
+                    ParquetColumn::Real(column) => column.push(value.parse().unwrap_or(0.0)),
+                }
+            }
+            written += 1;
+            advance_progress(pb, written, write_options.progress_interval);
+        }
+
+        let mut row_group = writer.next_row_group()?;
+        for column in &values {
+            let Some(mut column_writer) = row_group.next_column()? else {
+                break;
+            };
+            match column {
+                ParquetColumn::Text(values) => {
+                    column_writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(values, None, None)?;
+                }
+                ParquetColumn::Integer(values) => {
+                    column_writer
+                        .typed::<Int64Type>()
+                        .write_batch(values, None, None)?;
+                }
+                ParquetColumn::Real(values) => {
+                    column_writer
+                        .typed::<DoubleType>()
+                        .write_batch(values, None, None)?;
+                }
+            }
+            column_writer.close()?;
+        }
+        row_group.close()?;
+    }
+    writer.close()?;
+    temp.commit()?;
+    pb.set_position(len as u64);
+    Ok((len, WriteStats { sort_elapsed }))
+}
+
+// 每写入 interval 行才更新一次进度条，written 为已写入的行数
+fn advance_progress(pb: &ProgressBar, written: usize, interval: usize) {
+    if written.is_multiple_of(interval.max(1)) {
+        pb.set_position(written as u64);
+    }
+}
+
+// key 所在的分片编号，按 key 的 64 位 xxh3 哈希取余，不同次运行、不同平台的结果都相同
+fn shard_of(key: &str, shards: usize) -> usize {
+    (xxh3_64(key.as_bytes()) % shards.max(1) as u64) as usize
+}
+
+// 按行内容比较，numeric 为 true 时按数值比较，无法解析为数字的行无论升降序都排在最后
+// 给出 collator 时按语言规则比较文本，规则认为相等的不同行再按字节比较，保证顺序唯一
+fn compare_lines(
+    a: &str,
+    b: &str,
+    numeric: bool,
+    ascending: bool,
+    collator: Option<&CollatorBorrowed<'static>>,
+) -> Ordering {
+    let directed = |ordering: Ordering| {
+        if ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    };
+    if numeric {
+        match (parse_number(a), parse_number(b)) {
+            (Some(x), Some(y)) => return directed(x.total_cmp(&y).then_with(|| a.cmp(b))),
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => {}
+        }
+    }
+    let by_text = match collator {
+        Some(collator) => collator.compare(a, b).then_with(|| a.cmp(b)),
+        None => a.cmp(b),
+    };
+    directed(by_text)
+}
+
+// 按环境变量中的语言创建排序规则，无法识别的语言使用 Unicode 默认规则，
+// 排序规则数据编译在程序中，创建失败时退回按字节比较
+fn locale_collator() -> Option<CollatorBorrowed<'static>> {
+    let prefs: CollatorPreferences = ["LC_ALL", "LC_COLLATE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            // 去掉 zh_CN.UTF-8、de_DE@euro 中的编码和修饰部分，下划线换成 BCP 47 的连字符
+            let tag = value.split(['.', '@']).next().unwrap_or_default();
+            Locale::try_from_str(&tag.replace('_', "-")).ok()
+        })
+        .map(|locale| (&locale).into())
+        .unwrap_or_default();
+    CollatorBorrowed::try_new(prefs, CollatorOptions::default()).ok()
+}
+
+// 把一行解析为数字，整数和小数都按 f64 处理，NaN 视为无法解析
+fn parse_number(line: &str) -> Option<f64> {
+    line.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| !value.is_nan())
+}
+
+// 根据输出选项确定输出的列及其顺序
+fn result_columns(write_options: &WriteOptions) -> Vec<ResultColumn> {
+    if let Some(columns) = &write_options.columns {
+        return columns.clone();
+    }
+    let mut columns = vec![ResultColumn::Line];
+    if write_options.show_key {
+        columns.push(ResultColumn::Key);
+    }
+    columns.push(ResultColumn::Count);
+    if write_options.percent {
+        columns.push(ResultColumn::Percent);
+    }
+    if write_options.cumulative {
+        columns.push(ResultColumn::Cumulative);
+    }
+    if write_options.first_seen {
+        columns.push(ResultColumn::FirstSeen);
+    }
+    if !write_options.tiers.is_empty() {
+        columns.push(ResultColumn::Tier);
+    }
+    if write_options.positions {
+        columns.push(ResultColumn::Positions);
+    }
+    if write_options.tag.is_some() {
+        columns.push(ResultColumn::Source);
+    }
+    columns
+}
+
+// 输出的每一列的表头及 JSON 字段名
+fn output_columns(write_options: &WriteOptions) -> Vec<OutputColumn> {
+    result_columns(write_options)
+        .into_iter()
+        .map(|column| {
+            let (header, key) = match column {
+                ResultColumn::Line => (write_options.line_header.clone(), "line"),
+                ResultColumn::Key => (String::from("Key"), "key"),
+                ResultColumn::Count => (write_options.count_header.clone(), "count"),
+                ResultColumn::Percent => (String::from("Percent"), "percent"),
+                ResultColumn::Cumulative => (String::from("Cumulative"), "cumulative"),
+                ResultColumn::FirstSeen => (String::from("FirstSeen"), "first_seen"),
+                ResultColumn::Tier => (String::from("Tier"), "tier"),
+                ResultColumn::Positions => (String::from("Positions"), "positions"),
+                ResultColumn::Source => (String::from("Source"), "source"),
+            };
+            OutputColumn {
+                header,
+                key: Cow::Borrowed(key),
+            }
+        })
+        .collect()
+}
+
+// 输出的换行符，开启 crlf 时为 Windows 风格的 \r\n
+fn newline(write_options: &WriteOptions) -> &'static str {
+    if write_options.crlf {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+// 写入表头，JSON 数组格式写入起始括号
+fn write_header(
+    result_file: &mut impl Write,
+    write_options: &WriteOptions,
+    columns: &[OutputColumn],
+) -> std::io::Result<()> {
+    if write_options.bom {
+        result_file.write_all(UTF8_BOM)?;
+    }
+    let newline = newline(write_options);
+    match write_options.format {
+        OutputFormat::Csv | OutputFormat::Tsv if write_options.no_header => Ok(()),
+        OutputFormat::Csv => {
+            let headers = columns
+                .iter()
+                .map(|column| escape_csv_field(&column.header, write_options))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            write!(
+                result_file,
+                "{}{}",
+                headers.join(&write_options.output_delimiter),
+                newline
+            )
+        }
+        OutputFormat::Tsv => {
+            let headers: Vec<_> = columns
+                .iter()
+                .map(|column| escape_tsv_field(&column.header))
+                .collect();
+            write!(result_file, "{}{}", headers.join("\t"), newline)
+        }
+        OutputFormat::Json if write_options.summary => {
+            write!(result_file, "{{{}  \"data\": [", newline)
+        }
+        OutputFormat::Json => write!(result_file, "["),
+        OutputFormat::Ndjson => Ok(()),
+        // SQLite 和 Parquet 输出由 write_sqlite、write_parquet 处理，不会经过这里
+        OutputFormat::Sqlite | OutputFormat::Parquet => unreachable!(),
+    }
+}
+
+// 按输出格式写入一行数据，index 为该行在输出中的序号
+fn write_row(
+    result_file: &mut impl Write,
+    write_options: &WriteOptions,
+    columns: &[OutputColumn],
+    fields: &[Field],
+    index: usize,
+) -> std::io::Result<()> {
+    let newline = newline(write_options);
+    match write_options.format {
+        OutputFormat::Csv => {
+            let values = fields
+                .iter()
+                .map(|field| match field {
+                    Field::Text(text) => escape_csv_field(text, write_options),
+                    // 数字不会包含分隔符，只在 always 时加引号
+                    Field::Number(number) if write_options.quoting == Quoting::Always => {
+                        escape_csv_field(number, write_options)
+                    }
+                    Field::Number(number) => Ok(Cow::Borrowed(number.as_str())),
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+            write!(
+                result_file,
+                "{}{}",
+                values.join(&write_options.output_delimiter),
+                newline
+            )
+        }
+        OutputFormat::Tsv => {
+            let values: Vec<_> = fields
+                .iter()
+                .map(|field| match field {
+                    Field::Text(text) => escape_tsv_field(text),
+                    Field::Number(number) => Cow::Borrowed(number.as_str()),
+                })
+                .collect();
+            write!(result_file, "{}{}", values.join("\t"), newline)
+        }
+        OutputFormat::Json => {
+            let separator = if index == 0 { "" } else { "," };
+            // 带汇总时数组嵌套在对象的 data 字段中，多缩进一层
+            let indent = if write_options.summary { "    " } else { "  " };
+            write!(result_file, "{}{}", separator, newline)?;
+            write!(
+                result_file,
+                "{}{}",
+                indent,
+                json_object(columns, fields, ": ", ", ")
+            )
+        }
+        OutputFormat::Ndjson => write!(
+            result_file,
+            "{}{}",
+            json_object(columns, fields, ":", ","),
+            newline
+        ),
+        OutputFormat::Sqlite | OutputFormat::Parquet => unreachable!(),
+    }
+}
+
+// 在 --head 与 --tail 两段之间写入省略行，只有 CSV 和 TSV 写入，其他格式保持结构合法
+fn write_gap(result_file: &mut impl Write, write_options: &WriteOptions) -> std::io::Result<()> {
+    match write_options.format {
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            write!(result_file, "...{}", newline(write_options))
+        }
+        _ => Ok(()),
+    }
+}
+
+// 写入结尾，JSON 数组格式写入结束括号；开启汇总时写入不同行数 distinct 与出现次数总和 total
+// CSV、TSV 追加一行 TOTAL,<distinct>,<total>，JSON 写为顶层的 summary 字段，NDJSON 追加一个 summary 对象
+// CSV、TSV 的汇总行用空字段补齐到与表头相同的 columns 列，列数不足 3 列时不写入汇总行
+fn write_footer(
+    result_file: &mut impl Write,
+    write_options: &WriteOptions,
+    columns: usize,
+    distinct: usize,
+    total: usize,
+) -> std::io::Result<()> {
+    let newline = newline(write_options);
+    let fields = || {
+        let mut fields = vec![
+            String::from("TOTAL"),
+            distinct.to_string(),
+            total.to_string(),
+        ];
+        fields.resize(columns, String::new());
+        fields
+    };
+    match write_options.format {
+        OutputFormat::Csv if write_options.summary && columns >= 3 => {
+            let fields = fields();
+            let values = fields
+                .iter()
+                .map(|field| escape_csv_field(field, write_options))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            write!(
+                result_file,
+                "{}{}",
+                values.join(&write_options.output_delimiter),
+                newline
+            )
+        }
+        OutputFormat::Tsv if write_options.summary && columns >= 3 => {
+            write!(result_file, "{}{}", fields().join("\t"), newline)
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => Ok(()),
+        OutputFormat::Json if write_options.summary => write!(
+            result_file,
+            "{newline}  ],{newline}  \"summary\": {{\"distinct\": {distinct}, \"total\": {total}}}{newline}}}{newline}",
+        ),
+        OutputFormat::Json => write!(result_file, "{newline}]{newline}"),
+        OutputFormat::Ndjson if write_options.summary => write!(
+            result_file,
+            "{{\"summary\":{{\"distinct\":{},\"total\":{}}}}}{}",
+            distinct, total, newline
+        ),
+        OutputFormat::Ndjson => Ok(()),
+        OutputFormat::Sqlite | OutputFormat::Parquet => unreachable!(),
+    }
+}
+
+// 把一行数据拼成 JSON 对象，colon 和 comma 控制是否带空格
+fn json_object(columns: &[OutputColumn], fields: &[Field], colon: &str, comma: &str) -> String {
+    let members: Vec<_> = columns
+        .iter()
+        .zip(fields)
+        .map(|(column, field)| {
+            let value = match field {
+                Field::Text(text) => escape_json_string(text),
+                Field::Number(number) => number.clone(),
+            };
+            format!("{}{}{}", escape_json_string(&column.key), colon, value)
+        })
+        .collect();
+    format!("{{{}}}", members.join(comma))
+}
+
+// 按引号规则转义 CSV 字段：minimal 时按 RFC 4180 在包含分隔符、双引号或换行时用双引号包裹，内部双引号加倍
+fn escape_csv_field<'a>(
+    field: &'a str,
+    write_options: &WriteOptions,
+) -> std::io::Result<Cow<'a, str>> {
+    let delimiter = write_options.output_delimiter.as_str();
+    let breaks_row =
+        field.contains(['\r', '\n']) || (!delimiter.is_empty() && field.contains(delimiter));
+    let quoted = || Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")));
+    match write_options.quoting {
+        Quoting::Always => Ok(quoted()),
+        Quoting::Minimal if breaks_row || field.contains('"') => Ok(quoted()),
+        Quoting::Never if breaks_row => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "field {:?} contains the delimiter or a line break and cannot be written without quotes",
+                field
+            ),
+        )),
+        _ => Ok(Cow::Borrowed(field)),
+    }
+}
+
+// 转义 TSV 字段：反斜杠、制表符和换行写为 \\、\t、\n、\r，保证每行的列数不变
+fn escape_tsv_field(field: &str) -> Cow<'_, str> {
+    if !field.contains(['\\', '\t', '\r', '\n']) {
+        return Cow::Borrowed(field);
+    }
+    let mut escaped = String::with_capacity(field.len() + 2);
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+// 将字符串转义为带双引号的 JSON 字符串字面量
+fn escape_json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// 打开输出目标，"-" 表示标准输出，按 compression 压缩输出
+// append 为 true 时追加到文件末尾，压缩输出会追加一个新的 gzip 成员或 zstd 帧
+// 输出目标带缓冲，避免每写一行就产生一次系统调用，finish 时统一刷新
+// 覆盖写入时先写临时文件，中途出错或崩溃时已有的结果文件不会只剩一半
+pub(crate) fn open_output(
+    result_path: &str,
+    compression: OutputCompression,
+    append: bool,
+) -> std::io::Result<ResultWriter> {
+    let mut temp = None;
+    let writer: Box<dyn Write> = if result_path == "-" {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else if append {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(result_path)?;
+        Box::new(BufWriter::new(file))
+    } else {
+        let (file, temp_file) = TempFile::create(result_path)?;
+        temp = Some(temp_file);
+        Box::new(BufWriter::new(file))
+    };
+    let encoder = match compression {
+        OutputCompression::None => Encoder::Plain(writer),
+        OutputCompression::Gzip => {
+            Encoder::Gzip(Box::new(GzEncoder::new(writer, Compression::default())))
+        }
+        OutputCompression::Zstd => Encoder::Zstd(Box::new(ZstdEncoder::new(writer, 0)?)),
+    };
+    Ok(ResultWriter { encoder, temp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::{count_occurrences, CountMode, CountOptions};
+    use crate::test_util::TestFile;
+    use std::io::Read;
+
+    // 统计 input 后按 write_options 输出为字符串
+    fn render(input: &str, write_options: &WriteOptions) -> String {
+        let data_count = count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+        let mut output = Vec::new();
+        write_results(&mut output, &data_count, write_options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn summary_row_totals_match_input() {
+        let write_options = WriteOptions {
+            summary: true,
+            percent: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\nc\nc\nc\nd\n", &write_options);
+        assert_eq!(output.lines().last(), Some("TOTAL,4,7"));
+    }
+
+    #[test]
+    fn summary_row_is_padded_to_header_width() {
+        let write_options = WriteOptions {
+            summary: true,
+            percent: true,
+            cumulative: true,
+            first_seen: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\n", &write_options);
+        let widths: Vec<_> = output.lines().map(|line| line.split(',').count()).collect();
+        assert!(widths.iter().all(|width| *width == 5), "{output}");
+        assert_eq!(output.lines().last(), Some("TOTAL,2,3,,"));
+    }
+
+    #[test]
+    fn summary_row_is_omitted_when_it_does_not_fit() {
+        let write_options = WriteOptions {
+            summary: true,
+            format: OutputFormat::Tsv,
+            ..WriteOptions::default()
+        };
+        assert!(!write_options.summary_fits());
+        let output = render("a\nb\na\n", &write_options);
+        assert_eq!(output, "Line\tCount\na\t2\nb\t1\n");
+    }
+
+    #[test]
+    fn summary_is_a_top_level_json_field() {
+        let write_options = WriteOptions {
+            summary: true,
+            format: OutputFormat::Json,
+            ..WriteOptions::default()
+        };
+        assert!(write_options.summary_fits());
+        let output = render("a\nb\na\n", &write_options);
+        assert!(output.starts_with("{\n  \"data\": ["), "{output}");
+        assert!(
+            output.ends_with("  \"summary\": {\"distinct\": 2, \"total\": 3}\n}\n"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn csv_fields_with_commas_and_quotes_round_trip() {
+        let output = render("a,b\"c\nplain\na,b\"c\n", &WriteOptions::default());
+        assert_eq!(output, "Line,Count\n\"a,b\"\"c\",2\nplain,1\n");
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        let rows: Vec<(String, usize)> = reader.deserialize().map(|row| row.unwrap()).collect();
+        assert_eq!(
+            rows,
+            [(String::from("a,b\"c"), 2), (String::from("plain"), 1)]
+        );
+    }
+
+    #[test]
+    fn top_keeps_the_most_frequent_lines() {
+        let write_options = WriteOptions {
+            top: Some(2),
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\nb\nc\nc\nc\nd\nb\nc\n", &write_options);
+        assert_eq!(output, "Line,Count\nc,4\nb,3\n");
+    }
+
+    #[test]
+    fn min_count_drops_singletons() {
+        let write_options = WriteOptions {
+            min_count: 2,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\nc\nd\nd\nd\n", &write_options);
+        assert_eq!(output, "Line,Count\nd,3\na,2\n");
+    }
+
+    #[test]
+    fn json_output_parses_in_order_with_escaping() {
+        let write_options = WriteOptions {
+            format: OutputFormat::Json,
+            ..WriteOptions::default()
+        };
+        let output = render("say \"hi\"\\\nb\nsay \"hi\"\\\ntab\there\n", &write_options);
+        let rows: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            rows,
+            serde_json::json!([
+                {"line": "say \"hi\"\\", "count": 2},
+                {"line": "b", "count": 1},
+                {"line": "tab\there", "count": 1},
+            ])
+        );
+    }
+
+    #[test]
+    fn ndjson_lines_deserialize_independently() {
+        let write_options = WriteOptions {
+            format: OutputFormat::Ndjson,
+            ..WriteOptions::default()
+        };
+        let output = render("a\n{\"x\": 1}\na\n", &write_options);
+        let rows: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                serde_json::json!({"line": "a", "count": 2}),
+                serde_json::json!({"line": "{\"x\": 1}", "count": 1}),
+            ]
+        );
+    }
+
+    #[test]
+    fn gzip_output_decompresses_to_csv() {
+        let data_count =
+            count_occurrences("a\nb\na\n".as_bytes(), &CountOptions::default()).unwrap();
+        let file = TestFile::empty("result.csv.gz");
+        let write_options = WriteOptions {
+            compression: OutputCompression::from_path(file.path()),
+            ..WriteOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        write_sort_and_merge_result(file.path(), &data_count, &write_options, &pb).unwrap();
+        let compressed = std::fs::read(file.path()).unwrap();
+        let mut output = String::new();
+        flate2::read::MultiGzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "Line,Count\na,2\nb,1\n");
+    }
+
+    #[test]
+    fn ascending_puts_smallest_count_first() {
+        let write_options = WriteOptions {
+            ascending: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\nc\nc\nc\n", &write_options);
+        assert_eq!(output.lines().nth(1), Some("b,1"));
+        assert_eq!(output, "Line,Count\nb,1\na,2\nc,3\n");
+    }
+
+    #[test]
+    fn equal_counts_have_a_fixed_order() {
+        let expected = "Line,Count\nalpha,1\nbeta,1\ndelta,1\ngamma,1\n";
+        for input in ["gamma\nalpha\ndelta\nbeta\n", "beta\ndelta\nalpha\ngamma\n"] {
+            assert_eq!(render(input, &WriteOptions::default()), expected);
+        }
+    }
+
+    #[test]
+    fn percentages_sum_to_one_hundred() {
+        let write_options = WriteOptions {
+            percent: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\nc\na\nb\na\n", &write_options);
+        assert_eq!(
+            output,
+            "Line,Count,Percent\na,3,50.00\nb,2,33.33\nc,1,16.67\n"
+        );
+        let sum: f64 = output
+            .lines()
+            .skip(1)
+            .map(|line| line.rsplit(',').next().unwrap().parse::<f64>().unwrap())
+            .sum();
+        assert!((sum - 100.0).abs() < 0.05, "{sum}");
+    }
+
+    #[test]
+    fn library_api_counts_and_writes_in_memory() {
+        let data_count =
+            crate::count_occurrences(std::io::Cursor::new("x\ny\nx\n"), &CountOptions::default())
+                .unwrap();
+        assert_eq!(data_count["x"].count, 2);
+        let mut output = Vec::new();
+        crate::write_results(&mut output, &data_count, &WriteOptions::default()).unwrap();
+        assert_eq!(output, b"Line,Count\nx,2\ny,1\n");
+    }
+
+    #[test]
+    fn unique_only_keeps_singletons() {
+        let write_options = WriteOptions {
+            unique_only: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\nc\nd\nd\n", &write_options);
+        assert_eq!(output, "Line,Count\nb,1\nc,1\n");
+    }
+
+    #[test]
+    fn duplicates_only_keeps_repeated_lines_in_order() {
+        let write_options = WriteOptions {
+            duplicates_only: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\nc\nd\nd\nd\n", &write_options);
+        assert_eq!(output, "Line,Count\nd,3\na,2\n");
+    }
+
+    #[test]
+    fn custom_headers_and_no_header() {
+        let custom = WriteOptions {
+            line_header: String::from("Value"),
+            count_header: String::from("Hits"),
+            ..WriteOptions::default()
+        };
+        assert_eq!(render("a\na\n", &custom), "Value,Hits\na,2\n");
+        let no_header = WriteOptions {
+            no_header: true,
+            ..custom
+        };
+        assert_eq!(render("a\na\n", &no_header), "a,2\n");
+    }
+
+    #[test]
+    fn write_phase_uses_row_count_as_progress_length() {
+        let data_count =
+            count_occurrences("a\nb\na\nc\n".as_bytes(), &CountOptions::default()).unwrap();
+        // 读取阶段结束时进度条的长度和位置是输入的字节数
+        let pb = ProgressBar::hidden();
+        pb.set_length(8);
+        pb.set_position(8);
+        let file = TestFile::empty("progress.csv");
+        let (written, _) =
+            write_sort_and_merge_result(file.path(), &data_count, &WriteOptions::default(), &pb)
+                .unwrap();
+        assert_eq!(written, 3);
+        assert_eq!(pb.length(), Some(3));
+        assert_eq!(pb.position(), 3);
+    }
+
+    #[test]
+    fn last_cumulative_equals_total() {
+        let write_options = WriteOptions {
+            cumulative: true,
+            ..WriteOptions::default()
+        };
+        let input = "a\nb\na\nc\nc\nc\nd\n";
+        let output = render(input, &write_options);
+        assert_eq!(
+            output,
+            "Line,Count,Cumulative\nc,3,3\na,2,5\nb,1,6\nd,1,7\n"
+        );
+        let last = output.lines().last().unwrap().rsplit(',').next().unwrap();
+        assert_eq!(last.parse::<usize>().unwrap(), input.lines().count());
+    }
+
+    #[test]
+    fn numeric_sort_orders_by_value() {
+        let write_options = WriteOptions {
+            sort_by: SortKey::Line,
+            numeric: true,
+            ascending: true,
+            no_header: true,
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            render("2\n10\nx\n1\n", &write_options),
+            "1,1\n2,1\n10,1\nx,1\n"
+        );
+        // 不按数值比较时是字典序
+        let write_options = WriteOptions {
+            numeric: false,
+            ..write_options
+        };
+        assert_eq!(render("2\n10\n1\n", &write_options), "1,1\n10,1\n2,1\n");
+    }
+
+    #[test]
+    fn sort_by_line_in_both_directions() {
+        let ascending = WriteOptions {
+            sort_by: SortKey::Line,
+            ascending: true,
+            no_header: true,
+            ..WriteOptions::default()
+        };
+        let input = "pear\napple\npear\nfig\n";
+        assert_eq!(render(input, &ascending), "apple,1\nfig,1\npear,2\n");
+        let descending = WriteOptions {
+            ascending: false,
+            ..ascending
+        };
+        assert_eq!(render(input, &descending), "pear,2\nfig,1\napple,1\n");
+    }
+
+    #[test]
+    fn append_writes_one_header() {
+        let file = TestFile::empty("append.csv");
+        let write_options = WriteOptions {
+            append: true,
+            ..WriteOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        for input in ["a\n", "b\nb\n"] {
+            let data_count = count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+            write_sort_and_merge_result(file.path(), &data_count, &write_options, &pb).unwrap();
+        }
+        assert_eq!(file.read(), "Line,Count\na,1\nb,2\n");
+    }
+
+    #[test]
+    fn first_seen_line_numbers() {
+        let write_options = WriteOptions {
+            first_seen: true,
+            ..WriteOptions::default()
+        };
+        let output = render("b\na\nb\nc\na\nb\n", &write_options);
+        assert_eq!(output, "Line,Count,FirstSeen\nb,3,1\na,2,2\nc,1,4\n");
+    }
+
+    #[test]
+    fn sqlite_output_can_be_queried() {
+        let data_count =
+            count_occurrences("a\nb\na\nc\na\n".as_bytes(), &CountOptions::default()).unwrap();
+        let file = TestFile::empty("result.db");
+        let write_options = WriteOptions {
+            format: OutputFormat::from_path(file.path()),
+            ..WriteOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        let (written, _) =
+            write_sort_and_merge_result(file.path(), &data_count, &write_options, &pb).unwrap();
+        assert_eq!(written, 3);
+        let connection = rusqlite::Connection::open(file.path()).unwrap();
+        let top: (String, i64) = connection
+            .query_row(
+                "SELECT line, count FROM counts ORDER BY count DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(top, (String::from("a"), 3));
+        let total: i64 = connection
+            .query_row("SELECT SUM(count) FROM counts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn no_sort_writes_all_rows() {
+        let write_options = WriteOptions {
+            no_sort: true,
+            no_header: true,
+            ..WriteOptions::default()
+        };
+        let input: String = (0..1000).map(|i| format!("line{}\n", i % 300)).collect();
+        let output = render(&input, &write_options);
+        let mut rows: Vec<_> = output.lines().collect();
+        rows.sort();
+        let mut expected: Vec<_> = render(
+            &input,
+            &WriteOptions {
+                no_header: true,
+                ..WriteOptions::default()
+            },
+        )
+        .lines()
+        .map(String::from)
+        .collect();
+        expected.sort();
+        assert_eq!(rows, expected);
+        assert_eq!(rows.len(), 300);
+    }
+
+    #[test]
+    fn tsv_escapes_tabs_and_backslashes() {
+        let write_options = WriteOptions {
+            format: OutputFormat::Tsv,
+            ..WriteOptions::default()
+        };
+        let output = render("a\tb\na\tb\nback\\slash\n", &write_options);
+        assert_eq!(output, "Line\tCount\na\\tb\t2\nback\\\\slash\t1\n");
+        assert!(output.lines().all(|line| line.split('\t').count() == 2));
+    }
+
+    #[test]
+    fn custom_delimiter_quotes_only_when_needed() {
+        let write_options = WriteOptions {
+            output_delimiter: String::from(";"),
+            ..WriteOptions::default()
+        };
+        let output = render("x,y;z\nx,y\nx,y\n", &write_options);
+        assert_eq!(output, "Line;Count\nx,y;2\n\"x,y;z\";1\n");
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(output.as_bytes());
+        let lines: Vec<String> = reader
+            .records()
+            .map(|record| record.unwrap()[0].to_string())
+            .collect();
+        assert_eq!(lines, ["x,y", "x,y;z"]);
+    }
+
+    #[test]
+    fn head_and_tail_rows() {
+        let input = "a\na\na\na\na\nb\nb\nb\nb\nc\nc\nc\nd\nd\ne\n";
+        let head = WriteOptions {
+            head: Some(2),
+            no_header: true,
+            ..WriteOptions::default()
+        };
+        assert_eq!(render(input, &head), "a,5\nb,4\n");
+        let tail = WriteOptions {
+            head: None,
+            tail: Some(2),
+            ..head.clone()
+        };
+        assert_eq!(render(input, &tail), "d,2\ne,1\n");
+        let both = WriteOptions {
+            head: Some(1),
+            tail: Some(1),
+            ..head.clone()
+        };
+        let output = render(input, &both);
+        assert!(output.starts_with("a,5\n"), "{output}");
+        assert!(output.ends_with("e,1\n"), "{output}");
+        assert!(!output.contains("c,3"), "{output}");
+        // 两段重叠时每行只输出一次
+        let overlapping = WriteOptions {
+            head: Some(4),
+            tail: Some(4),
+            ..head
+        };
+        assert_eq!(render(input, &overlapping), "a,5\nb,4\nc,3\nd,2\ne,1\n");
+    }
+
+    #[test]
+    fn histogram_of_counts() {
+        let data_count = count_occurrences(
+            "a\na\na\nb\nb\nc\nc\nd\ne\n".as_bytes(),
+            &CountOptions::default(),
+        )
+        .unwrap();
+        let file = TestFile::empty("histogram.csv");
+        write_histogram(Some(file.path()), &data_count).unwrap();
+        // 出现一次的有 d、e，两次的有 b、c，三次的有 a
+        assert_eq!(file.read(), "Count,Lines\n1,2\n2,2\n3,1\n");
+    }
+
+    #[test]
+    fn parquet_output_reads_back() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+
+        let data_count =
+            count_occurrences("a\nb\na\n".as_bytes(), &CountOptions::default()).unwrap();
+        let file = TestFile::empty("result.parquet");
+        let write_options = WriteOptions {
+            format: OutputFormat::from_path(file.path()),
+            percent: true,
+            ..WriteOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        write_sort_and_merge_result(file.path(), &data_count, &write_options, &pb).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(file.path()).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        let names: Vec<_> = schema
+            .columns()
+            .iter()
+            .map(|column| column.name())
+            .collect();
+        assert_eq!(names, ["line", "count", "percent"]);
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                (row.get_string(0).unwrap().clone(), row.get_long(1).unwrap())
+            })
+            .collect();
+        assert_eq!(rows, [(String::from("a"), 2), (String::from("b"), 1)]);
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let write_options = WriteOptions {
+            crlf: true,
+            ..WriteOptions::default()
+        };
+        let output = render("a\nb\na\n", &write_options);
+        assert_eq!(output.as_bytes(), b"Line,Count\r\na,2\r\nb,1\r\n");
+        assert_eq!(render("a\n", &WriteOptions::default()), "Line,Count\na,1\n");
+    }
+
+    #[test]
+    fn tiers_bucket_lines_by_count() {
+        let write_options = WriteOptions {
+            tiers: vec![2, 3],
+            ..WriteOptions::default()
+        };
+        let output = render("a\na\na\na\nb\nb\nb\nc\nc\nd\n", &write_options);
+        assert_eq!(output, "Line,Count,Tier\na,4,3+\nb,3,3+\nc,2,2\nd,1,1\n");
+    }
+
+    #[test]
+    fn append_to_seeded_file_skips_the_header() {
+        let file = TestFile::new("seeded.csv", "Line,Count\nold,4\n");
+        let data_count = count_occurrences("new\n".as_bytes(), &CountOptions::default()).unwrap();
+        let write_options = WriteOptions {
+            append: true,
+            ..WriteOptions::default()
+        };
+        let pb = ProgressBar::hidden();
+        write_sort_and_merge_result(file.path(), &data_count, &write_options, &pb).unwrap();
+        assert_eq!(file.read(), "Line,Count\nold,4\nnew,1\n");
+        assert_eq!(file.read().matches("Line,Count").count(), 1);
+        // 指定 force_header 时仍写入表头
+        let write_options = WriteOptions {
+            force_header: true,
+            ..write_options
+        };
+        write_sort_and_merge_result(file.path(), &data_count, &write_options, &pb).unwrap();
+        assert_eq!(file.read(), "Line,Count\nold,4\nnew,1\nLine,Count\nnew,1\n");
+    }
+
+    // 按 count_options 统计 input 后按 write_options 输出为字符串
+    fn render_counted(
+        input: &str,
+        count_options: &CountOptions,
+        write_options: &WriteOptions,
+    ) -> String {
+        let data_count = count_occurrences(input.as_bytes(), count_options).unwrap();
+        let mut output = Vec::new();
+        write_results(&mut output, &data_count, write_options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn chars_mode_counts_characters() {
+        let count_options = CountOptions {
+            mode: CountMode::Chars,
+            ..CountOptions::default()
+        };
+        let write_options = WriteOptions {
+            line_header: String::from("Char"),
+            ..WriteOptions::default()
+        };
+        // 换行符不计入，制表符转义后输出
+        let output = render_counted("héé\na\tb\n", &count_options, &write_options);
+        assert_eq!(output, "Char,Count\né,2\n\\t,1\na,1\nb,1\nh,1\n");
+    }
+
+    #[test]
+    fn quoting_policies() {
+        let input = "plain\nq\"r\n";
+        let quoted = |quoting| {
+            render(
+                input,
+                &WriteOptions {
+                    quoting,
+                    ..WriteOptions::default()
+                },
+            )
+        };
+        assert_eq!(
+            quoted(Quoting::Minimal),
+            "Line,Count\nplain,1\n\"q\"\"r\",1\n"
+        );
+        assert_eq!(
+            quoted(Quoting::Always),
+            "\"Line\",\"Count\"\n\"plain\",\"1\"\n\"q\"\"r\",\"1\"\n"
+        );
+        assert_eq!(quoted(Quoting::Never), "Line,Count\nplain,1\nq\"r,1\n");
+
+        // 不加引号时含分隔符的行无法写出，报错而不是写出错位的列
+        let data_count = count_occurrences("a,b\n".as_bytes(), &CountOptions::default()).unwrap();
+        let write_options = WriteOptions {
+            quoting: Quoting::Never,
+            ..WriteOptions::default()
+        };
+        let err = write_results(Vec::new(), &data_count, &write_options).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn top_percent_cutoff_row() {
+        let input = "a\na\na\na\nb\nb\nb\nc\nc\nd\n";
+        let top_percent = |percent| {
+            render(
+                input,
+                &WriteOptions {
+                    top_percent: Some(percent),
+                    no_header: true,
+                    ..WriteOptions::default()
+                },
+            )
+        };
+        // a 占 40%，a、b 累计 70%，a、b、c 累计 90%
+        assert_eq!(top_percent(40.0), "a,4\n");
+        assert_eq!(top_percent(50.0), "a,4\nb,3\n");
+        assert_eq!(top_percent(70.0), "a,4\nb,3\n");
+        assert_eq!(top_percent(71.0), "a,4\nb,3\nc,2\n");
+        assert_eq!(top_percent(100.0), "a,4\nb,3\nc,2\nd,1\n");
+    }
+
+    #[test]
+    fn empty_keys_use_the_placeholder() {
+        let count_options = CountOptions {
+            column: vec![1],
+            ..CountOptions::default()
+        };
+        let write_options = WriteOptions {
+            empty_placeholder: Some(String::from("(empty)")),
+            ..WriteOptions::default()
+        };
+        let input = "a,x\nb,\nc\nd,x\n";
+        let output = render_counted(input, &count_options, &write_options);
+        assert_eq!(output, "Line,Count\n(empty),2\nx,2\n");
+        let output = render_counted(input, &count_options, &WriteOptions::default());
+        assert_eq!(output, "Line,Count\n,2\nx,2\n");
+    }
+
+    #[test]
+    fn tiebreak_variants() {
+        let input = "b\nc\na\nc\n";
+        let tiebreak = |tiebreak| {
+            render(
+                input,
+                &WriteOptions {
+                    tiebreak,
+                    no_header: true,
+                    ..WriteOptions::default()
+                },
+            )
+        };
+        assert_eq!(tiebreak(Tiebreak::LineAsc), "c,2\na,1\nb,1\n");
+        assert_eq!(tiebreak(Tiebreak::LineDesc), "c,2\nb,1\na,1\n");
+        assert_eq!(tiebreak(Tiebreak::FirstSeen), "c,2\nb,1\na,1\n");
+        let ascending = |tiebreak| {
+            render(
+                "b\nc\na\n",
+                &WriteOptions {
+                    tiebreak,
+                    ascending: true,
+                    no_header: true,
+                    ..WriteOptions::default()
+                },
+            )
+        };
+        assert_eq!(ascending(Tiebreak::FirstSeen), "b,1\nc,1\na,1\n");
+        assert_eq!(ascending(Tiebreak::LineAsc), "a,1\nb,1\nc,1\n");
+    }
+
+    #[test]
+    fn sorted_results_iterates_in_order() {
+        let data_count =
+            count_occurrences("x\ny\nz\ny\nz\nz\n".as_bytes(), &CountOptions::default()).unwrap();
+        let write_options = WriteOptions {
+            top: Some(2),
+            ..WriteOptions::default()
+        };
+        let rows: Vec<_> = sorted_results(&data_count, &write_options)
+            .map(|row| (row.line, row.count, row.first_seen, row.cumulative))
+            .collect();
+        assert_eq!(
+            rows,
+            [(String::from("z"), 3, 3, 3), (String::from("y"), 2, 2, 5)]
+        );
+    }
+
+    // 写入若干字节后返回错误的 writer
+    struct FailingWriter<W> {
+        inner: W,
+        remaining: usize,
+    }
+
+    impl<W: Write> Write for FailingWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(std::io::Error::other("injected write error"));
+            }
+            let n = buf.len().min(self.remaining);
+            self.remaining -= n;
+            self.inner.write(&buf[..n])
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn failed_write_keeps_the_original_file() {
+        let file = TestFile::new("atomic.csv", "Line,Count\nold,1\n");
+        let data_count =
+            count_occurrences("a\nb\nc\n".as_bytes(), &CountOptions::default()).unwrap();
+        let (temp_file, temp) = TempFile::create(file.path()).unwrap();
+        let temp_path = temp.temp_path.clone();
+        let mut writer = FailingWriter {
+            inner: temp_file,
+            remaining: 15,
+        };
+        assert!(write_results(&mut writer, &data_count, &WriteOptions::default()).is_err());
+        // 没有 commit 的临时文件被删除，结果文件保持原样
+        drop(temp);
+        assert!(!temp_path.exists());
+        assert_eq!(file.read(), "Line,Count\nold,1\n");
+
+        // 写入成功后才替换结果文件
+        let pb = ProgressBar::hidden();
+        write_sort_and_merge_result(file.path(), &data_count, &WriteOptions::default(), &pb)
+            .unwrap();
+        assert_eq!(file.read(), "Line,Count\na,1\nb,1\nc,1\n");
+    }
+
+    #[test]
+    fn sort_by_length() {
+        let write_options = WriteOptions {
+            sort_by: SortKey::Length,
+            no_header: true,
+            ..WriteOptions::default()
+        };
+        let input = "ccc\na\nbb\nbb\ndd\n";
+        assert_eq!(render(input, &write_options), "ccc,1\nbb,2\ndd,1\na,1\n");
+        let ascending = WriteOptions {
+            ascending: true,
+            ..write_options
+        };
+        assert_eq!(render(input, &ascending), "a,1\nbb,2\ndd,1\nccc,1\n");
+    }
+
+    #[test]
+    fn columns_in_reversed_order() {
+        let write_options = WriteOptions {
+            columns: Some(vec![ResultColumn::Count, ResultColumn::Line]),
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            render("a\nb\na\n", &write_options),
+            "Count,Line\n2,a\n1,b\n"
+        );
+        let write_options = WriteOptions {
+            columns: Some(vec![ResultColumn::FirstSeen, ResultColumn::Line]),
+            format: OutputFormat::Ndjson,
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            render("a\nb\na\n", &write_options),
+            "{\"first_seen\":1,\"line\":\"a\"}\n{\"first_seen\":2,\"line\":\"b\"}\n"
+        );
+    }
+
+    #[test]
+    fn comparison_delta_rows() {
+        let data_count = count_occurrences(
+            "a\na\na\na\nb\nb\nb\nc\nc\nd\n".as_bytes(),
+            &CountOptions::default(),
+        )
+        .unwrap();
+        let other = count_occurrences("a\nb\nb\ne\n".as_bytes(), &CountOptions::default()).unwrap();
+        let file = TestFile::empty("compare.csv");
+        let pb = ProgressBar::hidden();
+        let written = write_comparison(
+            file.path(),
+            &data_count,
+            &other,
+            &WriteOptions::default(),
+            &pb,
+        )
+        .unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(
+            file.read(),
+            "Line,CountA,CountB,Delta\na,4,1,3\nc,2,0,2\nb,3,2,1\nd,1,0,1\ne,0,1,-1\n"
+        );
+    }
+
+    #[test]
+    fn stable_sort_keeps_first_seen_order_for_equal_rows() {
+        // 空行和真正的 <EMPTY> 输出为相同的内容，按次数和行内容比较时相等
+        for (input, expected) in [
+            ("\n<EMPTY>\n", "<EMPTY>,1,1\n<EMPTY>,1,2\n"),
+            ("<EMPTY>\n\n", "<EMPTY>,1,1\n<EMPTY>,1,2\n"),
+        ] {
+            for sort_buffer in [None, Some(1)] {
+                let write_options = WriteOptions {
+                    empty_placeholder: Some(String::from("<EMPTY>")),
+                    first_seen: true,
+                    no_header: true,
+                    stable_sort: true,
+                    sort_buffer,
+                    ..WriteOptions::default()
+                };
+                let data_count =
+                    count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+                let result = TestFile::empty("stable.csv");
+                write_sort_and_merge_result(
+                    result.path(),
+                    &data_count,
+                    &write_options,
+                    &ProgressBar::hidden(),
+                )
+                .unwrap();
+                assert_eq!(result.read(), expected, "sort_buffer {sort_buffer:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn zstd_input_and_output_round_trip() {
+        let compressed = zstd::encode_all("a\nb\na\n".as_bytes(), 0).unwrap();
+        // 按魔数识别 zstd 输入
+        let input = TestFile::new("input.bin", compressed);
+        let pb = ProgressBar::hidden();
+        let (data_count, _) = crate::count_file(
+            &[input.path().to_string()],
+            2,
+            &CountOptions::default(),
+            &pb,
+        )
+        .unwrap();
+        let output = TestFile::empty("result.csv.zst");
+        let write_options = WriteOptions {
+            compression: OutputCompression::from_path(output.path()),
+            ..WriteOptions::default()
+        };
+        write_sort_and_merge_result(output.path(), &data_count, &write_options, &pb).unwrap();
+        let decoded = zstd::decode_all(File::open(output.path()).unwrap()).unwrap();
+        assert_eq!(decoded, b"Line,Count\na,2\nb,1\n");
+    }
+
+    #[test]
+    fn locale_collation_differs_from_byte_order() {
+        let input = "zebra\n\u{c9}clair\neclair\ncote\nc\u{f4}te\n";
+        let sorted = |collation| {
+            render(
+                input,
+                &WriteOptions {
+                    sort_by: SortKey::Line,
+                    ascending: true,
+                    collation,
+                    no_header: true,
+                    ..WriteOptions::default()
+                },
+            )
+        };
+        let byte = sorted(Collation::Byte);
+        let locale = sorted(Collation::Locale);
+        // 按字节比较时 É 排在所有 ASCII 字母之后
+        assert_eq!(
+            byte,
+            "cote,1\nc\u{f4}te,1\neclair,1\nzebra,1\n\u{c9}clair,1\n"
+        );
+        assert_eq!(
+            locale,
+            "cote,1\nc\u{f4}te,1\neclair,1\n\u{c9}clair,1\nzebra,1\n"
+        );
+    }
+
+    #[test]
+    fn show_key_with_ignore_case() {
+        let count_options = CountOptions {
+            ignore_case: true,
+            keep_original: true,
+            ..CountOptions::default()
+        };
+        let write_options = WriteOptions {
+            show_key: true,
+            ..WriteOptions::default()
+        };
+        let output = render_counted("Foo\nfoo\nFOO\nbar\n", &count_options, &write_options);
+        assert_eq!(output, "Line,Key,Count\nFoo,foo,3\nbar,bar,1\n");
+    }
+
+    #[test]
+    fn shards_partition_the_lines() {
+        let input: String = (0..500).map(|i| format!("k{}\n", i % 120)).collect();
+        let all = render(
+            &input,
+            &WriteOptions {
+                no_header: true,
+                ..WriteOptions::default()
+            },
+        );
+        let mut rows = Vec::new();
+        for index in 0..2 {
+            let write_options = WriteOptions {
+                shard: Some((index, 2)),
+                no_header: true,
+                ..WriteOptions::default()
+            };
+            let shard = render(&input, &write_options);
+            assert!(!shard.is_empty());
+            rows.extend(shard.lines().map(String::from));
+        }
+        let mut expected: Vec<_> = all.lines().map(String::from).collect();
+        rows.sort();
+        expected.sort();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn tiny_sort_buffer_spills_with_the_same_output() {
+        let input: String = (0..5000)
+            .map(|i| format!("k{}\n", i * 7919 % 997))
+            .collect();
+        let data_count = count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+        for write_options in [
+            WriteOptions::default(),
+            WriteOptions {
+                sort_by: SortKey::Line,
+                cumulative: true,
+                top: Some(100),
+                ..WriteOptions::default()
+            },
+        ] {
+            let mut expected = Vec::new();
+            write_results(&mut expected, &data_count, &write_options).unwrap();
+            // 每 10 行就写出一个有序的临时文件，最后归并
+            let spilled = WriteOptions {
+                sort_buffer: Some(10),
+                ..write_options
+            };
+            let mut output = Vec::new();
+            write_results(&mut output, &data_count, &spilled).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                String::from_utf8(expected).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn matrix_has_a_column_per_file() {
+        let count =
+            |input: &str| count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+        let counts = [
+            (String::from("file1"), count("a\na\nb\n")),
+            (String::from("file2"), count("b\nc\n")),
+        ];
+        let file = TestFile::empty("matrix.csv");
+        let pb = ProgressBar::hidden();
+        // 按各文件出现次数之和排序
+        let written = write_matrix(file.path(), &counts, &WriteOptions::default(), &pb).unwrap();
+        assert_eq!(written, 3);
+        assert_eq!(file.read(), "Line,file1,file2\na,2,0\nb,1,1\nc,0,1\n");
+    }
+
+    #[test]
+    fn single_column_in_frequency_order() {
+        let write_options = WriteOptions {
+            columns: Some(vec![ResultColumn::Line]),
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            render("b\na\na\nc\na\nb\n", &write_options),
+            "Line\na\nb\nc\n"
+        );
+    }
+
+    #[test]
+    fn excel_output_has_bom_and_crlf() {
+        let write_options = WriteOptions {
+            bom: true,
+            crlf: true,
+            ..WriteOptions::default()
+        };
+        let output = render("中文\na\n中文\n", &write_options);
+        assert!(output.as_bytes().starts_with(b"\xef\xbb\xbf"));
+        assert_eq!(output, "\u{feff}Line,Count\r\n中文,2\r\na,1\r\n");
+        assert!(!render("a\n", &WriteOptions::default()).starts_with('\u{feff}'));
+    }
+
+    // 记录 flush 调用次数的 writer
+    #[derive(Default)]
+    struct FlushCounter {
+        written: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_interval_flushes_periodically() {
+        let input: String = (0..10).map(|i| format!("k{i}\n")).collect();
+        let data_count = count_occurrences(input.as_bytes(), &CountOptions::default()).unwrap();
+        let flushes = |flush_interval| {
+            let write_options = WriteOptions {
+                flush_interval,
+                no_sort: true,
+                ..WriteOptions::default()
+            };
+            let mut writer = FlushCounter::default();
+            write_results(&mut writer, &data_count, &write_options).unwrap();
+            assert_eq!(writer.written.split(|b| *b == b'\n').count(), 12);
+            writer.flushes
+        };
+        // write_results 结束时总会 flush 一次
+        let baseline = flushes(None);
+        assert_eq!(flushes(Some(3)), baseline + 3);
+        assert_eq!(flushes(Some(1)), baseline + 10);
+    }
+
+    #[test]
+    fn tag_is_written_on_every_row() {
+        let input = "a\nb\na\n";
+        let write_options = WriteOptions {
+            tag: Some(String::from("run,1")),
+            ..WriteOptions::default()
+        };
+        assert_eq!(
+            render(input, &write_options),
+            "Line,Count,Source\na,2,\"run,1\"\nb,1,\"run,1\"\n"
+        );
+        let write_options = WriteOptions {
+            format: OutputFormat::Ndjson,
+            ..write_options
+        };
+        let output = render(input, &write_options);
+        for line in output.lines() {
+            let row: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(row["source"], "run,1");
+        }
+        assert_eq!(output.lines().count(), 2);
+    }
+
+    #[test]
+    fn preserve_first_seen_order() {
+        let write_options = WriteOptions {
+            sort_by: SortKey::FirstSeen,
+            no_header: true,
+            ..WriteOptions::default()
+        };
+        // 与次数无关
+        let output = render("c\na\nb\na\na\nb\nd\n", &write_options);
+        assert_eq!(output, "c,1\na,3\nb,2\nd,1\n");
+    }
+
+    #[test]
+    fn manifest_parses_as_json() {
+        let manifest = Manifest {
+            version: String::from("0.1.0"),
+            started_at: String::from("2026-01-01T00:00:00+00:00"),
+            arguments: vec![String::from("-f"), String::from("in \"1\".txt")],
+            inputs: vec![String::from("in \"1\".txt")],
+            result_path: String::from("result.csv"),
+            lines: 10,
+            distinct: 4,
+            written: 4,
+            elapsed: Duration::from_millis(1500),
+        };
+        let file = TestFile::empty("manifest.json");
+        write_manifest(file.path(), &manifest).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&file.read()).unwrap();
+        assert_eq!(value["inputs"], serde_json::json!(["in \"1\".txt"]));
+        assert_eq!(value["lines"], 10);
+        assert_eq!(value["distinct"], 4);
+        assert_eq!(value["elapsed_seconds"], 1.5);
+    }
+
+    #[test]
+    fn top_per_group_truncates_each_group() {
+        let count_options = CountOptions {
+            column: vec![0, 1],
+            ..CountOptions::default()
+        };
+        let input = "g1,a\ng1,a\ng1,b\ng1,b\ng1,b\ng1,c\ng2,x\ng2,y\ng2,y\n";
+        let data_count = count_occurrences(input.as_bytes(), &count_options).unwrap();
+        let file = TestFile::empty("top-per-group.csv");
+        let pb = ProgressBar::hidden();
+        let written = write_top_per_group(
+            file.path(),
+            &data_count,
+            ",",
+            2,
+            &WriteOptions::default(),
+            &pb,
+        )
+        .unwrap();
+        assert_eq!(written, 4);
+        assert_eq!(
+            file.read(),
+            "Group,Value,Count\ng1,b,3\ng1,a,2\ng2,y,2\ng2,x,1\n"
+        );
+    }
+}
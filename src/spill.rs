@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::output::DataCount;
+
+// 每次归并最多同时打开的分段文件数，分段更多时先分组归并成较少的分段
+const MERGE_FAN_IN: usize = 64;
+
+// 同一进程内分段文件的编号，保证文件名不重复
+static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+
+// Run 临时目录中的一段已排序的行，不再使用时删除文件
+struct Run {
+    path: PathBuf,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        // 删除失败只会在临时目录中留下文件，可以忽略
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// 把已排序的行依次写入新的分段文件
+fn write_run(rows: impl Iterator<Item = std::io::Result<DataCount>>) -> std::io::Result<Run> {
+    let id = NEXT_RUN.fetch_add(1, AtomicOrdering::Relaxed);
+    let path =
+        std::env::temp_dir().join(format!("csvaction-sort-{}-{}.tmp", std::process::id(), id));
+    let run = Run { path };
+    let mut writer = BufWriter::new(File::create(&run.path)?);
+    for row in rows {
+        write_row(&mut writer, &row?)?;
+    }
+    writer.flush()?;
+    Ok(run)
+}
+
+// 每行依次写入 line、key、count、first_seen 和 positions，字符串和列表前面是长度，整数为小端序 u64
+fn write_row(writer: &mut impl Write, row: &DataCount) -> std::io::Result<()> {
+    for text in [&row.line, &row.key] {
+        writer.write_all(&(text.len() as u64).to_le_bytes())?;
+        writer.write_all(text.as_bytes())?;
+    }
+    writer.write_all(&(row.count as u64).to_le_bytes())?;
+    writer.write_all(&(row.first_seen as u64).to_le_bytes())?;
+    writer.write_all(&(row.positions.len() as u64).to_le_bytes())?;
+    for position in &row.positions {
+        writer.write_all(&(*position as u64).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// RunReader 顺序读取分段文件中的行，读完前分段文件不会被删除
+struct RunReader {
+    reader: BufReader<File>,
+    _run: Run,
+}
+
+impl RunReader {
+    fn open(run: Run) -> std::io::Result<Self> {
+        Ok(RunReader {
+            reader: BufReader::new(File::open(&run.path)?),
+            _run: run,
+        })
+    }
+
+    // 读取下一行，读完时返回 None
+    fn next_row(&mut self) -> std::io::Result<Option<DataCount>> {
+        let mut buf = [0; 8];
+        // 只有在一行的开头遇到文件末尾才是正常结束
+        match self.reader.read_exact(&mut buf) {
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let line = self.read_text(u64::from_le_bytes(buf))?;
+        let len = self.read_u64()?;
+        let key = self.read_text(len)?;
+        let count = self.read_u64()? as usize;
+        let first_seen = self.read_u64()? as usize;
+        let len = self.read_u64()?;
+        let positions = (0..len)
+            .map(|_| self.read_u64().map(|position| position as usize))
+            .collect::<std::io::Result<_>>()?;
+        Ok(Some(DataCount {
+            line,
+            key,
+            count,
+            first_seen,
+            cumulative: 0,
+            positions,
+        }))
+    }
+
+    fn read_u64(&mut self) -> std::io::Result<u64> {
+        let mut buf = [0; 8];
+        self.reader.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_text(&mut self, len: u64) -> std::io::Result<String> {
+        let mut buf = vec![0; len as usize];
+        self.reader.read_exact(&mut buf)?;
+        String::from_utf8(buf).map_err(std::io::Error::other)
+    }
+}
+
+// MergedRuns 按 compare 的顺序归并多个分段，heads 为每个分段当前的第一行
+pub(crate) struct MergedRuns<F> {
+    readers: Vec<RunReader>,
+    heads: Vec<Option<DataCount>>,
+    compare: F,
+}
+
+impl<F: Fn(&DataCount, &DataCount) -> Ordering> MergedRuns<F> {
+    fn new(runs: Vec<Run>, compare: F) -> std::io::Result<Self> {
+        let mut readers = runs
+            .into_iter()
+            .map(RunReader::open)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let heads = readers
+            .iter_mut()
+            .map(RunReader::next_row)
+            .collect::<std::io::Result<_>>()?;
+        Ok(MergedRuns {
+            readers,
+            heads,
+            compare,
+        })
+    }
+}
+
+impl<F: Fn(&DataCount, &DataCount) -> Ordering> Iterator for MergedRuns<F> {
+    type Item = std::io::Result<DataCount>;
+
+    // 分段数不超过 MERGE_FAN_IN，逐个比较各分段的第一行即可，不需要堆
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut smallest: Option<usize> = None;
+        for (index, head) in self.heads.iter().enumerate() {
+            let Some(head) = head else {
+                continue;
+            };
+            let smaller = smallest.is_none_or(|smallest| {
+                let current = self.heads[smallest].as_ref();
+                current.is_some_and(|current| (self.compare)(head, current) == Ordering::Less)
+            });
+            if smaller {
+                smallest = Some(index);
+            }
+        }
+        let index = smallest?;
+        let row = self.heads[index].take();
+        match self.readers[index].next_row() {
+            Ok(next) => self.heads[index] = next,
+            Err(err) => return Some(Err(err)),
+        }
+        row.map(Ok)
+    }
+}
+
+// 外部排序：每 run_len 行排序后写入一个分段文件，再把所有分段按 compare 归并，
+// 内存中最多同时保存 run_len 行；返回总行数及按顺序给出所有行的迭代器
+// 归并时相等的行取自靠前的分段，stable 为 true 时分段内也用稳定排序，整体保持相等的行在 rows 中的顺序
+pub(crate) fn sort_spilled<F>(
+    rows: impl Iterator<Item = DataCount>,
+    run_len: usize,
+    compare: F,
+    stable: bool,
+) -> std::io::Result<(usize, MergedRuns<F>)>
+where
+    F: Fn(&DataCount, &DataCount) -> Ordering,
+{
+    let run_len = run_len.max(1);
+    let mut runs = Vec::new();
+    let mut buffer = Vec::new();
+    let sort = |buffer: &mut Vec<DataCount>| {
+        if stable {
+            buffer.sort_by(&compare);
+        } else {
+            buffer.sort_unstable_by(&compare);
+        }
+    };
+    let mut len = 0;
+    for row in rows {
+        len += 1;
+        buffer.push(row);
+        if buffer.len() >= run_len {
+            sort(&mut buffer);
+            runs.push(write_run(buffer.drain(..).map(Ok))?);
+        }
+    }
+    if !buffer.is_empty() {
+        sort(&mut buffer);
+        runs.push(write_run(buffer.drain(..).map(Ok))?);
+    }
+    // 分段过多时每 MERGE_FAN_IN 个归并为一个，直到可以一次归并完
+    while runs.len() > MERGE_FAN_IN {
+        let mut merged = Vec::new();
+        let mut rest = runs.into_iter();
+        loop {
+            let group: Vec<_> = rest.by_ref().take(MERGE_FAN_IN).collect();
+            if group.is_empty() {
+                break;
+            }
+            merged.push(write_run(MergedRuns::new(group, &compare)?)?);
+        }
+        runs = merged;
+    }
+    Ok((len, MergedRuns::new(runs, compare)?))
+}
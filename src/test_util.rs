@@ -0,0 +1,46 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 同一进程内临时文件的编号，并行运行的测试不会用到同一个文件
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+// 测试用的临时文件，离开作用域时删除；也可以在该路径上创建目录，离开作用域时连同其中的文件一起删除
+pub(crate) struct TestFile {
+    path: String,
+}
+
+impl TestFile {
+    // 尚未创建的临时文件，name 作为文件名的结尾，便于按扩展名判断格式
+    pub(crate) fn empty(name: &str) -> Self {
+        let id = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!("csvaction-test-{}-{}-{}", std::process::id(), id, name);
+        let path = std::env::temp_dir().join(file_name);
+        TestFile {
+            path: path.to_string_lossy().into_owned(),
+        }
+    }
+
+    // 内容为 content 的临时文件
+    pub(crate) fn new(name: &str, content: impl AsRef<[u8]>) -> Self {
+        let file = TestFile::empty(name);
+        std::fs::write(&file.path, content).unwrap();
+        file
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub(crate) fn read(&self) -> String {
+        std::fs::read_to_string(&self.path).unwrap()
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        if std::path::Path::new(&self.path).is_dir() {
+            let _ = std::fs::remove_dir_all(&self.path);
+        } else {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! 通过命令行运行 csvaction，检查输出文件、标准输出、标准错误和退出码
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// 同一进程内临时目录的编号，并行运行的测试不会用到同一个目录
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// 测试用的临时目录，命令在其中运行，离开作用域时删除
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new() -> Self {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let dir_name = format!("csvaction-cli-{}-{}", std::process::id(), id);
+        let path = std::env::temp_dir().join(dir_name);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    // 目录中名为 name 的文件的路径
+    fn file(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().into_owned()
+    }
+
+    // 在目录中写入内容为 content 的文件，返回其路径
+    fn write(&self, name: &str, content: impl AsRef<[u8]>) -> String {
+        let path = self.file(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    // 在目录中运行 csvaction
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_csvaction"))
+            .current_dir(&self.path)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[test]
+fn stdin_is_merged_with_files_and_counted_by_count_lines_only() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nc\n");
+    let run_with_stdin = |args: &[&str], stdin: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_csvaction"))
+            .current_dir(&dir.path)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    };
+    // 标准输入与普通文件合并统计，--concurrency 对统计阶段同样生效
+    let output = run_with_stdin(
+        &["-f", "input.txt", "-", "--concurrency", "4", "-r", "-"],
+        b"a\nb\na",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Line,Count\na,3\nb,1\nc,1\n"
+    );
+    let output = run_with_stdin(&["-f", "-", "--count-lines-only"], b"a\nb\na");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "3");
+}
+
+#[test]
+fn results_are_written_to_stdout() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\n");
+    let output = dir.run(&["-f", "input.txt", "-r", "-"]);
+    assert!(output.status.success());
+    // 进度条只会画到标准错误，标准输出中只有 CSV
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Line,Count\na,2\nb,1\n"
+    );
+    assert!(!std::path::Path::new(&dir.file("-")).exists());
+}
+
+#[test]
+fn missing_input_file_exits_with_error() {
+    let dir = TempDir::new();
+    let output = dir.run(&["-q", "-f", "does-not-exist.txt"]);
+    // 1 表示没有重复的行，出错时为 2
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("does-not-exist.txt"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn quiet_mode_writes_nothing_to_stderr() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\n");
+    // 不指定 --quiet 时 JSON 进度和汇总信息都写到 stderr
+    let output = dir.run(&["-f", "input.txt", "--progress-format", "json"]);
+    assert!(output.status.success());
+    assert!(!output.stderr.is_empty());
+    let output = dir.run(&["-q", "-f", "input.txt"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    assert_eq!(
+        std::fs::read_to_string(dir.file("result.csv")).unwrap(),
+        "Line,Count\na,2\nb,1\n"
+    );
+}
+
+#[test]
+fn summary_on_stderr_reports_distinct_lines() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\nc\n");
+    let output = dir.run(&["-f", "input.txt"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("共统计 4 行，其中不同的行 3 个"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn duplicates_only_exit_code() {
+    let dir = TempDir::new();
+    dir.write("repeated.txt", "a\nb\na\n");
+    dir.write("unique.txt", "a\nb\n");
+    let output = dir.run(&["-q", "--duplicates-only", "-f", "repeated.txt"]);
+    assert_eq!(output.status.code(), Some(0));
+    let output = dir.run(&["-q", "--duplicates-only", "-f", "unique.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    // 不指定 --duplicates-only 时没有重复行也正常退出
+    let output = dir.run(&["-q", "-f", "unique.txt"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn watch_rewrites_results_when_input_changes() {
+    let dir = TempDir::new();
+    let input = dir.write("input.txt", "a\n");
+    let result = dir.file("result.csv");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_csvaction"))
+        .current_dir(&dir.path)
+        .args(["-q", "--watch", "-f", "input.txt", "-r", "result.csv"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // 轮询等待结果文件的内容变为 expected，超时返回 false
+    let wait_for = |expected: &str| {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while Instant::now() < deadline {
+            if std::fs::read_to_string(&result).is_ok_and(|content| content == expected) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    };
+    let first = wait_for("Line,Count\na,1\n");
+    // 监视开始前的修改可能被错过，重复修改直到结果更新
+    let mut second = false;
+    for _ in 0..10 {
+        if !first {
+            break;
+        }
+        std::fs::write(&input, "a\nb\nb\n").unwrap();
+        if wait_for("Line,Count\nb,2\na,1\n") {
+            second = true;
+            break;
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(first, "the initial run did not write results");
+    assert!(second, "no results were written after the input changed");
+}
+
+#[test]
+fn deterministic_runs_are_identical() {
+    let dir = TempDir::new();
+    let input: String = (0..20_000)
+        .map(|i| format!("k{}\n", i * 7919 % 500))
+        .collect();
+    dir.write("input.txt", input);
+    let run = || {
+        let output = dir.run(&[
+            "-q",
+            "--deterministic",
+            "--first-seen",
+            "--sample",
+            "50",
+            "-f",
+            "input.txt",
+            "-r",
+            "-",
+        ]);
+        assert!(output.status.success());
+        output.stdout
+    };
+    let first = run();
+    assert_eq!(String::from_utf8_lossy(&first).lines().count(), 51);
+    for _ in 0..3 {
+        assert_eq!(run(), first);
+    }
+}
+
+#[test]
+fn throughput_line_is_parseable() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\n");
+    let output = dir.run(&["-f", "input.txt"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let line = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("读取速度 "))
+        .unwrap_or_else(|| panic!("no throughput line in {stderr:?}"));
+    let (lines_per_sec, rest) = line.split_once(" 行/秒，").unwrap();
+    let mb_per_sec = rest.strip_suffix(" MB/秒").unwrap();
+    assert!(lines_per_sec.parse::<f64>().unwrap() > 0.0);
+    assert!(mb_per_sec.parse::<f64>().unwrap() >= 0.0);
+    let output = dir.run(&["-q", "-f", "input.txt"]);
+    assert!(!String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("读取速度"));
+}
+
+#[test]
+fn checkpoint_partway_through_a_file_resumes_with_merge_with() {
+    let dir = TempDir::new();
+    let input: String = (0..2_000_000).map(|i| format!("k{}\n", i % 97)).collect();
+    dir.write("input.txt", input);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_csvaction"))
+        .current_dir(&dir.path)
+        .args([
+            "-f",
+            "input.txt",
+            "--checkpoint-every",
+            "10000",
+            "-r",
+            "result.csv",
+        ])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // 第一个检查点出现后立即杀掉进程，模拟统计到一半时崩溃
+    let checkpoint = dir.file("checkpoint.csv");
+    let started = Instant::now();
+    while !std::path::Path::new(&checkpoint).exists() {
+        assert!(started.elapsed() < Duration::from_secs(60));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(!std::path::Path::new(&dir.file("result.csv")).exists());
+    let saved = std::fs::read_to_string(&checkpoint).unwrap();
+    let header = saved.lines().next().unwrap();
+    assert!(header.ends_with(",ResumeFile=input.txt"), "{header}");
+
+    let resumed = dir.run(&[
+        "-f",
+        "input.txt",
+        "--merge-with",
+        "checkpoint.csv",
+        "-r",
+        "-",
+    ]);
+    assert!(resumed.status.success());
+    let expected = dir.run(&["-f", "input.txt", "-r", "-"]);
+    assert_eq!(
+        String::from_utf8(resumed.stdout).unwrap(),
+        String::from_utf8(expected.stdout).unwrap()
+    );
+}
+
+#[test]
+fn dry_run_creates_no_output_file() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\n");
+    let output = dir.run(&["--dry-run", "-f", "input.txt", "-r", "result.csv"]);
+    assert!(output.status.success());
+    assert!(!std::path::Path::new(&dir.file("result.csv")).exists());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("不同的行 2 个"), "{stderr}");
+}
+
+#[test]
+fn json_progress_records_are_parseable() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\n");
+    let output = dir.run(&["--progress-format", "json", "-f", "input.txt"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let records: Vec<serde_json::Value> = stderr
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(!records.is_empty(), "{stderr}");
+    let last_read = records
+        .iter()
+        .rfind(|record| record["phase"] == "read")
+        .unwrap();
+    assert_eq!(last_read["processed"], 6);
+    assert_eq!(last_read["total"], 6);
+    assert!(records.iter().any(|record| record["phase"] == "write"));
+}
+
+#[test]
+fn bytes_read_equals_file_size() {
+    let dir = TempDir::new();
+    let content = "alpha\nbeta\nalpha\n".repeat(100);
+    dir.write("input.txt", &content);
+    for mmap in [false, true] {
+        let mut args = vec!["-f", "input.txt"];
+        if mmap {
+            args.push("--mmap");
+        }
+        let output = dir.run(&args);
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let expected = format!("已读取 {0} 字节，输入文件共 {0} 字节\n", content.len());
+        assert!(stderr.contains(&expected), "{stderr}");
+    }
+}
+
+#[test]
+fn unknown_input_size_still_succeeds() {
+    let dir = TempDir::new();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_csvaction"))
+        .current_dir(&dir.path)
+        .args(["--progress-format", "json", "-f", "-", "-r", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"a\nb\na\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Line,Count\na,2\nb,1\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // 读取阶段的总数未知
+    assert!(
+        stderr.contains("\"phase\":\"read\",\"processed\":6,\"total\":null"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn progress_is_written_to_file() {
+    let dir = TempDir::new();
+    dir.write("input.txt", "a\nb\na\n");
+    let output = dir.run(&["-f", "input.txt", "--progress-to-file", "progress.log"]);
+    assert!(output.status.success());
+    let log = std::fs::read_to_string(dir.file("progress.log")).unwrap();
+    let last_read = log
+        .lines()
+        .rfind(|line| line.contains(" read "))
+        .unwrap_or_else(|| panic!("no read progress in {log:?}"));
+    assert!(last_read.ends_with(" read 6/6"), "{last_read}");
+    assert!(log.lines().any(|line| line.contains(" write ")), "{log}");
+}